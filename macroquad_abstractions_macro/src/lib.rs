@@ -0,0 +1,508 @@
+//! Derive macros for `macroquad_abstractions`: `#[derive(AsVertex)]`, `#[derive(DrawVertex)]`
+//! and `#[derive(Uniforms)]`.
+//!
+//! No `syn` or `quote` here, structs are parsed straight from the token stream,
+//! and the generated code is built as a string.
+
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, TokenStream, TokenTree};
+
+/// Implement `AsVertex` for a `#[repr(C)]` struct with named fields.
+///
+/// Every field becomes a vertex attribute, named after the field. Supported field types are
+/// `f32`, `u8`, `u16` and `u32`, arrays of 2 to 4 of them, `Vec2`, `Vec3`, `Vec4` and `Mat4`.
+///
+/// Both can be overridden with `#[vertex(name = "texcoord", format = Byte4)]`, where `format`
+/// is a `VertexFormat` variant.
+///
+/// A `position` attribute of type `Vec2`, `Vec3`, `[f32; 2]` or `[f32; 3]` is also used
+/// to implement `AsVertex::transformed`.
+#[proc_macro_derive(AsVertex, attributes(vertex))]
+pub fn derive_as_vertex(input: TokenStream) -> TokenStream {
+    expand(input, "vertex", as_vertex)
+}
+
+/// Implement `DrawVertex` for a struct with named fields, so the drawing helpers can build it.
+///
+/// Fields are matched by their attribute names, the same ones as in `#[derive(AsVertex)]`:
+/// `position` (`Vec2`, `Vec3`, `[f32; 2]` or `[f32; 3]`) is required, `texcoord` (`Vec2` or
+/// `[f32; 2]`) and `color0` (`[u8; 4]`, or `Vec4` and `[f32; 4]` with components from 0 to 1)
+/// are optional. Every other field is set to its `Default`.
+#[proc_macro_derive(DrawVertex, attributes(vertex))]
+pub fn derive_draw_vertex(input: TokenStream) -> TokenStream {
+    expand(input, "vertex", draw_vertex)
+}
+
+/// Implement `Uniforms` for a struct with named fields, so it can be passed to materials in one call.
+///
+/// Every field becomes a uniform, named after the field. Supported field types are `f32`, `i32`,
+/// `u32`, arrays of 2 to 4 of them, `Vec2`, `Vec3`, `Vec4`, `IVec2`, `IVec3`, `IVec4` and `Mat4`.
+/// Arrays of `f32` and `i32` of any other length, or arrays of `Vec2`, `Vec3`, `Vec4` and `Mat4`,
+/// become uniform arrays.
+///
+/// The name can be overridden with `#[uniform(name = "Color")]`.
+#[proc_macro_derive(Uniforms, attributes(uniform))]
+pub fn derive_uniforms(input: TokenStream) -> TokenStream {
+    expand(input, "uniform", uniforms)
+}
+
+fn expand(
+    input: TokenStream,
+    attribute: &str,
+    derive: fn(&Struct) -> Result<String, String>,
+) -> TokenStream {
+    let code = parse_struct(input, attribute)
+        .and_then(|parsed| derive(&parsed))
+        .unwrap_or_else(|err| format!("compile_error!({:?});", err));
+
+    code.parse().unwrap()
+}
+
+struct Struct {
+    name: String,
+    repr_c: bool,
+    fields: Vec<Field>,
+}
+
+struct Field {
+    name: String,
+    /// The type, as written (with spaces between tokens)
+    ty: String,
+    /// `key = value` pairs of the field attribute of the derive
+    options: Vec<(String, String)>,
+}
+
+impl Field {
+    fn option(&self, key: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .find(|(option, _)| option == key)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Split tokens by a punctuation character, ignoring the ones inside of `<...>`
+fn split(tokens: Vec<TokenTree>, separator: char) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![vec![]];
+    let mut depth = 0;
+
+    for token in tokens {
+        if let TokenTree::Punct(punct) = &token {
+            match punct.as_char() {
+                '<' => depth += 1,
+                '>' if depth > 0 => depth -= 1,
+                c if c == separator && depth == 0 => {
+                    parts.push(vec![]);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        parts.last_mut().unwrap().push(token);
+    }
+
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Take the leading `#[...]` attributes off the tokens, returning their contents
+fn take_attributes(tokens: &mut Vec<TokenTree>) -> Vec<Group> {
+    let mut attributes = vec![];
+
+    while let [TokenTree::Punct(punct), TokenTree::Group(group), ..] = tokens.as_slice() {
+        if punct.as_char() != '#' || group.delimiter() != Delimiter::Bracket {
+            break;
+        }
+        attributes.push(group.clone());
+        tokens.drain(..2);
+    }
+
+    attributes
+}
+
+/// Take the leading visibility (`pub`, `pub(crate)` and so on) off the tokens
+fn take_visibility(tokens: &mut Vec<TokenTree>) {
+    if let Some(TokenTree::Ident(ident)) = tokens.first() {
+        if ident.to_string() == "pub" {
+            tokens.remove(0);
+            if let Some(TokenTree::Group(group)) = tokens.first() {
+                if group.delimiter() == Delimiter::Parenthesis {
+                    tokens.remove(0);
+                }
+            }
+        }
+    }
+}
+
+/// Parse the contents of an attribute like `vertex(name = "texcoord", format = Byte4)`,
+/// if it's named `name`
+fn parse_options(attribute: &Group, name: &str) -> Result<Option<Vec<(String, String)>>, String> {
+    let tokens: Vec<_> = attribute.stream().into_iter().collect();
+    let options = match tokens.as_slice() {
+        [TokenTree::Ident(ident), TokenTree::Group(options)] if ident.to_string() == name => {
+            options
+        }
+        _ => return Ok(None),
+    };
+
+    split(options.stream().into_iter().collect(), ',')
+        .into_iter()
+        .map(|option| match option.as_slice() {
+            [TokenTree::Ident(key), TokenTree::Punct(eq), value] if eq.as_char() == '=' => {
+                let value = value.to_string();
+                let value = value.trim_matches('"').to_string();
+                Ok((key.to_string(), value))
+            }
+            _ => Err(format!(
+                "Expected `key = value` options in `#[{}(...)]`",
+                name
+            )),
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn parse_struct(input: TokenStream, attribute: &str) -> Result<Struct, String> {
+    let mut tokens: Vec<_> = input.into_iter().collect();
+
+    let repr_c = take_attributes(&mut tokens).iter().any(|attribute| {
+        let tokens: Vec<_> = attribute.stream().into_iter().collect();
+        match tokens.as_slice() {
+            [TokenTree::Ident(ident), TokenTree::Group(reprs)] if ident.to_string() == "repr" => {
+                reprs
+                    .stream()
+                    .into_iter()
+                    .any(|repr| repr.to_string() == "C")
+            }
+            _ => false,
+        }
+    });
+    take_visibility(&mut tokens);
+
+    let (name, body) = match tokens.as_slice() {
+        [TokenTree::Ident(keyword), TokenTree::Ident(name), TokenTree::Group(body)]
+            if keyword.to_string() == "struct" && body.delimiter() == Delimiter::Brace =>
+        {
+            (name.to_string(), body)
+        }
+        [TokenTree::Ident(keyword), TokenTree::Ident(_), TokenTree::Punct(punct), ..]
+            if keyword.to_string() == "struct" && punct.as_char() == '<' =>
+        {
+            return Err("Generic structs aren't supported".to_string())
+        }
+        _ => return Err("Only structs with named fields are supported".to_string()),
+    };
+
+    let fields = split(body.stream().into_iter().collect(), ',')
+        .into_iter()
+        .map(|mut field| {
+            let mut options = vec![];
+            for group in take_attributes(&mut field) {
+                if let Some(found) = parse_options(&group, attribute)? {
+                    options.extend(found);
+                }
+            }
+            take_visibility(&mut field);
+
+            match field.as_slice() {
+                [TokenTree::Ident(name), TokenTree::Punct(colon), ty @ ..]
+                    if colon.as_char() == ':' && !ty.is_empty() =>
+                {
+                    Ok(Field {
+                        name: name.to_string(),
+                        ty: ty.iter().cloned().collect::<TokenStream>().to_string(),
+                        options,
+                    })
+                }
+                _ => Err("Couldn't parse a struct field".to_string()),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Struct {
+        name,
+        repr_c,
+        fields,
+    })
+}
+
+/// The type without whitespace and paths (`glam::Vec2` becomes `Vec2`)
+fn normalize_type(ty: &str) -> String {
+    let ty: String = ty.chars().filter(|c| !c.is_whitespace()).collect();
+    match ty.rsplit_once("::") {
+        Some((_, name)) if !ty.starts_with('[') => name.to_string(),
+        _ => ty,
+    }
+}
+
+/// Split an array type like `[f32;4]` into its element type and length
+fn array_type(ty: &str) -> Option<(String, usize)> {
+    let (element, length) = ty.strip_prefix('[')?.strip_suffix(']')?.rsplit_once(';')?;
+    Some((normalize_type(element), length.parse().ok()?))
+}
+
+fn vertex_format(ty: &str) -> Option<&'static str> {
+    let ty = normalize_type(ty);
+    let format = match ty.as_str() {
+        "f32" => "Float1",
+        "Vec2" => "Float2",
+        "Vec3" => "Float3",
+        "Vec4" => "Float4",
+        "u8" => "Byte1",
+        "u16" => "Short1",
+        "u32" => "Int1",
+        "UVec2" => "Int2",
+        "UVec3" => "Int3",
+        "UVec4" => "Int4",
+        "Mat4" => "Mat4",
+        _ => {
+            let (element, length) = array_type(&ty)?;
+            let formats = match element.as_str() {
+                "f32" => ["Float2", "Float3", "Float4"],
+                "u8" => ["Byte2", "Byte3", "Byte4"],
+                "u16" => ["Short2", "Short3", "Short4"],
+                "u32" => ["Int2", "Int3", "Int4"],
+                _ => return None,
+            };
+            return formats.get(length.checked_sub(2)?).copied();
+        }
+    };
+
+    Some(format)
+}
+
+fn as_vertex(parsed: &Struct) -> Result<String, String> {
+    if !parsed.repr_c {
+        return Err(format!(
+            "`{}` has to be `#[repr(C)]` to derive AsVertex",
+            parsed.name
+        ));
+    }
+
+    let mut attributes = vec![];
+    for field in &parsed.fields {
+        let name = field.option("name").unwrap_or(&field.name);
+        let format = field
+            .option("format")
+            .or_else(|| vertex_format(&field.ty))
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported vertex attribute type `{}` of `{}`, specify it with `#[vertex(format = ...)]`",
+                    field.ty, field.name
+                )
+            })?;
+
+        attributes.push(format!(
+            "::macroquad_abstractions::miniquad::VertexAttribute::new({:?}, ::macroquad_abstractions::miniquad::VertexFormat::{})",
+            name, format
+        ));
+    }
+
+    let transformed = parsed
+        .fields
+        .iter()
+        .find(|field| field.option("name").unwrap_or(&field.name) == "position")
+        .and_then(|field| {
+            let position = format!("vertex.{}", field.name);
+            let transformed = match normalize_type(&field.ty).as_str() {
+                "Vec2" | "[f32;2]" => format!(
+                    "matrix.transform_point3(::macroquad_abstractions::glam::Vec2::from({}).extend(0.0)).truncate()",
+                    position
+                ),
+                "Vec3" | "[f32;3]" => format!(
+                    "matrix.transform_point3(::macroquad_abstractions::glam::Vec3::from({}))",
+                    position
+                ),
+                _ => return None,
+            };
+
+            Some(format!(
+                r#"
+            fn transformed(&self, matrix: &::macroquad_abstractions::glam::Mat4) -> ::core::option::Option<Self> {{
+                let mut vertex = *self;
+                {position} = ::core::convert::Into::into({transformed});
+                ::core::option::Option::Some(vertex)
+            }}
+                "#,
+            ))
+        })
+        .unwrap_or_default();
+
+    // miniquad lays attributes out one after another, so there can't be any padding between fields
+    let fields_size = parsed
+        .fields
+        .iter()
+        .map(|field| format!("::core::mem::size_of::<{}>()", field.ty))
+        .collect::<Vec<_>>()
+        .join(" + ");
+
+    Ok(format!(
+        r#"
+        const _: () = ::core::assert!(
+            ::core::mem::size_of::<{name}>() == 0 + {fields_size},
+            "Vertex structs can't have padding between their fields"
+        );
+
+        unsafe impl ::macroquad_abstractions::graphics::AsVertex for {name} {{
+            fn attributes() -> ::std::vec::Vec<::macroquad_abstractions::miniquad::VertexAttribute> {{
+                ::std::vec![{attributes}]
+            }}
+            {transformed}
+        }}
+        "#,
+        name = parsed.name,
+        attributes = attributes.join(", "),
+    ))
+}
+
+fn draw_vertex(parsed: &Struct) -> Result<String, String> {
+    let mut fields = vec![];
+    let mut has_position = false;
+
+    for field in &parsed.fields {
+        let name = field.option("name").unwrap_or(&field.name);
+        let ty = normalize_type(&field.ty);
+        let value = match (name, ty.as_str()) {
+            ("position", "Vec2" | "[f32;2]") => "position.truncate()",
+            ("position", "Vec3" | "[f32;3]") => "position",
+            ("texcoord", "Vec2" | "[f32;2]") => "uv",
+            ("color0", "[u8;4]") => "color",
+            ("color0", "Vec4" | "[f32;4]") => "<[f32; 4]>::from(color)",
+            ("position" | "texcoord" | "color0", _) => {
+                return Err(format!(
+                    "Unsupported type `{}` of the `{}` attribute to derive DrawVertex",
+                    field.ty, name
+                ))
+            }
+            _ => {
+                fields.push(format!(
+                    "{}: ::core::default::Default::default()",
+                    field.name
+                ));
+                continue;
+            }
+        };
+
+        has_position |= name == "position";
+        fields.push(format!(
+            "{}: ::core::convert::Into::into({})",
+            field.name, value
+        ));
+    }
+
+    if !has_position {
+        return Err(format!(
+            "`{}` needs a `position` attribute to derive DrawVertex",
+            parsed.name
+        ));
+    }
+
+    Ok(format!(
+        r#"
+        impl ::macroquad_abstractions::graphics::DrawVertex for {name} {{
+            #[allow(unused_variables)]
+            fn from_parts(
+                position: ::macroquad_abstractions::glam::Vec3,
+                uv: ::macroquad_abstractions::glam::Vec2,
+                color: ::macroquad_abstractions::color::Color,
+            ) -> Self {{
+                Self {{ {fields} }}
+            }}
+        }}
+        "#,
+        name = parsed.name,
+        fields = fields.join(", "),
+    ))
+}
+
+fn uniform_type(ty: &str) -> Option<&'static str> {
+    let ty = normalize_type(ty);
+    let format = match ty.as_str() {
+        "f32" => "Float1",
+        "Vec2" => "Float2",
+        "Vec3" => "Float3",
+        "Vec4" => "Float4",
+        "i32" | "u32" => "Int1",
+        "IVec2" | "UVec2" => "Int2",
+        "IVec3" | "UVec3" => "Int3",
+        "IVec4" | "UVec4" => "Int4",
+        "Mat4" => "Mat4",
+        _ => {
+            let (element, length) = array_type(&ty)?;
+            let formats = match element.as_str() {
+                "f32" => ["Float2", "Float3", "Float4"],
+                "i32" | "u32" => ["Int2", "Int3", "Int4"],
+                _ => return None,
+            };
+            return formats.get(length.checked_sub(2)?).copied();
+        }
+    };
+
+    Some(format)
+}
+
+fn uniforms(parsed: &Struct) -> Result<String, String> {
+    let mut descs = vec![];
+    let mut setters = vec![];
+
+    for field in &parsed.fields {
+        let name = field.option("name").unwrap_or(&field.name);
+
+        if let Some(uniform_type) = uniform_type(&field.ty) {
+            descs.push(format!(
+                "::macroquad_abstractions::miniquad::UniformDesc::new({:?}, ::macroquad_abstractions::miniquad::UniformType::{})",
+                name, uniform_type
+            ));
+            setters.push(format!(
+                "renderer.set_uniform(pipeline, {:?}, ::core::clone::Clone::clone(&self.{}));",
+                name, field.name
+            ));
+            continue;
+        }
+
+        // Only these can be uploaded as arrays
+        let array_element = |element: &str| match element {
+            "f32" | "i32" | "Vec2" | "Vec3" | "Vec4" | "Mat4" => uniform_type(element),
+            _ => None,
+        };
+        let (uniform_type, length) = array_type(&normalize_type(&field.ty))
+            .and_then(|(element, length)| Some((array_element(&element)?, length)))
+            .ok_or_else(|| {
+                format!(
+                    "Unsupported uniform type `{}` of `{}`",
+                    field.ty, field.name
+                )
+            })?;
+
+        descs.push(format!(
+            "::macroquad_abstractions::miniquad::UniformDesc::array(::macroquad_abstractions::miniquad::UniformDesc::new({:?}, ::macroquad_abstractions::miniquad::UniformType::{}), {})",
+            name, uniform_type, length
+        ));
+        setters.push(format!(
+            "renderer.set_uniform_array(pipeline, {:?}, &self.{}[..]);",
+            name, field.name
+        ));
+    }
+
+    Ok(format!(
+        r#"
+        impl ::macroquad_abstractions::graphics::Uniforms for {name} {{
+            fn uniforms() -> ::std::vec::Vec<::macroquad_abstractions::miniquad::UniformDesc> {{
+                ::std::vec![{descs}]
+            }}
+
+            fn set_uniforms<V: ::macroquad_abstractions::graphics::AsVertex>(
+                &self,
+                renderer: &mut ::macroquad_abstractions::graphics::Renderer<V>,
+                pipeline: &::macroquad_abstractions::graphics::GlPipeline<V>,
+            ) {{
+                {setters}
+            }}
+        }}
+        "#,
+        name = parsed.name,
+        descs = descs.join(", "),
+        setters = setters.join("\n"),
+    ))
+}
@@ -5,7 +5,7 @@ use crate::color::Color;
 use glam::{vec2, vec3, Quat, Vec2, Vec3};
 use miniquad::TextureId;
 
-use crate::graphics::{AsVertex, DrawMode, Mesh, Renderer, Vertex};
+use crate::graphics::{AsIndex, AsVertex, DrawMode, DrawTarget, DrawVertex, Mesh};
 
 /// Draw a mesh with an arbitrary Vertex
 ///
@@ -13,24 +13,33 @@ use crate::graphics::{AsVertex, DrawMode, Mesh, Renderer, Vertex};
 /// so if you would like to draw your own shapes - you can simply construct a custom [Mesh] and call this
 /// function.
 ///
-/// Or you can simply do it directly using [Renderer]. You're free on this one
-pub fn draw_mesh<V: AsVertex>(renderer: &mut Renderer<V>, mesh: &Mesh<V>) {
+/// Meshes can use both [u16] and [u32] indices. Meshes with more than 65536 vertices require [u32]
+/// indices and a renderer created with [IndexFormat::U32](crate::graphics::IndexFormat::U32).
+///
+/// Or you can simply do it directly using [Renderer](crate::graphics::Renderer).
+/// You're free on this one
+pub fn draw_mesh<V: AsVertex, I: AsIndex>(renderer: &mut impl DrawTarget<V>, mesh: &Mesh<V, I>) {
     renderer.with_texture(mesh.texture.as_ref());
     renderer.with_draw_mode(DrawMode::Triangles);
-    renderer.push_geometry(&mesh.vertices[..], &mesh.indices[..]);
+    renderer.push_geometry_indexed(&mesh.vertices[..], &mesh.indices[..]);
 }
 
-fn draw_quad(renderer: &mut Renderer<Vertex>, vertices: [Vertex; 4]) {
+fn draw_quad<V: DrawVertex>(renderer: &mut impl DrawTarget<V>, vertices: [V; 4]) {
     let indices = [0, 1, 2, 0, 2, 3];
     renderer.with_draw_mode(DrawMode::Triangles);
     renderer.push_geometry(&vertices, &indices);
 }
 
-pub fn draw_line_3d(renderer: &mut Renderer<Vertex>, start: Vec3, end: Vec3, color: Color) {
+pub fn draw_line_3d<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
+    start: Vec3,
+    end: Vec3,
+    color: Color,
+) {
     let uv = vec2(0., 0.);
     let indices = [0, 1];
 
-    let line = [Vertex::new2(start, uv, color), Vertex::new2(end, uv, color)];
+    let line = [V::from_parts(start, uv, color), V::from_parts(end, uv, color)];
 
     renderer.with_texture(None);
     renderer.with_draw_mode(DrawMode::Lines);
@@ -38,8 +47,8 @@ pub fn draw_line_3d(renderer: &mut Renderer<Vertex>, start: Vec3, end: Vec3, col
 }
 
 /// Draw a grid centered at (0, 0, 0)
-pub fn draw_grid(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_grid<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     slices: u32,
     spacing: f32,
     axes_color: Color,
@@ -57,8 +66,8 @@ pub fn draw_grid(
 }
 
 /// Draw a rotated grid centered at a specified point
-pub fn draw_grid_ex(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_grid_ex<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     slices: u32,
     spacing: f32,
     axes_color: Color,
@@ -92,17 +101,17 @@ pub fn draw_grid_ex(
     }
 }
 
-pub fn draw_plane(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_plane<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     center: Vec3,
     size: Vec2,
     texture: Option<&TextureId>,
     color: Color,
 ) {
-    let v1 = Vertex::new2(center + vec3(-size.x, 0., -size.y), vec2(0., 0.), color);
-    let v2 = Vertex::new2(center + vec3(-size.x, 0., size.y), vec2(0., 1.), color);
-    let v3 = Vertex::new2(center + vec3(size.x, 0., size.y), vec2(1., 1.), color);
-    let v4 = Vertex::new2(center + vec3(size.x, 0., -size.y), vec2(1., 0.), color);
+    let v1 = V::from_parts(center + vec3(-size.x, 0., -size.y), vec2(0., 0.), color);
+    let v2 = V::from_parts(center + vec3(-size.x, 0., size.y), vec2(0., 1.), color);
+    let v3 = V::from_parts(center + vec3(size.x, 0., size.y), vec2(1., 1.), color);
+    let v4 = V::from_parts(center + vec3(size.x, 0., -size.y), vec2(1., 0.), color);
 
     renderer.with_texture(texture);
     draw_quad(renderer, [v1, v2, v3, v4]);
@@ -127,18 +136,18 @@ pub fn draw_plane(
 /// # use macroquad::prelude::*;
 /// draw_affine_parallelogram(Vec3::ZERO, 3. * Vec3::X, 5. * Vec3::Z, None, RED);
 /// ```
-pub fn draw_affine_parallelogram(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_affine_parallelogram<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     offset: Vec3,
     e1: Vec3,
     e2: Vec3,
     texture: Option<&TextureId>,
     color: Color,
 ) {
-    let v1 = Vertex::new2(offset, vec2(0., 0.), color);
-    let v2 = Vertex::new2(offset + e1, vec2(0., 1.), color);
-    let v3 = Vertex::new2(offset + e1 + e2, vec2(1., 1.), color);
-    let v4 = Vertex::new2(offset + e2, vec2(1., 0.), color);
+    let v1 = V::from_parts(offset, vec2(0., 0.), color);
+    let v2 = V::from_parts(offset + e1, vec2(0., 1.), color);
+    let v3 = V::from_parts(offset + e1 + e2, vec2(1., 1.), color);
+    let v4 = V::from_parts(offset + e2, vec2(1., 0.), color);
 
     renderer.with_texture(texture);
     draw_quad(renderer, [v1, v2, v3, v4]);
@@ -170,8 +179,8 @@ pub fn draw_affine_parallelogram(
 /// # use macroquad::prelude::*;
 /// draw_affine_parallelepiped(Vec3::ZERO, 3. * Vec3::X, 2. * Vec3::Y, 5. * Vec3::Z, None, RED);
 /// ```
-pub fn draw_affine_parallelepiped(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_affine_parallelepiped<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     offset: Vec3,
     e1: Vec3,
     e2: Vec3,
@@ -188,8 +197,8 @@ pub fn draw_affine_parallelepiped(
     draw_affine_parallelogram(renderer, offset + e3, e1, e2, texture, color);
 }
 
-pub fn draw_cube(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_cube<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     position: Vec3,
     size: Vec3,
     texture: Option<&TextureId>,
@@ -215,10 +224,10 @@ pub fn draw_cube(
     draw_quad(
         renderer,
         [
-            Vertex::new2(bl_pos, bl_uv, color),
-            Vertex::new2(br_pos, br_uv, color),
-            Vertex::new2(tr_pos, tr_uv, color),
-            Vertex::new2(tl_pos, tl_uv, color),
+            V::from_parts(bl_pos, bl_uv, color),
+            V::from_parts(br_pos, br_uv, color),
+            V::from_parts(tr_pos, tr_uv, color),
+            V::from_parts(tl_pos, tl_uv, color),
         ],
     );
 
@@ -237,10 +246,10 @@ pub fn draw_cube(
     draw_quad(
         renderer,
         [
-            Vertex::new2(bl_pos, bl_uv, color),
-            Vertex::new2(br_pos, br_uv, color),
-            Vertex::new2(tr_pos, tr_uv, color),
-            Vertex::new2(tl_pos, tl_uv, color),
+            V::from_parts(bl_pos, bl_uv, color),
+            V::from_parts(br_pos, br_uv, color),
+            V::from_parts(tr_pos, tr_uv, color),
+            V::from_parts(tl_pos, tl_uv, color),
         ],
     );
 
@@ -259,10 +268,10 @@ pub fn draw_cube(
     draw_quad(
         renderer,
         [
-            Vertex::new2(bl_pos, bl_uv, color),
-            Vertex::new2(br_pos, br_uv, color),
-            Vertex::new2(tr_pos, tr_uv, color),
-            Vertex::new2(tl_pos, tl_uv, color),
+            V::from_parts(bl_pos, bl_uv, color),
+            V::from_parts(br_pos, br_uv, color),
+            V::from_parts(tr_pos, tr_uv, color),
+            V::from_parts(tl_pos, tl_uv, color),
         ],
     );
 
@@ -281,10 +290,10 @@ pub fn draw_cube(
     draw_quad(
         renderer,
         [
-            Vertex::new2(bl_pos, bl_uv, color),
-            Vertex::new2(br_pos, br_uv, color),
-            Vertex::new2(tr_pos, tr_uv, color),
-            Vertex::new2(tl_pos, tl_uv, color),
+            V::from_parts(bl_pos, bl_uv, color),
+            V::from_parts(br_pos, br_uv, color),
+            V::from_parts(tr_pos, tr_uv, color),
+            V::from_parts(tl_pos, tl_uv, color),
         ],
     );
 
@@ -303,10 +312,10 @@ pub fn draw_cube(
     draw_quad(
         renderer,
         [
-            Vertex::new2(bl_pos, bl_uv, color),
-            Vertex::new2(br_pos, br_uv, color),
-            Vertex::new2(tr_pos, tr_uv, color),
-            Vertex::new2(tl_pos, tl_uv, color),
+            V::from_parts(bl_pos, bl_uv, color),
+            V::from_parts(br_pos, br_uv, color),
+            V::from_parts(tr_pos, tr_uv, color),
+            V::from_parts(tl_pos, tl_uv, color),
         ],
     );
 
@@ -325,15 +334,20 @@ pub fn draw_cube(
     draw_quad(
         renderer,
         [
-            Vertex::new2(bl_pos, bl_uv, color),
-            Vertex::new2(br_pos, br_uv, color),
-            Vertex::new2(tr_pos, tr_uv, color),
-            Vertex::new2(tl_pos, tl_uv, color),
+            V::from_parts(bl_pos, bl_uv, color),
+            V::from_parts(br_pos, br_uv, color),
+            V::from_parts(tr_pos, tr_uv, color),
+            V::from_parts(tl_pos, tl_uv, color),
         ],
     );
 }
 
-pub fn draw_cube_wires(renderer: &mut Renderer<Vertex>, position: Vec3, size: Vec3, color: Color) {
+pub fn draw_cube_wires<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
+    position: Vec3,
+    size: Vec3,
+    color: Color,
+) {
     let (x, y, z) = (position.x, position.y, position.z);
     let (width, height, length) = (size.x, size.y, size.z);
 
@@ -426,8 +440,8 @@ impl Default for DrawSphereParams {
     }
 }
 
-pub fn draw_sphere(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_sphere<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     center: Vec3,
     radius: f32,
     texture: Option<&TextureId>,
@@ -436,8 +450,8 @@ pub fn draw_sphere(
     draw_sphere_ex(renderer, center, radius, texture, color, Default::default());
 }
 
-pub fn draw_sphere_wires(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_sphere_wires<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     center: Vec3,
     radius: f32,
     texture: Option<&TextureId>,
@@ -450,8 +464,8 @@ pub fn draw_sphere_wires(
     draw_sphere_ex(renderer, center, radius, texture, color, params);
 }
 
-pub fn draw_sphere_ex(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_sphere_ex<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     center: Vec3,
     radius: f32,
     texture: Option<&TextureId>,
@@ -498,9 +512,9 @@ pub fn draw_sphere_ex(
 
             renderer.push_geometry(
                 &[
-                    Vertex::new2(v1 * scale + center, uv1, color),
-                    Vertex::new2(v2 * scale + center, uv2, color),
-                    Vertex::new2(v3 * scale + center, uv3, color),
+                    V::from_parts(v1 * scale + center, uv1, color),
+                    V::from_parts(v2 * scale + center, uv2, color),
+                    V::from_parts(v3 * scale + center, uv3, color),
                 ],
                 &[0, 1, 2],
             );
@@ -526,9 +540,9 @@ pub fn draw_sphere_ex(
 
             renderer.push_geometry(
                 &[
-                    Vertex::new2(v1 * scale + center, uv1, color),
-                    Vertex::new2(v2 * scale + center, uv2, color),
-                    Vertex::new2(v3 * scale + center, uv3, color),
+                    V::from_parts(v1 * scale + center, uv1, color),
+                    V::from_parts(v2 * scale + center, uv2, color),
+                    V::from_parts(v3 * scale + center, uv3, color),
                 ],
                 &[0, 1, 2],
             );
@@ -551,8 +565,8 @@ impl Default for DrawCylinderParams {
     }
 }
 
-pub fn draw_cylinder(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_cylinder<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     position: Vec3,
     radius_top: f32,
     radius_bottom: f32,
@@ -572,8 +586,8 @@ pub fn draw_cylinder(
     );
 }
 
-pub fn draw_cylinder_wires(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_cylinder_wires<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     position: Vec3,
     radius_top: f32,
     radius_bottom: f32,
@@ -599,8 +613,8 @@ pub fn draw_cylinder_wires(
 }
 
 //Note: can also be used to draw a cone by setting radius_top or radius_bottom to 0
-pub fn draw_cylinder_ex(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_cylinder_ex<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     position: Vec3,
     radius_top: f32,
     radius_bottom: f32,
@@ -640,9 +654,9 @@ pub fn draw_cylinder_ex(
 
         renderer.push_geometry(
             &[
-                Vertex::new2(v1 + position, vec2(0.0, 0.0), color),
-                Vertex::new2(v2 + position, vec2(1.0, 0.0), color),
-                Vertex::new2(v3 + position, vec2(1.0, 1.0), color),
+                V::from_parts(v1 + position, vec2(0.0, 0.0), color),
+                V::from_parts(v2 + position, vec2(1.0, 0.0), color),
+                V::from_parts(v3 + position, vec2(1.0, 1.0), color),
             ],
             &[0, 1, 2],
         );
@@ -668,9 +682,9 @@ pub fn draw_cylinder_ex(
 
         renderer.push_geometry(
             &[
-                Vertex::new2(v1 + position, vec2(0.0, 0.0), color),
-                Vertex::new2(v2 + position, vec2(1.0, 0.0), color),
-                Vertex::new2(v3 + position, vec2(1.0, 1.0), color),
+                V::from_parts(v1 + position, vec2(0.0, 0.0), color),
+                V::from_parts(v2 + position, vec2(1.0, 0.0), color),
+                V::from_parts(v3 + position, vec2(1.0, 1.0), color),
             ],
             &[0, 1, 2],
         );
@@ -693,9 +707,9 @@ pub fn draw_cylinder_ex(
 
         renderer.push_geometry(
             &[
-                Vertex::new2(v1 + position, vec2(0.0, 0.0), color),
-                Vertex::new2(v2 + position, vec2(1.0, 0.0), color),
-                Vertex::new2(v3 + position, vec2(1.0, 1.0), color),
+                V::from_parts(v1 + position, vec2(0.0, 0.0), color),
+                V::from_parts(v2 + position, vec2(1.0, 0.0), color),
+                V::from_parts(v3 + position, vec2(1.0, 1.0), color),
             ],
             &[0, 1, 2],
         );
@@ -718,9 +732,9 @@ pub fn draw_cylinder_ex(
 
         renderer.push_geometry(
             &[
-                Vertex::new2(v1 + position, vec2(0.0, 0.0), color),
-                Vertex::new2(v2 + position, vec2(1.0, 0.0), color),
-                Vertex::new2(v3 + position, vec2(1.0, 1.0), color),
+                V::from_parts(v1 + position, vec2(0.0, 0.0), color),
+                V::from_parts(v2 + position, vec2(1.0, 0.0), color),
+                V::from_parts(v3 + position, vec2(1.0, 1.0), color),
             ],
             &[0, 1, 2],
         );
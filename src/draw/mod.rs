@@ -10,3 +10,10 @@ pub use geometry::*;
 pub use shapes::*;
 pub use text::*;
 pub use texture::*;
+
+use crate::{color::Color, graphics::DrawVertex};
+
+/// A vertex from its position, texture coordinates and color
+fn vertex<V: DrawVertex>(x: f32, y: f32, z: f32, u: f32, v: f32, color: Color) -> V {
+    V::from_parts(glam::vec3(x, y, z), glam::vec2(u, v), color)
+}
@@ -2,15 +2,22 @@
 
 use crate::color::Color;
 
-use crate::graphics::{DrawMode, Renderer, Vertex};
+use super::vertex;
+use crate::graphics::{DrawMode, DrawTarget, DrawVertex};
 use glam::{vec2, vec3, vec4, Mat4, Vec2};
 
 /// Draws a solid triangle between points `v1`, `v2`, and `v3` with a given `color`.
-pub fn draw_triangle(renderer: &mut Renderer<Vertex>, v1: Vec2, v2: Vec2, v3: Vec2, color: Color) {
+pub fn draw_triangle<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
+    v1: Vec2,
+    v2: Vec2,
+    v3: Vec2,
+    color: Color,
+) {
     let vertices = [
-        Vertex::new(v1.x, v1.y, 0., 0., 0., color),
-        Vertex::new(v2.x, v2.y, 0., 0., 0., color),
-        Vertex::new(v3.x, v3.y, 0., 0., 0., color),
+        vertex(v1.x, v1.y, 0., 0., 0., color),
+        vertex(v2.x, v2.y, 0., 0., 0., color),
+        vertex(v3.x, v3.y, 0., 0., 0., color),
     ];
 
     let indices: [u16; 3] = [0, 1, 2];
@@ -21,8 +28,8 @@ pub fn draw_triangle(renderer: &mut Renderer<Vertex>, v1: Vec2, v2: Vec2, v3: Ve
 }
 
 /// Draws a triangle outline between points `v1`, `v2`, and `v3` with a given line `thickness` and `color`.
-pub fn draw_triangle_lines(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_triangle_lines<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     v1: Vec2,
     v2: Vec2,
     v3: Vec2,
@@ -36,8 +43,8 @@ pub fn draw_triangle_lines(
 
 /// Draws a solid rectangle with its top-left corner at `[x, y]` with size `[w, h]` (width going to
 /// the right, height going down), with a given `color`.
-pub fn draw_rectangle(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_rectangle<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     w: f32,
@@ -46,10 +53,10 @@ pub fn draw_rectangle(
 ) {
     #[rustfmt::skip]
     let vertices = [
-        Vertex::new(x    , y    , 0., 0.0, 0.0, color),
-        Vertex::new(x + w, y    , 0., 1.0, 0.0, color),
-        Vertex::new(x + w, y + h, 0., 1.0, 1.0, color),
-        Vertex::new(x    , y + h, 0., 0.0, 1.0, color),
+        vertex(x    , y    , 0., 0.0, 0.0, color),
+        vertex(x + w, y    , 0., 1.0, 0.0, color),
+        vertex(x + w, y + h, 0., 1.0, 1.0, color),
+        vertex(x    , y + h, 0., 0.0, 1.0, color),
     ];
     let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
 
@@ -60,8 +67,8 @@ pub fn draw_rectangle(
 
 /// Draws a rectangle outline with its top-left corner at `[x, y]` with size `[w, h]` (width going to
 /// the right, height going down), with a given line `thickness` and `color`.
-pub fn draw_rectangle_lines(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_rectangle_lines<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     w: f32,
@@ -73,15 +80,15 @@ pub fn draw_rectangle_lines(
 
     #[rustfmt::skip]
     let vertices = [
-        Vertex::new(x    , y    , 0., 0.0, 1.0, color),
-        Vertex::new(x + w, y    , 0., 1.0, 0.0, color),
-        Vertex::new(x + w, y + h, 0., 1.0, 1.0, color),
-        Vertex::new(x    , y + h, 0., 0.0, 0.0, color),
+        vertex(x    , y    , 0., 0.0, 1.0, color),
+        vertex(x + w, y    , 0., 1.0, 0.0, color),
+        vertex(x + w, y + h, 0., 1.0, 1.0, color),
+        vertex(x    , y + h, 0., 0.0, 0.0, color),
         //inner rectangle
-        Vertex::new(x + t    , y + t    , 0., 0.0, 0.0, color),
-        Vertex::new(x + w - t, y + t    , 0., 0.0, 0.0, color),
-        Vertex::new(x + w - t, y + h - t, 0., 0.0, 0.0, color),
-        Vertex::new(x + t    , y + h - t, 0., 0.0, 0.0, color),
+        vertex(x + t    , y + t    , 0., 0.0, 0.0, color),
+        vertex(x + w - t, y + t    , 0., 0.0, 0.0, color),
+        vertex(x + w - t, y + h - t, 0., 0.0, 0.0, color),
+        vertex(x + t    , y + h - t, 0., 0.0, 0.0, color),
     ];
     let indices: [u16; 24] = [
         0, 1, 4, 1, 4, 5, 1, 5, 6, 1, 2, 6, 3, 7, 2, 2, 7, 6, 0, 4, 3, 3, 4, 7,
@@ -92,8 +99,8 @@ pub fn draw_rectangle_lines(
     renderer.push_geometry(&vertices, &indices);
 }
 
-pub fn draw_rectangle_lines_ex(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_rectangle_lines_ex<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     w: f32,
@@ -124,15 +131,15 @@ pub fn draw_rectangle_lines_ex(
     // TODO: fix UVs
     #[rustfmt::skip]
     let vertices = [
-        Vertex::new(v[0].x, v[0].y, v[0].z, 0.0, 1.0, params.color),
-        Vertex::new(v[1].x, v[1].y, v[1].z, 1.0, 0.0, params.color),
-        Vertex::new(v[2].x, v[2].y, v[2].z, 1.0, 1.0, params.color),
-        Vertex::new(v[3].x, v[3].y, v[3].z, 1.0, 0.0, params.color),
-
-        Vertex::new(v[4].x, v[4].y, v[4].z, 0.0, 0.0, params.color),
-        Vertex::new(v[5].x, v[5].y, v[5].z, 0.0, 0.0, params.color),
-        Vertex::new(v[6].x, v[6].y, v[6].z, 0.0, 0.0, params.color),
-        Vertex::new(v[7].x, v[7].y, v[7].z, 0.0, 0.0, params.color),
+        vertex(v[0].x, v[0].y, v[0].z, 0.0, 1.0, params.color),
+        vertex(v[1].x, v[1].y, v[1].z, 1.0, 0.0, params.color),
+        vertex(v[2].x, v[2].y, v[2].z, 1.0, 1.0, params.color),
+        vertex(v[3].x, v[3].y, v[3].z, 1.0, 0.0, params.color),
+
+        vertex(v[4].x, v[4].y, v[4].z, 0.0, 0.0, params.color),
+        vertex(v[5].x, v[5].y, v[5].z, 0.0, 0.0, params.color),
+        vertex(v[6].x, v[6].y, v[6].z, 0.0, 0.0, params.color),
+        vertex(v[7].x, v[7].y, v[7].z, 0.0, 0.0, params.color),
     ];
     #[rustfmt::skip]
     let indices: [u16; 24] = [
@@ -176,8 +183,8 @@ impl Default for DrawRectangleParams {
 
 /// Draws a solid rectangle with its position at `[x, y]` with size `[w, h]`,
 /// with parameters.
-pub fn draw_rectangle_ex(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_rectangle_ex<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     w: f32,
@@ -198,10 +205,10 @@ pub fn draw_rectangle_ex(
 
     #[rustfmt::skip]
     let vertices = [
-        Vertex::new(v[0].x, v[0].y, v[0].z, 0.0, 0.0, params.color),
-        Vertex::new(v[1].x, v[1].y, v[1].z, 1.0, 0.0, params.color),
-        Vertex::new(v[2].x, v[2].y, v[2].z, 1.0, 1.0, params.color),
-        Vertex::new(v[3].x, v[3].y, v[3].z, 0.0, 1.0, params.color),
+        vertex(v[0].x, v[0].y, v[0].z, 0.0, 0.0, params.color),
+        vertex(v[1].x, v[1].y, v[1].z, 1.0, 0.0, params.color),
+        vertex(v[2].x, v[2].y, v[2].z, 1.0, 1.0, params.color),
+        vertex(v[3].x, v[3].y, v[3].z, 0.0, 1.0, params.color),
     ];
     let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
 
@@ -213,8 +220,8 @@ pub fn draw_rectangle_ex(
 /// Draws an outlined solid hexagon centered at `[x, y]` with a radius `size`, outline thickness
 /// defined by `border`, orientation defined by `vertical` (when `true`, the hexagon points along
 /// the `y` axis), and colors for outline given by `border_color` and fill by `fill_color`.
-pub fn draw_hexagon(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_hexagon<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     size: f32,
@@ -232,8 +239,8 @@ pub fn draw_hexagon(
 
 /// Draws a solid regular polygon centered at `[x, y]` with a given number of `sides`, `radius`,
 /// clockwise `rotation` (in degrees) and `color`.
-pub fn draw_poly(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_poly<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     sides: u8,
@@ -241,16 +248,16 @@ pub fn draw_poly(
     rotation: f32,
     color: Color,
 ) {
-    let mut vertices = Vec::<Vertex>::with_capacity(sides as usize + 2);
+    let mut vertices = Vec::<V>::with_capacity(sides as usize + 2);
     let mut indices = Vec::<u16>::with_capacity(sides as usize * 3);
 
     let rot = rotation.to_radians();
-    vertices.push(Vertex::new(x, y, 0., 0., 0., color));
+    vertices.push(vertex(x, y, 0., 0., 0., color));
     for i in 0..=sides {
         let rx = (i as f32 / sides as f32 * std::f32::consts::PI * 2. + rot).cos();
         let ry = (i as f32 / sides as f32 * std::f32::consts::PI * 2. + rot).sin();
 
-        let vertex = Vertex::new(x + radius * rx, y + radius * ry, 0., rx, ry, color);
+        let vertex = vertex(x + radius * rx, y + radius * ry, 0., rx, ry, color);
 
         vertices.push(vertex);
 
@@ -266,8 +273,8 @@ pub fn draw_poly(
 
 /// Draws a regular polygon outline centered at `[x, y]` with a given number of `sides`, `radius`,
 /// clockwise `rotation` (in degrees), line `thickness`, and `color`.
-pub fn draw_poly_lines(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_poly_lines<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     sides: u8,
@@ -282,13 +289,19 @@ pub fn draw_poly_lines(
 }
 
 /// Draws a solid circle centered at `[x, y]` with a given radius `r` and `color`.
-pub fn draw_circle(renderer: &mut Renderer<Vertex>, x: f32, y: f32, r: f32, color: Color) {
+pub fn draw_circle<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
+    x: f32,
+    y: f32,
+    r: f32,
+    color: Color,
+) {
     draw_poly(renderer, x, y, 20, r, 0., color);
 }
 
 /// Draws a circle outline centered at `[x, y]` with a given radius, line `thickness` and `color`.
-pub fn draw_circle_lines(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_circle_lines<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     r: f32,
@@ -300,8 +313,8 @@ pub fn draw_circle_lines(
 
 /// Draws a solid ellipse centered at `[x, y]` with a given size `[w, h]`,
 /// clockwise `rotation` (in degrees) and `color`.
-pub fn draw_ellipse(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_ellipse<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     w: f32,
@@ -311,13 +324,13 @@ pub fn draw_ellipse(
 ) {
     let sides = 20;
 
-    let mut vertices = Vec::<Vertex>::with_capacity(sides as usize + 2);
+    let mut vertices = Vec::<V>::with_capacity(sides as usize + 2);
     let mut indices = Vec::<u16>::with_capacity(sides as usize * 3);
 
     let rot = rotation.to_radians();
     let sr = rot.sin();
     let cr = rot.cos();
-    vertices.push(Vertex::new(x, y, 0., 0., 0., color));
+    vertices.push(vertex(x, y, 0., 0., 0., color));
     for i in 0..=sides {
         let rx = (i as f32 / sides as f32 * std::f32::consts::PI * 2.).cos();
         let ry = (i as f32 / sides as f32 * std::f32::consts::PI * 2.).sin();
@@ -326,7 +339,7 @@ pub fn draw_ellipse(
         let py = h * ry;
         let rotated_x = px * cr - py * sr;
         let rotated_y = py * cr + px * sr;
-        let vertex = Vertex::new(x + rotated_x, y + rotated_y, 0., rx, ry, color);
+        let vertex = vertex(x + rotated_x, y + rotated_y, 0., rx, ry, color);
 
         vertices.push(vertex);
 
@@ -342,8 +355,8 @@ pub fn draw_ellipse(
 
 /// Draws an ellipse outline centered at `[x, y]` with a given size `[w, h]`,
 /// clockwise `rotation` (in degrees), line `thickness` and `color`.
-pub fn draw_ellipse_lines(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_ellipse_lines<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     w: f32,
@@ -381,8 +394,8 @@ pub fn draw_ellipse_lines(
 }
 
 /// Draws a line between points `[x1, y1]` and `[x2, y2]` with a given `thickness` and `color`.
-pub fn draw_line(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_line<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x1: f32,
     y1: f32,
     x2: f32,
@@ -409,10 +422,10 @@ pub fn draw_line(
     renderer.with_draw_mode(DrawMode::Triangles);
     renderer.push_geometry(
         &[
-            Vertex::new(x1 + tx, y1 + ty, 0., 0., 0., color),
-            Vertex::new(x1 - tx, y1 - ty, 0., 0., 0., color),
-            Vertex::new(x2 + tx, y2 + ty, 0., 0., 0., color),
-            Vertex::new(x2 - tx, y2 - ty, 0., 0., 0., color),
+            vertex(x1 + tx, y1 + ty, 0., 0., 0., color),
+            vertex(x1 - tx, y1 - ty, 0., 0., 0., color),
+            vertex(x2 + tx, y2 + ty, 0., 0., 0., color),
+            vertex(x2 - tx, y2 - ty, 0., 0., 0., color),
         ],
         &[0, 1, 2, 2, 1, 3],
     );
@@ -420,8 +433,8 @@ pub fn draw_line(
 
 /// Draw arc from `rotation`(in degrees) to `arc + rotation` (`arc` in degrees),
 /// centered at `[x, y]` with a given number of `sides`, `radius`, line `thickness`, and `color`.
-pub fn draw_arc(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_arc<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     x: f32,
     y: f32,
     sides: u8,
@@ -443,7 +456,7 @@ pub fn draw_arc(
     renderer.with_texture(None);
     renderer.with_draw_mode(DrawMode::Triangles);
 
-    let mut verticies = Vec::<Vertex>::with_capacity(sides * 2);
+    let mut verticies = Vec::<V>::with_capacity(sides * 2);
     let mut indicies = Vec::<u16>::with_capacity(sides * 2);
 
     for i in 0..sides {
@@ -459,7 +472,7 @@ pub fn draw_arc(
             (end_angle, radius + thickness),
         ] {
             let point = Vec2::new(x, y) + radius * Vec2::from_angle(angle);
-            verticies.push(Vertex::new(point.x, point.y, 0., 0., 0., color));
+            verticies.push(vertex(point.x, point.y, 0., 0., 0., color));
         }
     }
 
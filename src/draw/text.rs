@@ -3,8 +3,9 @@ use miniquad::RenderingBackend;
 
 use crate::{
     color::{Color, WHITE},
-    graphics::{Renderer, Vertex},
-    text::{FontAtlas, TextDimensions},
+    graphics::{DrawTarget, DrawVertex},
+    text::{FontAtlas, FontSnapshot, Glyph, TextDimensions},
+    texture::Texture,
     utils::Rect,
 };
 
@@ -40,9 +41,9 @@ impl Default for TextParams {
 
 /// Draw text with given font_size
 /// Returns text size
-pub fn draw_text(
+pub fn draw_text<V: DrawVertex>(
     backend: &mut dyn RenderingBackend,
-    renderer: &mut Renderer<Vertex>,
+    renderer: &mut impl DrawTarget<V>,
     font: &mut FontAtlas,
     text: &str,
     x: f32,
@@ -68,9 +69,9 @@ pub fn draw_text(
 
 /// Draw text with custom params such as font, font size and font scale
 /// Returns text size
-pub fn draw_text_ex(
+pub fn draw_text_ex<V: DrawVertex>(
     backend: &mut dyn RenderingBackend,
-    renderer: &mut Renderer,
+    renderer: &mut impl DrawTarget<V>,
     font: &mut FontAtlas,
     text: &str,
     x: f32,
@@ -82,7 +83,66 @@ pub fn draw_text_ex(
     }
 
     let dpi_scaling = miniquad::window::dpi_scale();
+    let font_size = (params.font_size as f32 * dpi_scaling).ceil() as u16;
+    for character in text.chars() {
+        font.cache_glyph(character, font_size);
+    }
+    let texture = font.atlas.texture(backend).clone();
+
+    draw_glyphs(
+        renderer,
+        &texture,
+        dpi_scaling,
+        text,
+        x,
+        y,
+        params,
+        |character, size| font.glyph(character, size),
+    )
+}
 
+/// Draw text with glyphs from a [FontSnapshot]. Unlike [draw_text_ex], it needs neither a backend
+/// nor a mutable font, so it can be used on worker threads to fill a
+/// [CommandBuffer](crate::graphics::CommandBuffer).
+///
+/// Characters missing from the snapshot are skipped.
+/// Returns text size
+pub fn draw_text_cached<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
+    font: &FontSnapshot,
+    text: &str,
+    x: f32,
+    y: f32,
+    params: TextParams,
+) -> TextDimensions {
+    if text.is_empty() {
+        return TextDimensions::default();
+    }
+
+    draw_glyphs(
+        renderer,
+        font.texture(),
+        font.dpi_scale(),
+        text,
+        x,
+        y,
+        params,
+        |character, size| font.glyph(character, size),
+    )
+}
+
+/// Draw the glyphs of every character of the text, skipping the ones `glyph` doesn't have
+#[allow(clippy::too_many_arguments)]
+fn draw_glyphs<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
+    texture: &Texture,
+    dpi_scaling: f32,
+    text: &str,
+    x: f32,
+    y: f32,
+    params: TextParams,
+    glyph: impl Fn(char, u16) -> Option<Glyph>,
+) -> TextDimensions {
     let rot = params.rotation;
     let font_scale_x = params.font_scale * params.font_scale_aspect;
     let font_scale_y = params.font_scale;
@@ -93,16 +153,13 @@ pub fn draw_text_ex(
     let mut min_offset_y = f32::MAX;
 
     for character in text.chars() {
-        if !font.contains(character, font_size) {
-            font.cache_glyph(character, font_size);
-        }
-
-        let char_data = &font.characters[&(character, font_size)];
+        let Some(char_data) = glyph(character, font_size) else {
+            continue;
+        };
         let offset_x = char_data.offset_x as f32 * font_scale_x;
         let offset_y = char_data.offset_y as f32 * font_scale_y;
 
-        let atlas = &mut font.atlas;
-        let glyph = atlas.get(char_data.sprite).unwrap().rect;
+        let glyph = char_data.rect;
         let glyph_scaled_h = glyph.h * font_scale_y;
 
         min_offset_y = min_offset_y.min(offset_y);
@@ -124,7 +181,7 @@ pub fn draw_text_ex(
 
         super::draw_texture_ex(
             renderer,
-            atlas.texture(backend),
+            texture,
             dest.x,
             dest.y,
             params.color,
@@ -138,6 +195,11 @@ pub fn draw_text_ex(
         );
     }
 
+    if min_offset_y > max_offset_y {
+        // None of the characters had a glyph
+        return TextDimensions::default();
+    }
+
     TextDimensions {
         width: total_width / dpi_scaling,
         height: (max_offset_y - min_offset_y) / dpi_scaling,
@@ -147,9 +209,9 @@ pub fn draw_text_ex(
 
 /// Draw multiline text with the given font_size, line_distance_factor and color.
 /// If no line distance but a custom font is given, the fonts line gap will be used as line distance factor if it exists.
-pub fn draw_multiline_text(
+pub fn draw_multiline_text<V: DrawVertex>(
     backend: &mut dyn RenderingBackend,
-    renderer: &mut Renderer<Vertex>,
+    renderer: &mut impl DrawTarget<V>,
     font: &mut FontAtlas,
     text: &str,
     x: f32,
@@ -177,9 +239,9 @@ pub fn draw_multiline_text(
 
 /// Draw multiline text with the given line distance and custom params such as font, font size and font scale.
 /// If no line distance but a custom font is given, the fonts newline size will be used as line distance factor if it exists.
-pub fn draw_multiline_text_ex(
+pub fn draw_multiline_text_ex<V: DrawVertex>(
     backend: &mut dyn RenderingBackend,
-    renderer: &mut Renderer<Vertex>,
+    renderer: &mut impl DrawTarget<V>,
     font: &mut FontAtlas,
     text: &str,
     x: f32,
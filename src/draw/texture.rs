@@ -1,6 +1,7 @@
+use super::vertex;
 use crate::{
     color::Color,
-    graphics::{DrawMode, Renderer, Vertex},
+    graphics::{DrawMode, DrawTarget, DrawVertex},
     texture::Texture,
     utils::Rect,
 };
@@ -45,8 +46,8 @@ impl Default for DrawTextureParams {
     }
 }
 
-pub fn draw_texture(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_texture<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     texture: &Texture,
     x: f32,
     y: f32,
@@ -55,8 +56,8 @@ pub fn draw_texture(
     draw_texture_ex(renderer, texture, x, y, color, Default::default());
 }
 
-pub fn draw_texture_ex(
-    renderer: &mut Renderer<Vertex>,
+pub fn draw_texture_ex<V: DrawVertex>(
+    renderer: &mut impl DrawTarget<V>,
     texture: &Texture,
     x: f32,
     y: f32,
@@ -138,10 +139,10 @@ pub fn draw_texture_ex(
     ];
     #[rustfmt::skip]
     let vertices = [
-        Vertex::new(p[0].x, p[0].y, 0.,  sx      /width,  sy      /height, color),
-        Vertex::new(p[1].x, p[1].y, 0., (sx + sw)/width,  sy      /height, color),
-        Vertex::new(p[2].x, p[2].y, 0., (sx + sw)/width, (sy + sh)/height, color),
-        Vertex::new(p[3].x, p[3].y, 0.,  sx      /width, (sy + sh)/height, color),
+        vertex(p[0].x, p[0].y, 0.,  sx      /width,  sy      /height, color),
+        vertex(p[1].x, p[1].y, 0., (sx + sw)/width,  sy      /height, color),
+        vertex(p[2].x, p[2].y, 0., (sx + sw)/width, (sy + sh)/height, color),
+        vertex(p[3].x, p[3].y, 0.,  sx      /width, (sy + sh)/height, color),
     ];
     let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
 
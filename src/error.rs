@@ -1,3 +1,26 @@
+/// Errors of the checked uniform setters,
+/// like [Renderer::try_set_uniform](crate::graphics::Renderer::try_set_uniform)
+#[derive(Debug)]
+pub enum UniformError {
+    /// The pipeline has no uniform with this name
+    UnknownName(String),
+    /// The uniform was declared with another type
+    TypeMismatch {
+        name: String,
+        expected: miniquad::UniformType,
+        found: miniquad::UniformType,
+    },
+    /// More values were given than the uniform array can hold
+    ArrayOverflow {
+        name: String,
+        capacity: usize,
+        len: usize,
+    },
+    /// The handle was created before its pipeline was replaced
+    /// (see [Renderer::replace_pipeline](crate::graphics::Renderer::replace_pipeline))
+    StaleHandle,
+}
+
 #[derive(Debug)]
 pub enum Error {
     FontError(&'static str),
@@ -7,6 +30,27 @@ pub enum Error {
     },
     ShaderError(miniquad::ShaderError),
     ImageError(image::ImageError),
+    /// A shader preprocessor error, see [crate::graphics::material::preprocessor]
+    PreprocessorError {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    UniformError(UniformError),
+    /// The pipeline (or material) was deleted, or belongs to another renderer
+    UnknownPipeline,
+    /// A shader that can't be cross-compiled, see `graphics::cross`
+    /// (behind the `cross-compile` feature)
+    CrossCompileError {
+        shader_type: miniquad::ShaderType,
+        line: usize,
+        message: String,
+    },
+    /// A material texture or uniform uses a name reserved by the renderer, like `Texture`
+    ReservedName(String),
+    /// Post-processing was applied with draw calls still pending,
+    /// see [PostProcessChain::apply](crate::graphics::PostProcessChain::apply)
+    PendingDrawCalls,
     UnknownError(&'static str),
 }
 
@@ -22,6 +66,12 @@ impl From<miniquad::ShaderError> for Error {
     }
 }
 
+impl From<UniformError> for Error {
+    fn from(s: UniformError) -> Self {
+        Error::UniformError(s)
+    }
+}
+
 impl From<image::ImageError> for Error {
     fn from(s: image::ImageError) -> Self {
         Error::ImageError(s)
@@ -0,0 +1,257 @@
+//! Alternative [RenderingBackend](miniquad::RenderingBackend) implementations.
+//!
+//! These backends don't need a window or a GPU, which makes them useful for testing rendering
+//! code on CI machines: [SoftwareBackend] rasterizes everything on the CPU, so the result of
+//! [Renderer::draw](crate::graphics::Renderer::draw) can be read back and compared against
+//! reference images, while [RecordingBackend] captures the stream of commands issued by the
+//! renderer, so batching behaviour can be checked.
+//!
+//! # Soundness
+//!
+//! miniquad doesn't give backends outside of it a way to read the data of a
+//! [BufferSource::Slice], so the backends here read its fields through a copy of its declaration
+//! (see `ArgFields`). Rust doesn't guarantee that 2 identical `repr(Rust)` declarations get the
+//! same layout, which makes this unsound in principle, however well it works in practice. This
+//! is why the module is only compiled for the tests of this crate, and behind the
+//! `unstable-software-backend` feature for tests of other crates. Don't enable it in builds you
+//! ship.
+
+use miniquad::BufferSource;
+
+use crate::texture::Image;
+
+mod raster;
+mod recording;
+mod software;
+
+pub use recording::{Command, RecordingBackend};
+pub use software::SoftwareBackend;
+
+/// Compare 2 images pixel by pixel, and return the amount of pixels that differ.
+///
+/// A pixel is considered different if any of its channels differs by more than `tolerance`.
+/// Returns [None] if the images have different sizes.
+pub fn compare_images(expected: &Image, actual: &Image, tolerance: u8) -> Option<usize> {
+    if expected.width != actual.width || expected.height != actual.height {
+        return None;
+    }
+
+    let different = expected
+        .get_image_data()
+        .iter()
+        .zip(actual.get_image_data())
+        .filter(|(a, b)| {
+            a.iter()
+                .zip(b.iter())
+                .any(|(a, b)| a.abs_diff(*b) > tolerance)
+        })
+        .count();
+
+    Some(different)
+}
+
+/// Create a miniquad resource handle from an index.
+///
+/// miniquad doesn't expose constructors for its handles ([miniquad::ShaderId], [miniquad::Pipeline],
+/// [miniquad::BufferId], [miniquad::RenderPass]). In miniquad 0.4.7 (the version this crate pins)
+/// all of them are `struct Handle(usize)`, which is checked against their [Debug] output here.
+fn handle<T: Copy + std::fmt::Debug>(index: usize) -> T {
+    assert_eq!(std::mem::size_of::<T>(), std::mem::size_of::<usize>());
+    // SAFETY: sizes are checked above, and `T` is a usize newtype, which is checked below
+    let handle: T = unsafe { std::mem::transmute_copy(&index) };
+
+    assert!(
+        format!("{handle:?}").ends_with(&format!("({index})")),
+        "Unsupported miniquad handle layout"
+    );
+    handle
+}
+
+/// The inverse of [handle]
+fn handle_index<T: Copy>(handle: T) -> usize {
+    assert_eq!(std::mem::size_of::<T>(), std::mem::size_of::<usize>());
+    // SAFETY: sizes are checked above, and all handles are created with [handle]
+    unsafe { std::mem::transmute_copy(&handle) }
+}
+
+/// Raw data of a [BufferSource]
+struct BufferData<'a> {
+    bytes: &'a [u8],
+    element_size: usize,
+}
+
+/// A copy of the declaration of miniquad's `Arg` (the payload of [BufferSource::Slice]) in
+/// miniquad 0.4.7, the version this crate pins.
+///
+/// `Arg` keeps all of its fields private, so there's no way for a backend outside of miniquad to
+/// read the data it was given. Both structs are declared the same way, but Rust doesn't promise
+/// that this gives them the same layout, so reading an `Arg` through this struct is undefined
+/// behaviour as far as the language is concerned (see the [module docs](self)).
+/// [ArgFields::check] catches a layout that is obviously different, it can't prove the cast
+/// sound since it reads through the same cast.
+struct ArgFields<'a> {
+    ptr: *const std::ffi::c_void,
+    element_size: usize,
+    size: usize,
+    is_slice: bool,
+    _phantom: std::marker::PhantomData<&'a ()>,
+}
+
+impl<'a> ArgFields<'a> {
+    fn of<'s>(source: &'s BufferSource<'a>) -> Option<&'s Self> {
+        let BufferSource::Slice(arg) = source else {
+            return None;
+        };
+        // SAFETY: not guaranteed, see the struct docs. Both structs have the same declaration,
+        // and `check` verifies their size and the field values against an `Arg` with known values
+        Some(unsafe { &*(arg as *const _ as *const ArgFields<'a>) })
+    }
+
+    fn check() {
+        static CHECKED: std::sync::Once = std::sync::Once::new();
+
+        CHECKED.call_once(|| {
+            let probe = [0u32; 5];
+            let source = BufferSource::slice(&probe[..]);
+            let BufferSource::Slice(arg) = &source else {
+                unreachable!()
+            };
+            assert_eq!(std::mem::size_of_val(arg), std::mem::size_of::<ArgFields>());
+            assert_eq!(std::mem::align_of_val(arg), std::mem::align_of::<ArgFields>());
+
+            let fields = ArgFields::of(&source).unwrap();
+            assert!(
+                fields.ptr == probe.as_ptr() as *const _
+                    && fields.size == std::mem::size_of_val(&probe)
+                    && fields.element_size == std::mem::size_of::<u32>()
+                    && fields.is_slice,
+                "Unsupported miniquad BufferSource layout"
+            );
+        });
+    }
+}
+
+impl<'a> BufferData<'a> {
+    /// Read the raw data of a buffer source. Returns [None] for [BufferSource::Empty]
+    fn from_source(source: &BufferSource<'a>) -> Option<Self> {
+        ArgFields::check();
+        let fields = ArgFields::of(source)?;
+
+        // SAFETY: the pointer and size were given to us by miniquad, and come from a slice
+        // that lives for at least 'a
+        let bytes = unsafe { std::slice::from_raw_parts(fields.ptr as *const u8, fields.size) };
+
+        Some(Self {
+            bytes,
+            element_size: fields.element_size,
+        })
+    }
+}
+
+#[cfg(test)]
+fn render_to_image(draw: impl FnOnce(&mut crate::graphics::Renderer)) -> Image {
+    use crate::{color::BLACK, graphics::test_renderer};
+
+    let (mut backend, mut renderer, target) = test_renderer(8);
+    renderer.clear(&mut backend, BLACK);
+    draw(&mut renderer);
+    renderer.draw(
+        &mut backend,
+        glam::Mat4::orthographic_rh_gl(0., 8., 8., 0., -1., 1.),
+    );
+
+    Image::from_texture(&mut backend, &target.texture)
+}
+
+#[test]
+fn software_rectangle_coverage() {
+    use crate::color::RED;
+
+    let image =
+        render_to_image(|renderer| crate::draw::draw_rectangle(renderer, 2., 0., 4., 2., RED));
+
+    // Render targets start from the bottom row, so the rectangle ends up in the last 2 rows
+    let covered: Vec<(usize, usize)> = image
+        .get_image_data()
+        .iter()
+        .enumerate()
+        .filter(|(_, pixel)| **pixel == <[u8; 4]>::from(RED))
+        .map(|(ix, _)| (ix % 8, ix / 8))
+        .collect();
+
+    assert_eq!(covered.len(), 8);
+    assert!(covered
+        .iter()
+        .all(|&(x, y)| (2..6).contains(&x) && (6..8).contains(&y)));
+}
+
+#[test]
+fn software_alpha_blending() {
+    use crate::color::Color;
+
+    let image = render_to_image(|renderer| {
+        crate::draw::draw_rectangle(renderer, 0., 0., 8., 8., Color::new(1.0, 1.0, 1.0, 0.5))
+    });
+
+    // Alpha is blended with the same function as the color
+    assert!(image
+        .get_image_data()
+        .iter()
+        .all(|pixel| *pixel == [127, 127, 127, 191]));
+}
+
+#[cfg(test)]
+fn record_draws(draw: impl FnOnce(&mut crate::graphics::Renderer)) -> Vec<Command> {
+    let (mut backend, mut renderer, _target) = crate::graphics::test_renderer(8);
+    draw(&mut renderer);
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    backend.take_commands()
+}
+
+#[test]
+fn recording_batches_rectangles() {
+    use crate::color::WHITE;
+
+    let commands = record_draws(|renderer| {
+        for i in 0..10 {
+            crate::draw::draw_rectangle(renderer, i as f32, 0., 1., 1., WHITE);
+        }
+    });
+
+    let draws: Vec<&Command> = commands
+        .iter()
+        .filter(|command| matches!(command, Command::Draw { .. }))
+        .collect();
+    assert_eq!(
+        draws,
+        [&Command::Draw {
+            base_element: 0,
+            num_elements: 60,
+            num_instances: 1
+        }]
+    );
+}
+
+#[test]
+fn recording_texture_breaks_batch() {
+    use crate::color::WHITE;
+
+    let commands = record_draws(|renderer| {
+        crate::draw::draw_rectangle(renderer, 0., 0., 1., 1., WHITE);
+        renderer.with_texture(Some(&miniquad::TextureId::from_raw_id(
+            miniquad::RawId::OpenGl(1),
+        )));
+        renderer.push_geometry(
+            &[crate::graphics::Vertex::new(0., 0., 0., 0., 0., WHITE); 3],
+            &[0, 1, 2],
+        );
+    });
+
+    let draws = commands
+        .iter()
+        .filter(|command| matches!(command, Command::Draw { .. }))
+        .count();
+    assert_eq!(draws, 2);
+}
@@ -0,0 +1,704 @@
+//! CPU rasterization for the [SoftwareBackend](super::SoftwareBackend)
+
+use glam::{vec4, Mat4, Vec2, Vec4};
+use miniquad::*;
+
+use super::software::SoftTexture;
+
+/// A vertex attribute with resolved byte offset and stride
+pub(super) struct SoftAttribute {
+    pub name: &'static str,
+    pub format: VertexFormat,
+    pub buffer_index: usize,
+    pub per_instance: bool,
+    pub stride: usize,
+    pub offset: usize,
+}
+
+/// [BlendState] with its fields accessible
+#[derive(Clone, Copy)]
+struct Blend {
+    equation: Equation,
+    sfactor: BlendFactor,
+    dfactor: BlendFactor,
+}
+
+impl Blend {
+    const EQUATIONS: [Equation; 3] = [Equation::Add, Equation::Subtract, Equation::ReverseSubtract];
+
+    const FACTORS: [BlendFactor; 11] = [
+        BlendFactor::Zero,
+        BlendFactor::One,
+        BlendFactor::Value(BlendValue::SourceColor),
+        BlendFactor::Value(BlendValue::SourceAlpha),
+        BlendFactor::Value(BlendValue::DestinationColor),
+        BlendFactor::Value(BlendValue::DestinationAlpha),
+        BlendFactor::OneMinusValue(BlendValue::SourceColor),
+        BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+        BlendFactor::OneMinusValue(BlendValue::DestinationColor),
+        BlendFactor::OneMinusValue(BlendValue::DestinationAlpha),
+        BlendFactor::SourceAlphaSaturate,
+    ];
+
+    /// miniquad keeps the fields of [BlendState] private, but it can be compared, so we simply look
+    /// for the matching combination
+    fn from_state(state: BlendState) -> Self {
+        for equation in Self::EQUATIONS {
+            for sfactor in Self::FACTORS {
+                for dfactor in Self::FACTORS {
+                    if BlendState::new(equation, sfactor, dfactor) == state {
+                        return Self {
+                            equation,
+                            sfactor,
+                            dfactor,
+                        };
+                    }
+                }
+            }
+        }
+        unreachable!()
+    }
+
+    /// Blend a single channel. `channel` is 3 for alpha
+    fn apply(&self, src: Vec4, dst: Vec4, channel: usize) -> f32 {
+        let factor = |factor: BlendFactor| match factor {
+            BlendFactor::Zero => 0.0,
+            BlendFactor::One => 1.0,
+            BlendFactor::Value(value) => Self::value(value, src, dst, channel),
+            BlendFactor::OneMinusValue(value) => 1.0 - Self::value(value, src, dst, channel),
+            BlendFactor::SourceAlphaSaturate if channel == 3 => 1.0,
+            BlendFactor::SourceAlphaSaturate => src.w.min(1.0 - dst.w),
+        };
+
+        let s = src[channel] * factor(self.sfactor);
+        let d = dst[channel] * factor(self.dfactor);
+
+        match self.equation {
+            Equation::Add => s + d,
+            Equation::Subtract => s - d,
+            Equation::ReverseSubtract => d - s,
+        }
+    }
+
+    fn value(value: BlendValue, src: Vec4, dst: Vec4, channel: usize) -> f32 {
+        match value {
+            BlendValue::SourceColor => src[channel],
+            BlendValue::SourceAlpha => src.w,
+            BlendValue::DestinationColor => dst[channel],
+            BlendValue::DestinationAlpha => dst.w,
+        }
+    }
+}
+
+/// A pipeline, prepared for rasterization
+pub(super) struct SoftPipeline {
+    attributes: Vec<SoftAttribute>,
+    params: PipelineParams,
+    color_blend: Option<Blend>,
+    alpha_blend: Option<Blend>,
+    projection: Option<usize>,
+    model: Option<usize>,
+}
+
+impl SoftPipeline {
+    pub fn new(
+        buffer_layout: &[BufferLayout],
+        attributes: &[VertexAttribute],
+        meta: &ShaderMeta,
+        params: PipelineParams,
+    ) -> Self {
+        // Same stride and offset rules as in miniquad's GL backend
+        let mut strides = vec![0; buffer_layout.len()];
+        for attribute in attributes {
+            let layout = &buffer_layout[attribute.buffer_index];
+            if layout.stride == 0 {
+                strides[attribute.buffer_index] += attribute.format.size_bytes() as usize;
+            } else {
+                strides[attribute.buffer_index] = layout.stride as usize;
+            }
+        }
+
+        let mut offsets = vec![0; buffer_layout.len()];
+        let attributes = attributes
+            .iter()
+            .map(|attribute| {
+                let offset = offsets[attribute.buffer_index];
+                offsets[attribute.buffer_index] += attribute.format.size_bytes() as usize;
+
+                SoftAttribute {
+                    name: attribute.name,
+                    format: attribute.format,
+                    buffer_index: attribute.buffer_index,
+                    per_instance: buffer_layout[attribute.buffer_index].step_func
+                        == VertexStep::PerInstance,
+                    stride: strides[attribute.buffer_index],
+                    offset,
+                }
+            })
+            .collect();
+
+        let uniform_offset = |name: &str| {
+            let mut offset = 0;
+            for uniform in &meta.uniforms.uniforms {
+                if uniform.name == name {
+                    return Some(offset);
+                }
+                offset += uniform.uniform_type.size() * uniform.array_count;
+            }
+            None
+        };
+
+        Self {
+            attributes,
+            projection: uniform_offset("Projection"),
+            model: uniform_offset("Model"),
+            color_blend: params.color_blend.map(Blend::from_state),
+            alpha_blend: params
+                .alpha_blend
+                .or(params.color_blend)
+                .map(Blend::from_state),
+            params,
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&SoftAttribute> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name == name)
+    }
+}
+
+/// Output of the emulated vertex shader
+#[derive(Clone, Copy)]
+pub(super) struct ClipVertex {
+    position: Vec4,
+    uv: Vec2,
+    color: Vec4,
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            position: self.position.lerp(other.position, t),
+            uv: self.uv.lerp(other.uv, t),
+            color: self.color.lerp(other.color, t),
+        }
+    }
+}
+
+/// Decode an attribute value the same way OpenGL does for `gl_pass_as_float` attributes.
+/// Missing components are filled from `(0, 0, 0, 1)`
+fn decode_attribute(format: VertexFormat, bytes: &[u8]) -> Vec4 {
+    let mut value = [0.0, 0.0, 0.0, 1.0];
+    let components = match format {
+        VertexFormat::Mat4 => 4,
+        format => format.components() as usize,
+    };
+    let component_size = match format {
+        VertexFormat::Byte1 | VertexFormat::Byte2 | VertexFormat::Byte3 | VertexFormat::Byte4 => 1,
+        VertexFormat::Short1
+        | VertexFormat::Short2
+        | VertexFormat::Short3
+        | VertexFormat::Short4 => 2,
+        _ => 4,
+    };
+
+    for (ix, component) in bytes
+        .chunks_exact(component_size)
+        .take(components)
+        .enumerate()
+    {
+        value[ix] = match (format, component) {
+            (_, &[byte]) => byte as f32,
+            (_, &[a, b]) => u16::from_le_bytes([a, b]) as f32,
+            (
+                VertexFormat::Int1 | VertexFormat::Int2 | VertexFormat::Int3 | VertexFormat::Int4,
+                &[a, b, c, d],
+            ) => u32::from_le_bytes([a, b, c, d]) as f32,
+            (_, &[a, b, c, d]) => f32::from_le_bytes([a, b, c, d]),
+            _ => unreachable!(),
+        };
+    }
+
+    Vec4::from_array(value)
+}
+
+fn read_mat4(uniforms: &[u8], offset: Option<usize>) -> Mat4 {
+    let Some(bytes) = offset.and_then(|offset| uniforms.get(offset..offset + 64)) else {
+        return Mat4::IDENTITY;
+    };
+
+    let mut values = [0.0; 16];
+    for (value, bytes) in values.iter_mut().zip(bytes.chunks_exact(4)) {
+        *value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    Mat4::from_cols_array(&values)
+}
+
+/// Run the emulated default vertex shader. `fetch` returns the raw bytes of an attribute
+pub(super) fn shade_vertex<'a>(
+    pipeline: &SoftPipeline,
+    uniforms: &[u8],
+    fetch: impl Fn(&SoftAttribute) -> Option<&'a [u8]>,
+) -> ClipVertex {
+    let try_attribute = |name: &str| {
+        pipeline
+            .attribute(name)
+            .and_then(|attribute| Some(decode_attribute(attribute.format, fetch(attribute)?)))
+    };
+    let attribute = |name: &str| try_attribute(name).unwrap_or(vec4(0.0, 0.0, 0.0, 1.0));
+
+    let position = attribute("position").truncate().extend(1.0);
+    let projection = read_mat4(uniforms, pipeline.projection);
+    let model = read_mat4(uniforms, pipeline.model);
+    let mut color = attribute("color0") / 255.0;
+
+    // The default instanced shader
+    let instance_model = match try_attribute("instance_model0") {
+        Some(x) => Mat4::from_cols(
+            x,
+            attribute("instance_model1"),
+            attribute("instance_model2"),
+            attribute("instance_model3"),
+        ),
+        None => Mat4::IDENTITY,
+    };
+    if let Some(instance_color) = try_attribute("instance_color") {
+        color *= instance_color / 255.0;
+    }
+
+    ClipVertex {
+        position: projection * model * instance_model * position,
+        uv: attribute("texcoord").truncate().truncate(),
+        color,
+    }
+}
+
+/// Render targets of a draw call, with the ids they're stored under
+pub(super) struct Attachments {
+    pub colors: Vec<(TextureId, SoftTexture)>,
+    pub depth: Option<SoftTexture>,
+}
+
+impl Attachments {
+    fn size(&self) -> (i32, i32) {
+        self.colors
+            .first()
+            .map(|(_, texture)| texture)
+            .or(self.depth.as_ref())
+            .map(|texture| (texture.params.width as i32, texture.params.height as i32))
+            .unwrap_or_default()
+    }
+}
+
+/// A vertex in window coordinates
+#[derive(Clone, Copy)]
+struct WindowVertex {
+    x: f32,
+    y: f32,
+    z: f32,
+    inv_w: f32,
+    vertex: ClipVertex,
+}
+
+struct Rasterizer<'a> {
+    pipeline: &'a SoftPipeline,
+    texture: Option<&'a SoftTexture>,
+    viewport: (i32, i32, i32, i32),
+    /// Pixel bounds (min x, min y, max x, max y), exclusive on the max side
+    bounds: (i32, i32, i32, i32),
+    attachments: &'a mut Attachments,
+    /// Whether the current primitive is front facing, which selects the stencil face state.
+    /// Lines and points are always front facing
+    front_facing: bool,
+}
+
+/// Rasterize a list of vertices (already indexed) with the current pipeline
+pub(super) fn rasterize(
+    pipeline: &SoftPipeline,
+    vertices: &[ClipVertex],
+    texture: Option<&SoftTexture>,
+    viewport: (i32, i32, i32, i32),
+    scissor: (i32, i32, i32, i32),
+    attachments: &mut Attachments,
+) {
+    let (width, height) = attachments.size();
+    let bounds = (
+        viewport.0.max(scissor.0).max(0),
+        viewport.1.max(scissor.1).max(0),
+        (viewport.0 + viewport.2)
+            .min(scissor.0 + scissor.2)
+            .min(width),
+        (viewport.1 + viewport.3)
+            .min(scissor.1 + scissor.3)
+            .min(height),
+    );
+    if bounds.0 >= bounds.2 || bounds.1 >= bounds.3 {
+        return;
+    }
+
+    let mut rasterizer = Rasterizer {
+        pipeline,
+        texture,
+        viewport,
+        bounds,
+        attachments,
+        front_facing: true,
+    };
+
+    match pipeline.params.primitive_type {
+        PrimitiveType::Triangles => {
+            for triangle in vertices.chunks_exact(3) {
+                rasterizer.triangle([triangle[0], triangle[1], triangle[2]]);
+            }
+        }
+        PrimitiveType::Lines => {
+            for line in vertices.chunks_exact(2) {
+                rasterizer.line(line[0], line[1]);
+            }
+        }
+        PrimitiveType::Points => {
+            for point in vertices {
+                rasterizer.point(*point);
+            }
+        }
+    }
+}
+
+/// Clip a polygon against the `w > 0` and near planes
+fn clip_polygon(polygon: Vec<ClipVertex>) -> Vec<ClipVertex> {
+    const EPSILON: f32 = 1e-5;
+    let planes: [fn(&Vec4) -> f32; 2] = [|p| p.w - EPSILON, |p| p.z + p.w];
+
+    let mut polygon = polygon;
+    for plane in planes {
+        if polygon.iter().all(|v| plane(&v.position) >= 0.0) {
+            continue;
+        }
+
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for (ix, current) in polygon.iter().enumerate() {
+            let next = &polygon[(ix + 1) % polygon.len()];
+            let (dc, dn) = (plane(&current.position), plane(&next.position));
+
+            if dc >= 0.0 {
+                clipped.push(*current);
+            }
+            if (dc >= 0.0) != (dn >= 0.0) {
+                clipped.push(current.lerp(next, dc / (dc - dn)));
+            }
+        }
+        polygon = clipped;
+    }
+    polygon
+}
+
+impl Rasterizer<'_> {
+    fn to_window(&self, vertex: ClipVertex) -> WindowVertex {
+        let inv_w = 1.0 / vertex.position.w;
+        let ndc = vertex.position.truncate() * inv_w;
+        let (vx, vy, vw, vh) = self.viewport;
+
+        WindowVertex {
+            x: vx as f32 + (ndc.x + 1.0) * 0.5 * vw as f32,
+            y: vy as f32 + (ndc.y + 1.0) * 0.5 * vh as f32,
+            z: (ndc.z + 1.0) * 0.5,
+            inv_w,
+            vertex,
+        }
+    }
+
+    fn triangle(&mut self, triangle: [ClipVertex; 3]) {
+        let polygon = clip_polygon(triangle.to_vec());
+        if polygon.len() < 3 {
+            return;
+        }
+
+        let polygon: Vec<WindowVertex> = polygon.into_iter().map(|v| self.to_window(v)).collect();
+        for ix in 1..polygon.len() - 1 {
+            self.window_triangle(polygon[0], polygon[ix], polygon[ix + 1]);
+        }
+    }
+
+    fn window_triangle(&mut self, v0: WindowVertex, mut v1: WindowVertex, mut v2: WindowVertex) {
+        let edge = |a: &WindowVertex, b: &WindowVertex, x: f32, y: f32| {
+            (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+        };
+
+        let area = edge(&v0, &v1, v2.x, v2.y);
+        if area == 0.0 || !area.is_finite() {
+            return;
+        }
+
+        let counter_clockwise = area > 0.0;
+        let front = counter_clockwise
+            == (self.pipeline.params.front_face_order == FrontFaceOrder::CounterClockwise);
+        match self.pipeline.params.cull_face {
+            CullFace::Back if !front => return,
+            CullFace::Front if front => return,
+            _ => {}
+        }
+        self.front_facing = front;
+
+        // Make the winding counter clockwise, so all edge functions are positive inside
+        if !counter_clockwise {
+            std::mem::swap(&mut v1, &mut v2);
+        }
+        let area = area.abs();
+
+        // Top-left fill rule, in y-up window coordinates
+        let top_left = |a: &WindowVertex, b: &WindowVertex| {
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            dy < 0.0 || (dy == 0.0 && dx < 0.0)
+        };
+        let inside = |w: f32, top_left: bool| w > 0.0 || (w == 0.0 && top_left);
+        let edges = [top_left(&v1, &v2), top_left(&v2, &v0), top_left(&v0, &v1)];
+
+        let min_x = (v0.x.min(v1.x).min(v2.x).floor() as i32).max(self.bounds.0);
+        let min_y = (v0.y.min(v1.y).min(v2.y).floor() as i32).max(self.bounds.1);
+        let max_x = (v0.x.max(v1.x).max(v2.x).ceil() as i32).min(self.bounds.2);
+        let max_y = (v0.y.max(v1.y).max(v2.y).ceil() as i32).min(self.bounds.3);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let w0 = edge(&v1, &v2, px, py);
+                let w1 = edge(&v2, &v0, px, py);
+                let w2 = edge(&v0, &v1, px, py);
+
+                if !(inside(w0, edges[0]) && inside(w1, edges[1]) && inside(w2, edges[2])) {
+                    continue;
+                }
+
+                let (b0, b1, b2) = (w0 / area, w1 / area, w2 / area);
+                let z = b0 * v0.z + b1 * v1.z + b2 * v2.z;
+
+                // Perspective correct interpolation
+                let (p0, p1, p2) = (b0 * v0.inv_w, b1 * v1.inv_w, b2 * v2.inv_w);
+                let sum = p0 + p1 + p2;
+                let (p0, p1, p2) = (p0 / sum, p1 / sum, p2 / sum);
+
+                let uv = v0.vertex.uv * p0 + v1.vertex.uv * p1 + v2.vertex.uv * p2;
+                let color = v0.vertex.color * p0 + v1.vertex.color * p1 + v2.vertex.color * p2;
+
+                self.fragment(x, y, z, uv, color);
+            }
+        }
+    }
+
+    fn line(&mut self, a: ClipVertex, b: ClipVertex) {
+        let line = clip_polygon(vec![a, b]);
+        let [a, b] = match line.len() {
+            2 => [line[0], line[1]],
+            // Clipping a 2-vertex "polygon" may produce duplicated vertices
+            3 | 4 => [line[0], line[line.len() - 1]],
+            _ => return,
+        };
+        let (a, b) = (self.to_window(a), self.to_window(b));
+
+        let steps = (b.x - a.x).abs().max((b.y - a.y).abs()).ceil().max(1.0) as usize;
+        for step in 0..steps {
+            let t = (step as f32 + 0.5) / steps as f32;
+            let x = a.x + (b.x - a.x) * t;
+            let y = a.y + (b.y - a.y) * t;
+
+            let (pa, pb) = ((1.0 - t) * a.inv_w, t * b.inv_w);
+            let (pa, pb) = (pa / (pa + pb), pb / (pa + pb));
+
+            self.bounded_fragment(
+                x.floor() as i32,
+                y.floor() as i32,
+                a.z + (b.z - a.z) * t,
+                a.vertex.uv * pa + b.vertex.uv * pb,
+                a.vertex.color * pa + b.vertex.color * pb,
+            );
+        }
+    }
+
+    fn point(&mut self, point: ClipVertex) {
+        if point.position.w <= 0.0 || point.position.z < -point.position.w {
+            return;
+        }
+        let point = self.to_window(point);
+        self.bounded_fragment(
+            point.x.floor() as i32,
+            point.y.floor() as i32,
+            point.z,
+            point.vertex.uv,
+            point.vertex.color,
+        );
+    }
+
+    fn bounded_fragment(&mut self, x: i32, y: i32, z: f32, uv: Vec2, color: Vec4) {
+        let (min_x, min_y, max_x, max_y) = self.bounds;
+        if x >= min_x && y >= min_y && x < max_x && y < max_y {
+            self.fragment(x, y, z, uv, color);
+        }
+    }
+
+    /// Shade, test and write a single fragment
+    fn fragment(&mut self, x: i32, y: i32, z: f32, uv: Vec2, color: Vec4) {
+        if !(0.0..=1.0).contains(&z) {
+            return;
+        }
+
+        let params = &self.pipeline.params;
+        let ix = y as usize * self.attachments.size().0 as usize + x as usize;
+
+        let stencil = params.stencil_test.map(|stencil| {
+            if self.front_facing {
+                stencil.front
+            } else {
+                stencil.back
+            }
+        });
+
+        if let Some(depth) = &mut self.attachments.depth {
+            if let Some(stencil) = &stencil {
+                if !stencil_compare(stencil, depth.stencil[ix]) {
+                    stencil_apply(stencil, stencil.fail_op, &mut depth.stencil[ix]);
+                    return;
+                }
+            }
+
+            // miniquad only enables depth testing together with depth writes
+            if params.depth_write {
+                if !compare(params.depth_test, z, depth.depth[ix]) {
+                    if let Some(stencil) = &stencil {
+                        stencil_apply(stencil, stencil.depth_fail_op, &mut depth.stencil[ix]);
+                    }
+                    return;
+                }
+                depth.depth[ix] = z;
+            }
+
+            if let Some(stencil) = &stencil {
+                stencil_apply(stencil, stencil.pass_op, &mut depth.stencil[ix]);
+            }
+        }
+
+        let texel = self
+            .texture
+            .map(|texture| sample(texture, uv))
+            .unwrap_or(Vec4::ONE);
+        let src = (color * texel).clamp(Vec4::ZERO, Vec4::ONE);
+
+        for (_, target) in &mut self.attachments.colors {
+            let dst = Vec4::from_array(target.color[ix].map(|c| c as f32 / 255.0));
+
+            let mut result = src;
+            if let (Some(color_blend), Some(alpha_blend)) =
+                (self.pipeline.color_blend, self.pipeline.alpha_blend)
+            {
+                for channel in 0..3 {
+                    result[channel] = color_blend.apply(src, dst, channel);
+                }
+                result.w = alpha_blend.apply(src, dst, 3);
+            }
+
+            let (r, g, b, a) = params.color_write;
+            for (channel, write) in [r, g, b, a].into_iter().enumerate() {
+                if write {
+                    target.color[ix][channel] =
+                        (result[channel].clamp(0.0, 1.0) * 255.0).round() as u8;
+                }
+            }
+        }
+    }
+}
+
+fn compare(comparison: Comparison, value: f32, stored: f32) -> bool {
+    match comparison {
+        Comparison::Never => false,
+        Comparison::Less => value < stored,
+        Comparison::LessOrEqual => value <= stored,
+        Comparison::Greater => value > stored,
+        Comparison::GreaterOrEqual => value >= stored,
+        Comparison::Equal => value == stored,
+        Comparison::NotEqual => value != stored,
+        Comparison::Always => true,
+    }
+}
+
+/// The stencil test: `(ref & mask) func (stencil & mask)`
+fn stencil_compare(state: &StencilFaceState, stored: u8) -> bool {
+    let value = state.test_ref as u32 & state.test_mask;
+    let stored = stored as u32 & state.test_mask;
+
+    match state.test_func {
+        CompareFunc::Always => true,
+        CompareFunc::Never => false,
+        CompareFunc::Less => value < stored,
+        CompareFunc::Equal => value == stored,
+        CompareFunc::LessOrEqual => value <= stored,
+        CompareFunc::Greater => value > stored,
+        CompareFunc::NotEqual => value != stored,
+        CompareFunc::GreaterOrEqual => value >= stored,
+    }
+}
+
+/// Update a stencil value with an operation, respecting the write mask
+fn stencil_apply(state: &StencilFaceState, op: StencilOp, stored: &mut u8) {
+    let value = match op {
+        StencilOp::Keep => *stored,
+        StencilOp::Zero => 0,
+        StencilOp::Replace => state.test_ref as u8,
+        StencilOp::IncrementClamp => stored.saturating_add(1),
+        StencilOp::DecrementClamp => stored.saturating_sub(1),
+        StencilOp::Invert => !*stored,
+        StencilOp::IncrementWrap => stored.wrapping_add(1),
+        StencilOp::DecrementWrap => stored.wrapping_sub(1),
+    };
+
+    let mask = state.write_mask as u8;
+    *stored = (*stored & !mask) | (value & mask);
+}
+
+fn wrap(coord: i32, size: u32, wrap: TextureWrap) -> u32 {
+    let size = size as i32;
+    let coord = match wrap {
+        TextureWrap::Clamp => coord.clamp(0, size - 1),
+        TextureWrap::Repeat => coord.rem_euclid(size),
+        TextureWrap::Mirror => {
+            let period = coord.rem_euclid(size * 2);
+            if period < size {
+                period
+            } else {
+                size * 2 - 1 - period
+            }
+        }
+    };
+    coord as u32
+}
+
+/// Sample a texture at normalized coordinates
+fn sample(texture: &SoftTexture, uv: Vec2) -> Vec4 {
+    let params = &texture.params;
+    if params.width == 0 || params.height == 0 {
+        return Vec4::ZERO;
+    }
+
+    let fetch = |x: i32, y: i32| {
+        Vec4::from_array(texture.texel(
+            wrap(x, params.width, params.wrap),
+            wrap(y, params.height, params.wrap),
+        ))
+    };
+
+    let x = uv.x * params.width as f32;
+    let y = uv.y * params.height as f32;
+
+    match params.mag_filter {
+        FilterMode::Nearest => fetch(x.floor() as i32, y.floor() as i32),
+        FilterMode::Linear => {
+            let (x, y) = (x - 0.5, y - 0.5);
+            let (x0, y0) = (x.floor(), y.floor());
+            let (tx, ty) = (x - x0, y - y0);
+            let (x0, y0) = (x0 as i32, y0 as i32);
+
+            let top = fetch(x0, y0).lerp(fetch(x0 + 1, y0), tx);
+            let bottom = fetch(x0, y0 + 1).lerp(fetch(x0 + 1, y0 + 1), tx);
+            top.lerp(bottom, ty)
+        }
+    }
+}
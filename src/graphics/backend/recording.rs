@@ -0,0 +1,455 @@
+use std::{cell::RefCell, fmt};
+
+use miniquad::*;
+
+use super::{handle_index, BufferData, SoftwareBackend};
+
+/// A single call, captured by [RecordingBackend].
+///
+/// Resource handles are stored as plain indices, so commands can be compared, printed and, with
+/// the `serde` feature, serialized.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Command {
+    NewBuffer {
+        buffer: usize,
+        #[cfg_attr(feature = "serde", serde(with = "BufferTypeDef"))]
+        buffer_type: BufferType,
+        #[cfg_attr(feature = "serde", serde(with = "BufferUsageDef"))]
+        usage: BufferUsage,
+        size: usize,
+    },
+    BufferUpdate {
+        buffer: usize,
+        bytes: Vec<u8>,
+        element_size: usize,
+    },
+    BeginPass {
+        /// [None] for the default pass
+        pass: Option<usize>,
+        clear: bool,
+    },
+    EndPass,
+    ApplyPipeline {
+        pipeline: usize,
+    },
+    ApplyBindings {
+        vertex_buffers: Vec<usize>,
+        index_buffer: usize,
+        /// See [RecordingBackend::texture_index]
+        textures: Vec<usize>,
+    },
+    ApplyViewport(i32, i32, i32, i32),
+    ApplyScissorRect(i32, i32, i32, i32),
+    ApplyUniforms {
+        bytes: Vec<u8>,
+    },
+    Draw {
+        base_element: i32,
+        num_elements: i32,
+        num_instances: i32,
+    },
+    CommitFrame,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(remote = "BufferType")]
+enum BufferTypeDef {
+    VertexBuffer,
+    IndexBuffer,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(remote = "BufferUsage")]
+enum BufferUsageDef {
+    Immutable,
+    Dynamic,
+    Stream,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::NewBuffer {
+                buffer,
+                buffer_type,
+                usage,
+                size,
+            } => write!(f, "new_buffer {buffer} {buffer_type:?} {usage:?} {size}"),
+            Command::BufferUpdate {
+                buffer,
+                bytes,
+                element_size,
+            } => write!(
+                f,
+                "buffer_update {buffer} {} bytes, element size {element_size}",
+                bytes.len()
+            ),
+            Command::BeginPass { pass: None, clear } => write!(f, "begin_pass default clear={clear}"),
+            Command::BeginPass {
+                pass: Some(pass),
+                clear,
+            } => write!(f, "begin_pass {pass} clear={clear}"),
+            Command::EndPass => write!(f, "end_pass"),
+            Command::ApplyPipeline { pipeline } => write!(f, "apply_pipeline {pipeline}"),
+            Command::ApplyBindings {
+                vertex_buffers,
+                index_buffer,
+                textures,
+            } => write!(
+                f,
+                "apply_bindings vertex={vertex_buffers:?} index={index_buffer} textures={textures:?}"
+            ),
+            Command::ApplyViewport(x, y, w, h) => write!(f, "apply_viewport {x} {y} {w} {h}"),
+            Command::ApplyScissorRect(x, y, w, h) => {
+                write!(f, "apply_scissor_rect {x} {y} {w} {h}")
+            }
+            Command::ApplyUniforms { bytes } => write!(f, "apply_uniforms {} bytes", bytes.len()),
+            Command::Draw {
+                base_element,
+                num_elements,
+                num_instances,
+            } => write!(f, "draw {base_element} {num_elements} {num_instances}"),
+            Command::CommitFrame => write!(f, "commit_frame"),
+        }
+    }
+}
+
+/// A [RenderingBackend] that records every buffer upload, pass, pipeline, binding, uniform and draw
+/// call before forwarding it to an inner backend.
+///
+/// This allows testing the structure of the produced command stream (for example, that batching
+/// works), instead of the pixels. By default it wraps a [SoftwareBackend], so it doesn't need a
+/// window. The log can be printed with [RecordingBackend::dump], one command per line.
+pub struct RecordingBackend<B: RenderingBackend = SoftwareBackend> {
+    inner: B,
+    // `draw` takes `&self`, so commands need interior mutability
+    commands: RefCell<Vec<Command>>,
+    textures: Vec<TextureId>,
+}
+
+impl<B: RenderingBackend> RecordingBackend<B> {
+    pub fn new(inner: B) -> Self {
+        Self {
+            inner,
+            commands: RefCell::new(Vec::new()),
+            textures: Vec::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut B {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// All commands, recorded since creation or the last [RecordingBackend::take_commands] call
+    pub fn commands(&mut self) -> &[Command] {
+        self.commands.get_mut()
+    }
+
+    /// Take all recorded commands, leaving the log empty
+    pub fn take_commands(&mut self) -> Vec<Command> {
+        std::mem::take(self.commands.get_mut())
+    }
+
+    pub fn clear_commands(&mut self) {
+        self.commands.get_mut().clear();
+    }
+
+    /// Amount of recorded [Command::Draw] commands
+    pub fn draw_calls(&mut self) -> usize {
+        self.commands()
+            .iter()
+            .filter(|command| matches!(command, Command::Draw { .. }))
+            .count()
+    }
+
+    /// The recorded log as text, one command per line
+    pub fn dump(&mut self) -> String {
+        self.commands()
+            .iter()
+            .map(|command| format!("{command}\n"))
+            .collect()
+    }
+
+    /// The index `texture` is recorded with.
+    ///
+    /// Textures are numbered in the order they're created (or first bound, if they were created
+    /// elsewhere), since miniquad's [TextureId] can't be turned into a plain index.
+    pub fn texture_index(&mut self, texture: TextureId) -> usize {
+        match self.textures.iter().position(|id| *id == texture) {
+            Some(index) => index,
+            None => {
+                self.textures.push(texture);
+                self.textures.len() - 1
+            }
+        }
+    }
+
+    fn record(&self, command: Command) {
+        self.commands.borrow_mut().push(command);
+    }
+}
+
+impl<B: RenderingBackend> RenderingBackend for RecordingBackend<B> {
+    fn info(&self) -> ContextInfo {
+        self.inner.info()
+    }
+
+    fn new_shader(
+        &mut self,
+        shader: ShaderSource,
+        meta: ShaderMeta,
+    ) -> Result<ShaderId, ShaderError> {
+        self.inner.new_shader(shader, meta)
+    }
+
+    fn new_texture(
+        &mut self,
+        access: TextureAccess,
+        data: TextureSource,
+        params: TextureParams,
+    ) -> TextureId {
+        let texture = self.inner.new_texture(access, data, params);
+        self.texture_index(texture);
+        texture
+    }
+
+    fn texture_params(&self, texture: TextureId) -> TextureParams {
+        self.inner.texture_params(texture)
+    }
+
+    unsafe fn texture_raw_id(&self, texture: TextureId) -> RawId {
+        self.inner.texture_raw_id(texture)
+    }
+
+    fn texture_set_min_filter(
+        &mut self,
+        texture: TextureId,
+        filter: FilterMode,
+        mipmap_filter: MipmapFilterMode,
+    ) {
+        self.inner
+            .texture_set_min_filter(texture, filter, mipmap_filter)
+    }
+
+    fn texture_set_mag_filter(&mut self, texture: TextureId, filter: FilterMode) {
+        self.inner.texture_set_mag_filter(texture, filter)
+    }
+
+    fn texture_set_wrap(&mut self, texture: TextureId, wrap_x: TextureWrap, wrap_y: TextureWrap) {
+        self.inner.texture_set_wrap(texture, wrap_x, wrap_y)
+    }
+
+    fn texture_generate_mipmaps(&mut self, texture: TextureId) {
+        self.inner.texture_generate_mipmaps(texture)
+    }
+
+    fn texture_resize(
+        &mut self,
+        texture: TextureId,
+        width: u32,
+        height: u32,
+        bytes: Option<&[u8]>,
+    ) {
+        self.inner.texture_resize(texture, width, height, bytes)
+    }
+
+    fn texture_read_pixels(&mut self, texture: TextureId, bytes: &mut [u8]) {
+        self.inner.texture_read_pixels(texture, bytes)
+    }
+
+    fn texture_update_part(
+        &mut self,
+        texture: TextureId,
+        x_offset: i32,
+        y_offset: i32,
+        width: i32,
+        height: i32,
+        bytes: &[u8],
+    ) {
+        self.inner
+            .texture_update_part(texture, x_offset, y_offset, width, height, bytes)
+    }
+
+    fn new_render_pass_mrt(
+        &mut self,
+        color_img: &[TextureId],
+        resolve_img: Option<&[TextureId]>,
+        depth_img: Option<TextureId>,
+    ) -> RenderPass {
+        self.inner
+            .new_render_pass_mrt(color_img, resolve_img, depth_img)
+    }
+
+    fn render_pass_color_attachments(&self, render_pass: RenderPass) -> &[TextureId] {
+        self.inner.render_pass_color_attachments(render_pass)
+    }
+
+    fn delete_render_pass(&mut self, render_pass: RenderPass) {
+        self.inner.delete_render_pass(render_pass)
+    }
+
+    fn new_pipeline(
+        &mut self,
+        buffer_layout: &[BufferLayout],
+        attributes: &[VertexAttribute],
+        shader: ShaderId,
+        params: PipelineParams,
+    ) -> Pipeline {
+        self.inner
+            .new_pipeline(buffer_layout, attributes, shader, params)
+    }
+
+    fn apply_pipeline(&mut self, pipeline: &Pipeline) {
+        self.record(Command::ApplyPipeline {
+            pipeline: handle_index(*pipeline),
+        });
+        self.inner.apply_pipeline(pipeline)
+    }
+
+    fn delete_pipeline(&mut self, pipeline: Pipeline) {
+        self.inner.delete_pipeline(pipeline)
+    }
+
+    fn new_buffer(
+        &mut self,
+        buffer_type: BufferType,
+        usage: BufferUsage,
+        data: BufferSource,
+    ) -> BufferId {
+        let size = match (&data, BufferData::from_source(&data)) {
+            (_, Some(data)) => data.bytes.len(),
+            (BufferSource::Empty { size, .. }, None) => *size,
+            _ => unreachable!(),
+        };
+
+        let buffer = self.inner.new_buffer(buffer_type, usage, data);
+        self.record(Command::NewBuffer {
+            buffer: handle_index(buffer),
+            buffer_type,
+            usage,
+            size,
+        });
+        buffer
+    }
+
+    fn buffer_update(&mut self, buffer: BufferId, data: BufferSource) {
+        if let Some(bytes) = BufferData::from_source(&data) {
+            self.record(Command::BufferUpdate {
+                buffer: handle_index(buffer),
+                bytes: bytes.bytes.to_vec(),
+                element_size: bytes.element_size,
+            });
+        }
+        self.inner.buffer_update(buffer, data)
+    }
+
+    fn buffer_size(&mut self, buffer: BufferId) -> usize {
+        self.inner.buffer_size(buffer)
+    }
+
+    fn delete_buffer(&mut self, buffer: BufferId) {
+        self.inner.delete_buffer(buffer)
+    }
+
+    fn delete_texture(&mut self, texture: TextureId) {
+        self.inner.delete_texture(texture)
+    }
+
+    fn delete_shader(&mut self, program: ShaderId) {
+        self.inner.delete_shader(program)
+    }
+
+    fn apply_viewport(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        self.record(Command::ApplyViewport(x, y, w, h));
+        self.inner.apply_viewport(x, y, w, h)
+    }
+
+    fn apply_scissor_rect(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        self.record(Command::ApplyScissorRect(x, y, w, h));
+        self.inner.apply_scissor_rect(x, y, w, h)
+    }
+
+    fn apply_bindings_from_slice(
+        &mut self,
+        vertex_buffers: &[BufferId],
+        index_buffer: BufferId,
+        textures: &[TextureId],
+    ) {
+        let texture_indices = textures
+            .iter()
+            .map(|texture| self.texture_index(*texture))
+            .collect();
+        self.record(Command::ApplyBindings {
+            vertex_buffers: vertex_buffers.iter().copied().map(handle_index).collect(),
+            index_buffer: handle_index(index_buffer),
+            textures: texture_indices,
+        });
+        self.inner
+            .apply_bindings_from_slice(vertex_buffers, index_buffer, textures)
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn apply_uniforms_from_bytes(&mut self, uniform_ptr: *const u8, size: usize) {
+        // SAFETY: the caller is expected to pass a valid pointer, the same as with miniquad's backends
+        let bytes = unsafe { std::slice::from_raw_parts(uniform_ptr, size) };
+        self.record(Command::ApplyUniforms {
+            bytes: bytes.to_vec(),
+        });
+        self.inner.apply_uniforms_from_bytes(uniform_ptr, size)
+    }
+
+    fn clear(
+        &mut self,
+        color: Option<(f32, f32, f32, f32)>,
+        depth: Option<f32>,
+        stencil: Option<i32>,
+    ) {
+        self.inner.clear(color, depth, stencil)
+    }
+
+    fn begin_default_pass(&mut self, action: PassAction) {
+        self.record(Command::BeginPass {
+            pass: None,
+            clear: matches!(action, PassAction::Clear { .. }),
+        });
+        self.inner.begin_default_pass(action)
+    }
+
+    fn begin_pass(&mut self, pass: Option<RenderPass>, action: PassAction) {
+        self.record(Command::BeginPass {
+            pass: pass.map(handle_index),
+            clear: matches!(action, PassAction::Clear { .. }),
+        });
+        self.inner.begin_pass(pass, action)
+    }
+
+    fn end_render_pass(&mut self) {
+        self.record(Command::EndPass);
+        self.inner.end_render_pass()
+    }
+
+    fn commit_frame(&mut self) {
+        self.record(Command::CommitFrame);
+        self.inner.commit_frame()
+    }
+
+    fn draw(&self, base_element: i32, num_elements: i32, num_instances: i32) {
+        self.record(Command::Draw {
+            base_element,
+            num_elements,
+            num_instances,
+        });
+        self.inner.draw(base_element, num_elements, num_instances)
+    }
+}
@@ -0,0 +1,744 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use miniquad::*;
+
+use crate::texture::Image;
+
+use super::{
+    handle, handle_index,
+    raster::{self, Attachments, ClipVertex, SoftPipeline},
+    BufferData,
+};
+
+/// A texture, stored in CPU memory.
+///
+/// Color formats are always stored as RGBA8, while depth formats are stored as normalized floats.
+/// Depth textures also have an 8-bit stencil plane, like the depth textures of the Metal backend.
+pub(super) struct SoftTexture {
+    pub params: TextureParams,
+    pub raw: u32,
+    pub color: Vec<[u8; 4]>,
+    pub depth: Vec<f32>,
+    pub stencil: Vec<u8>,
+}
+
+impl SoftTexture {
+    fn new(params: TextureParams, raw: u32) -> Self {
+        let mut texture = Self {
+            params,
+            raw,
+            color: Vec::new(),
+            depth: Vec::new(),
+            stencil: Vec::new(),
+        };
+        texture.resize(params.width, params.height);
+        texture
+    }
+
+    pub fn is_depth(&self) -> bool {
+        matches!(
+            self.params.format,
+            TextureFormat::Depth | TextureFormat::Depth32
+        )
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        self.params.width = width;
+        self.params.height = height;
+
+        let len = width as usize * height as usize;
+        if self.is_depth() {
+            self.depth = vec![1.0; len];
+            self.stencil = vec![0; len];
+        } else {
+            self.color = vec![[0, 0, 0, 0]; len];
+        }
+    }
+
+    /// Write texels from `bytes` (in the texture's format) into a region of this texture
+    fn write(&mut self, x: i32, y: i32, width: i32, height: i32, bytes: &[u8]) {
+        let format = self.params.format;
+        let texel_size = format.size(1, 1) as usize;
+
+        for row in 0..height {
+            for column in 0..width {
+                let (tx, ty) = (x + column, y + row);
+                if tx < 0
+                    || ty < 0
+                    || tx >= self.params.width as i32
+                    || ty >= self.params.height as i32
+                {
+                    continue;
+                }
+
+                let src = (row * width + column) as usize * texel_size;
+                let Some(texel) = bytes.get(src..src + texel_size) else {
+                    return;
+                };
+                let dst = ty as usize * self.params.width as usize + tx as usize;
+
+                match format {
+                    TextureFormat::RGBA8 => {
+                        self.color[dst] = [texel[0], texel[1], texel[2], texel[3]]
+                    }
+                    TextureFormat::RGB8 => self.color[dst] = [texel[0], texel[1], texel[2], 255],
+                    TextureFormat::Alpha => self.color[dst] = [texel[0], 0, 0, 255],
+                    TextureFormat::RGBA16F => {
+                        let channel = |i: usize| {
+                            let half = u16::from_le_bytes([texel[i * 2], texel[i * 2 + 1]]);
+                            (half_to_f32(half).clamp(0.0, 1.0) * 255.0).round() as u8
+                        };
+                        self.color[dst] = [channel(0), channel(1), channel(2), channel(3)];
+                    }
+                    TextureFormat::Depth => {
+                        self.depth[dst] =
+                            u16::from_le_bytes([texel[0], texel[1]]) as f32 / u16::MAX as f32
+                    }
+                    TextureFormat::Depth32 => {
+                        self.depth[dst] =
+                            f32::from_le_bytes([texel[0], texel[1], texel[2], texel[3]])
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read the whole texture into `bytes`, in the texture's format
+    fn read(&self, bytes: &mut [u8]) {
+        let texel_size = self.params.format.size(1, 1) as usize;
+        let len = self.params.width as usize * self.params.height as usize;
+
+        for (ix, texel) in bytes.chunks_exact_mut(texel_size).take(len).enumerate() {
+            match self.params.format {
+                TextureFormat::RGBA8 => texel.copy_from_slice(&self.color[ix]),
+                TextureFormat::RGB8 => texel.copy_from_slice(&self.color[ix][..3]),
+                TextureFormat::Alpha => texel[0] = self.color[ix][0],
+                TextureFormat::RGBA16F => {
+                    for (channel, value) in self.color[ix].iter().enumerate() {
+                        let half = f32_to_half(*value as f32 / 255.0).to_le_bytes();
+                        texel[channel * 2..channel * 2 + 2].copy_from_slice(&half);
+                    }
+                }
+                TextureFormat::Depth => {
+                    let depth = (self.depth[ix].clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
+                    texel.copy_from_slice(&depth.to_le_bytes());
+                }
+                TextureFormat::Depth32 => texel.copy_from_slice(&self.depth[ix].to_le_bytes()),
+            }
+        }
+    }
+
+    /// Fetch a single texel. Depth textures return their depth in all color channels.
+    pub fn texel(&self, x: u32, y: u32) -> [f32; 4] {
+        let ix = y as usize * self.params.width as usize + x as usize;
+        if self.is_depth() {
+            let depth = self.depth[ix];
+            [depth, depth, depth, 1.0]
+        } else {
+            self.color[ix].map(|c| c as f32 / 255.0)
+        }
+    }
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 => sign * f32::INFINITY,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn f32_to_half(value: f32) -> u16 {
+    // Values here are always normalized colors, so there's no need to handle the special cases
+    if value <= 0.0 {
+        return 0;
+    }
+    let exponent = value.log2().floor() as i32;
+    let mantissa = ((value / 2f32.powi(exponent) - 1.0) * 1024.0).round() as u16;
+
+    (((exponent + 15) as u16) << 10) | (mantissa & 0x3ff)
+}
+
+struct SoftBuffer {
+    bytes: Vec<u8>,
+    element_size: usize,
+}
+
+struct SoftPass {
+    colors: Vec<TextureId>,
+    resolves: Option<Vec<TextureId>>,
+    depth: Option<TextureId>,
+}
+
+#[derive(Default)]
+struct State {
+    pipeline: Option<Pipeline>,
+    pass: Option<RenderPass>,
+    viewport: (i32, i32, i32, i32),
+    scissor: (i32, i32, i32, i32),
+    vertex_buffers: Vec<BufferId>,
+    index_buffer: Option<BufferId>,
+    images: Vec<TextureId>,
+    uniforms: Vec<u8>,
+}
+
+/// A [RenderingBackend] that rasterizes everything on the CPU.
+///
+/// Since it can't run GLSL or Metal, every shader is executed as the default macroquad shader:
+/// vertices are transformed by the `Projection` and `Model` uniforms, and fragments are colored by
+/// `color0 * texture2D(Texture, texcoord)`. The default instanced shader is emulated as well, if the
+/// pipeline has its per-instance attributes. Everything else (textures, render passes, depth and
+/// stencil testing, blending, culling, scissor and viewport) follows OpenGL semantics, so the images
+/// it produces can be compared against the ones produced on a GPU.
+///
+/// The default framebuffer has a fixed size, which is passed in [SoftwareBackend::new].
+/// Its content can be retrieved with [SoftwareBackend::framebuffer].
+pub struct SoftwareBackend {
+    textures: RefCell<HashMap<TextureId, SoftTexture>>,
+    next_texture: u32,
+    default_color: TextureId,
+    default_depth: TextureId,
+
+    shaders: Vec<Option<ShaderMeta>>,
+    pipelines: Vec<Option<SoftPipeline>>,
+    buffers: Vec<Option<SoftBuffer>>,
+    passes: Vec<Option<SoftPass>>,
+
+    state: State,
+}
+
+impl SoftwareBackend {
+    /// The `gl_version_string` reported by [RenderingBackend::info]
+    pub const VERSION: &'static str = "macroquad software rasterizer";
+
+    /// Create a new backend with a default framebuffer of the specified size
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut backend = Self {
+            textures: RefCell::new(HashMap::new()),
+            next_texture: 1,
+            default_color: TextureId::from_raw_id(RawId::OpenGl(0)),
+            default_depth: TextureId::from_raw_id(RawId::OpenGl(0)),
+            shaders: Vec::new(),
+            pipelines: Vec::new(),
+            buffers: Vec::new(),
+            passes: Vec::new(),
+            state: State::default(),
+        };
+
+        backend.default_color = backend.add_texture(TextureParams {
+            width,
+            height,
+            ..Default::default()
+        });
+        backend.default_depth = backend.add_texture(TextureParams {
+            width,
+            height,
+            format: TextureFormat::Depth32,
+            ..Default::default()
+        });
+
+        backend
+    }
+
+    /// Get the size of the default framebuffer
+    pub fn framebuffer_size(&self) -> (u32, u32) {
+        self.texture_size(self.default_color)
+    }
+
+    /// Resize the default framebuffer. This clears its content
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let mut textures = self.textures.borrow_mut();
+        for texture in [self.default_color, self.default_depth] {
+            textures.get_mut(&texture).unwrap().resize(width, height);
+        }
+    }
+
+    /// Get the content of the default framebuffer as an [Image].
+    ///
+    /// Unlike OpenGL's framebuffer (which starts from the bottom), the returned image starts from
+    /// the top row, so it can be compared to images loaded from files directly.
+    pub fn framebuffer(&self) -> Image {
+        let textures = self.textures.borrow();
+        let texture = &textures[&self.default_color];
+        let (width, height) = (
+            texture.params.width as usize,
+            texture.params.height as usize,
+        );
+
+        let mut bytes = Vec::with_capacity(width * height * 4);
+        for row in texture.color.chunks_exact(width.max(1)).rev().take(height) {
+            bytes.extend(row.iter().flatten());
+        }
+
+        Image {
+            bytes,
+            width: width as _,
+            height: height as _,
+        }
+    }
+
+    fn add_texture(&mut self, params: TextureParams) -> TextureId {
+        let raw = self.next_texture;
+        self.next_texture += 1;
+
+        let id = TextureId::from_raw_id(RawId::OpenGl(raw));
+        self.textures
+            .borrow_mut()
+            .insert(id, SoftTexture::new(params, raw));
+        id
+    }
+
+    fn with_texture<T>(&self, texture: TextureId, f: impl FnOnce(&SoftTexture) -> T) -> T {
+        let textures = self.textures.borrow();
+        f(textures
+            .get(&texture)
+            .expect("Trying to use a deleted texture"))
+    }
+
+    fn with_texture_mut<T>(&self, texture: TextureId, f: impl FnOnce(&mut SoftTexture) -> T) -> T {
+        let mut textures = self.textures.borrow_mut();
+        f(textures
+            .get_mut(&texture)
+            .expect("Trying to use a deleted texture"))
+    }
+
+    fn buffer(&self, buffer: BufferId) -> &SoftBuffer {
+        self.buffers[handle_index(buffer)]
+            .as_ref()
+            .expect("Trying to use a deleted buffer")
+    }
+
+    /// Color and depth attachments of the current pass
+    fn pass_attachments(&self, pass: Option<RenderPass>) -> (Vec<TextureId>, Option<TextureId>) {
+        match pass {
+            None => (vec![self.default_color], Some(self.default_depth)),
+            Some(pass) => {
+                let pass = self.passes[handle_index(pass)]
+                    .as_ref()
+                    .expect("Trying to use a deleted render pass");
+                (pass.colors.clone(), pass.depth)
+            }
+        }
+    }
+
+    /// Fetch and transform the vertex with the specified index
+    fn shade_vertex(&self, pipeline: &SoftPipeline, index: usize, instance: usize) -> ClipVertex {
+        let buffers = &self.state.vertex_buffers;
+        let fetch = |attribute: &raster::SoftAttribute| {
+            let buffer = self.buffer(*buffers.get(attribute.buffer_index)?);
+            let element = if attribute.per_instance {
+                instance
+            } else {
+                index
+            };
+            let start = element * attribute.stride + attribute.offset;
+            buffer
+                .bytes
+                .get(start..start + attribute.format.size_bytes() as usize)
+        };
+
+        raster::shade_vertex(pipeline, &self.state.uniforms, fetch)
+    }
+}
+
+impl RenderingBackend for SoftwareBackend {
+    fn info(&self) -> ContextInfo {
+        ContextInfo {
+            backend: Backend::OpenGl,
+            gl_version_string: Self::VERSION.to_string(),
+            glsl_support: GlslSupport {
+                v100: true,
+                ..Default::default()
+            },
+            features: Features::default(),
+        }
+    }
+
+    fn new_shader(
+        &mut self,
+        _shader: ShaderSource,
+        meta: ShaderMeta,
+    ) -> Result<ShaderId, ShaderError> {
+        self.shaders.push(Some(meta));
+        Ok(handle(self.shaders.len() - 1))
+    }
+
+    fn new_texture(
+        &mut self,
+        _access: TextureAccess,
+        data: TextureSource,
+        params: TextureParams,
+    ) -> TextureId {
+        let texture = self.add_texture(params);
+
+        let bytes = match data {
+            TextureSource::Empty => None,
+            TextureSource::Bytes(bytes) => Some(bytes),
+            TextureSource::Array(faces) => faces.first().and_then(|mips| mips.first()).copied(),
+        };
+        if let Some(bytes) = bytes {
+            self.texture_update(texture, bytes);
+        }
+
+        texture
+    }
+
+    fn texture_params(&self, texture: TextureId) -> TextureParams {
+        self.with_texture(texture, |texture| texture.params)
+    }
+
+    unsafe fn texture_raw_id(&self, texture: TextureId) -> RawId {
+        RawId::OpenGl(self.with_texture(texture, |texture| texture.raw))
+    }
+
+    fn texture_set_min_filter(
+        &mut self,
+        texture: TextureId,
+        filter: FilterMode,
+        mipmap_filter: MipmapFilterMode,
+    ) {
+        self.with_texture_mut(texture, |texture| {
+            texture.params.min_filter = filter;
+            texture.params.mipmap_filter = mipmap_filter;
+        });
+    }
+
+    fn texture_set_mag_filter(&mut self, texture: TextureId, filter: FilterMode) {
+        self.with_texture_mut(texture, |texture| texture.params.mag_filter = filter);
+    }
+
+    fn texture_set_wrap(&mut self, texture: TextureId, wrap_x: TextureWrap, _wrap_y: TextureWrap) {
+        // miniquad only stores a single wrap mode for both axes
+        self.with_texture_mut(texture, |texture| texture.params.wrap = wrap_x);
+    }
+
+    fn texture_generate_mipmaps(&mut self, _texture: TextureId) {}
+
+    fn texture_resize(
+        &mut self,
+        texture: TextureId,
+        width: u32,
+        height: u32,
+        bytes: Option<&[u8]>,
+    ) {
+        self.with_texture_mut(texture, |texture| {
+            texture.resize(width, height);
+            if let Some(bytes) = bytes {
+                texture.write(0, 0, width as _, height as _, bytes);
+            }
+        });
+    }
+
+    fn texture_read_pixels(&mut self, texture: TextureId, bytes: &mut [u8]) {
+        self.with_texture(texture, |texture| texture.read(bytes));
+    }
+
+    fn texture_update_part(
+        &mut self,
+        texture: TextureId,
+        x_offset: i32,
+        y_offset: i32,
+        width: i32,
+        height: i32,
+        bytes: &[u8],
+    ) {
+        self.with_texture_mut(texture, |texture| {
+            texture.write(x_offset, y_offset, width, height, bytes)
+        });
+    }
+
+    fn new_render_pass_mrt(
+        &mut self,
+        color_img: &[TextureId],
+        resolve_img: Option<&[TextureId]>,
+        depth_img: Option<TextureId>,
+    ) -> RenderPass {
+        if color_img.is_empty() && depth_img.is_none() {
+            panic!("Render pass should have at least one non-none target");
+        }
+
+        self.passes.push(Some(SoftPass {
+            colors: color_img.to_vec(),
+            resolves: resolve_img.map(|textures| textures.to_vec()),
+            depth: depth_img,
+        }));
+        handle(self.passes.len() - 1)
+    }
+
+    fn render_pass_color_attachments(&self, render_pass: RenderPass) -> &[TextureId] {
+        let pass = self.passes[handle_index(render_pass)]
+            .as_ref()
+            .expect("Trying to use a deleted render pass");
+        pass.resolves.as_deref().unwrap_or(&pass.colors)
+    }
+
+    fn delete_render_pass(&mut self, render_pass: RenderPass) {
+        // Like with OpenGL, attachments are deleted together with the pass, but resolve textures aren't
+        if let Some(pass) = self.passes[handle_index(render_pass)].take() {
+            let mut textures = self.textures.borrow_mut();
+            for texture in pass.colors.iter().chain(pass.depth.iter()) {
+                textures.remove(texture);
+            }
+        }
+    }
+
+    fn new_pipeline(
+        &mut self,
+        buffer_layout: &[BufferLayout],
+        attributes: &[VertexAttribute],
+        shader: ShaderId,
+        params: PipelineParams,
+    ) -> Pipeline {
+        let meta = self.shaders[handle_index(shader)]
+            .as_ref()
+            .expect("Trying to use a deleted shader");
+
+        self.pipelines.push(Some(SoftPipeline::new(
+            buffer_layout,
+            attributes,
+            meta,
+            params,
+        )));
+        handle(self.pipelines.len() - 1)
+    }
+
+    fn apply_pipeline(&mut self, pipeline: &Pipeline) {
+        self.state.pipeline = Some(*pipeline);
+    }
+
+    fn delete_pipeline(&mut self, pipeline: Pipeline) {
+        self.pipelines[handle_index(pipeline)] = None;
+    }
+
+    fn new_buffer(
+        &mut self,
+        _type_: BufferType,
+        _usage: BufferUsage,
+        data: BufferSource,
+    ) -> BufferId {
+        let buffer = match (&data, BufferData::from_source(&data)) {
+            (_, Some(data)) => SoftBuffer {
+                bytes: data.bytes.to_vec(),
+                element_size: data.element_size,
+            },
+            (&BufferSource::Empty { size, element_size }, None) => SoftBuffer {
+                bytes: vec![0; size],
+                element_size,
+            },
+            _ => unreachable!(),
+        };
+
+        self.buffers.push(Some(buffer));
+        handle(self.buffers.len() - 1)
+    }
+
+    fn buffer_update(&mut self, buffer: BufferId, data: BufferSource) {
+        let Some(data) = BufferData::from_source(&data) else {
+            return;
+        };
+        let buffer = self.buffers[handle_index(buffer)]
+            .as_mut()
+            .expect("Trying to update a deleted buffer");
+
+        assert!(
+            data.bytes.len() <= buffer.bytes.len(),
+            "Buffer update is larger than the buffer"
+        );
+        buffer.bytes[..data.bytes.len()].copy_from_slice(data.bytes);
+        buffer.element_size = data.element_size;
+    }
+
+    fn buffer_size(&mut self, buffer: BufferId) -> usize {
+        self.buffer(buffer).bytes.len()
+    }
+
+    fn delete_buffer(&mut self, buffer: BufferId) {
+        self.buffers[handle_index(buffer)] = None;
+    }
+
+    fn delete_texture(&mut self, texture: TextureId) {
+        self.textures.borrow_mut().remove(&texture);
+    }
+
+    fn delete_shader(&mut self, program: ShaderId) {
+        self.shaders[handle_index(program)] = None;
+    }
+
+    fn apply_viewport(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        self.state.viewport = (x, y, w, h);
+    }
+
+    fn apply_scissor_rect(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        self.state.scissor = (x, y, w, h);
+    }
+
+    fn apply_bindings_from_slice(
+        &mut self,
+        vertex_buffers: &[BufferId],
+        index_buffer: BufferId,
+        textures: &[TextureId],
+    ) {
+        self.state.vertex_buffers = vertex_buffers.to_vec();
+        self.state.index_buffer = Some(index_buffer);
+        self.state.images = textures.to_vec();
+    }
+
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    fn apply_uniforms_from_bytes(&mut self, uniform_ptr: *const u8, size: usize) {
+        // SAFETY: the caller is expected to pass a valid pointer, the same as with miniquad's backends
+        let uniforms = unsafe { std::slice::from_raw_parts(uniform_ptr, size) };
+        self.state.uniforms = uniforms.to_vec();
+    }
+
+    fn clear(
+        &mut self,
+        color: Option<(f32, f32, f32, f32)>,
+        depth: Option<f32>,
+        stencil: Option<i32>,
+    ) {
+        let (colors, depth_texture) = self.pass_attachments(self.state.pass);
+        let mut textures = self.textures.borrow_mut();
+
+        if let Some((r, g, b, a)) = color {
+            let color = [r, g, b, a].map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+            for texture in &colors {
+                if let Some(texture) = textures.get_mut(texture) {
+                    texture.color.fill(color);
+                }
+            }
+        }
+
+        if let Some(texture) = depth_texture.and_then(|texture| textures.get_mut(&texture)) {
+            if let Some(depth) = depth {
+                texture.depth.fill(depth);
+            }
+            if let Some(stencil) = stencil {
+                texture.stencil.fill(stencil as u8);
+            }
+        }
+    }
+
+    fn begin_default_pass(&mut self, action: PassAction) {
+        self.begin_pass(None, action);
+    }
+
+    fn begin_pass(&mut self, pass: Option<RenderPass>, action: PassAction) {
+        self.state.pass = pass;
+
+        let (colors, depth) = self.pass_attachments(pass);
+        let size = colors
+            .first()
+            .or(depth.as_ref())
+            .map(|texture| self.texture_size(*texture))
+            .unwrap_or_default();
+
+        self.state.viewport = (0, 0, size.0 as _, size.1 as _);
+        self.state.scissor = self.state.viewport;
+
+        if let PassAction::Clear {
+            color,
+            depth,
+            stencil,
+        } = action
+        {
+            self.clear(color, depth, stencil);
+        }
+    }
+
+    fn end_render_pass(&mut self) {
+        let Some(pass) = self.state.pass.take() else {
+            return;
+        };
+        let pass = self.passes[handle_index(pass)].as_ref().unwrap();
+
+        // Multisampling isn't emulated, so resolving is just a copy
+        if let Some(resolves) = &pass.resolves {
+            let mut textures = self.textures.borrow_mut();
+            for (color, resolve) in pass.colors.iter().zip(resolves) {
+                let Some(pixels) = textures.get(color).map(|texture| texture.color.clone()) else {
+                    continue;
+                };
+                if let Some(resolve) = textures.get_mut(resolve) {
+                    if resolve.color.len() == pixels.len() {
+                        resolve.color = pixels;
+                    }
+                }
+            }
+        }
+    }
+
+    fn commit_frame(&mut self) {}
+
+    fn draw(&self, base_element: i32, num_elements: i32, num_instances: i32) {
+        let pipeline = self
+            .state
+            .pipeline
+            .and_then(|pipeline| self.pipelines[handle_index(pipeline)].as_ref())
+            .expect("Drawing without any binded pipeline");
+        let index_buffer = self.buffer(
+            self.state
+                .index_buffer
+                .expect("Drawing without an index buffer"),
+        );
+
+        let indices: Vec<usize> = index_buffer
+            .bytes
+            .chunks_exact(index_buffer.element_size)
+            .skip(base_element as usize)
+            .take(num_elements as usize)
+            .map(|index| match *index {
+                [a] => a as usize,
+                [a, b] => u16::from_le_bytes([a, b]) as usize,
+                [a, b, c, d] => u32::from_le_bytes([a, b, c, d]) as usize,
+                _ => panic!("Unsupported index size: {}", index.len()),
+            })
+            .collect();
+
+        let (colors, depth) = self.pass_attachments(self.state.pass);
+        let mut textures = self.textures.borrow_mut();
+
+        // Attachments are taken out of the storage, so the rest of the textures can be sampled at
+        // the same time. Sampling an attachment of the current pass is undefined behaviour in OpenGL
+        // anyway.
+        let mut attachments = Attachments {
+            colors: colors
+                .iter()
+                .filter_map(|id| textures.remove(id).map(|texture| (*id, texture)))
+                .collect(),
+            depth: depth.and_then(|texture| textures.remove(&texture)),
+        };
+
+        let texture = self
+            .state
+            .images
+            .first()
+            .and_then(|texture| textures.get(texture));
+
+        for instance in 0..num_instances.max(0) as usize {
+            let vertices: Vec<ClipVertex> = indices
+                .iter()
+                .map(|index| self.shade_vertex(pipeline, *index, instance))
+                .collect();
+
+            raster::rasterize(
+                pipeline,
+                &vertices,
+                texture,
+                self.state.viewport,
+                self.state.scissor,
+                &mut attachments,
+            );
+        }
+
+        for (id, texture) in attachments.colors {
+            textures.insert(id, texture);
+        }
+        if let (Some(id), Some(texture)) = (depth, attachments.depth) {
+            textures.insert(id, texture);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Drawing without a [Renderer], to build geometry on worker threads
+
+use std::ops::Range;
+
+use miniquad::TextureId;
+
+use super::{AsIndex, AsVertex, DrawMode, Renderer, Vertex};
+
+/// Something the drawing helpers of [crate::draw] can draw into: a [Renderer],
+/// or a [CommandBuffer] filled on another thread
+pub trait DrawTarget<V = Vertex>
+where V: AsVertex {
+    fn with_texture(&mut self, texture: Option<&TextureId>);
+
+    fn with_draw_mode(&mut self, mode: DrawMode);
+
+    /// See [Renderer::push_geometry_indexed]
+    fn push_geometry_indexed<I: AsIndex>(&mut self, vertices: &[V], indices: &[I]);
+
+    /// See [Renderer::push_geometry]
+    fn push_geometry(&mut self, vertices: &[V], indices: &[u16]) {
+        self.push_geometry_indexed(vertices, indices);
+    }
+}
+
+impl<V> DrawTarget<V> for Renderer<V>
+where V: AsVertex {
+    fn with_texture(&mut self, texture: Option<&TextureId>) {
+        Renderer::with_texture(self, texture);
+    }
+
+    fn with_draw_mode(&mut self, mode: DrawMode) {
+        Renderer::with_draw_mode(self, mode);
+    }
+
+    fn push_geometry_indexed<I: AsIndex>(&mut self, vertices: &[V], indices: &[I]) {
+        Renderer::push_geometry_indexed(self, vertices, indices);
+    }
+}
+
+/// Geometry pushed into a [CommandBuffer], with the state it was pushed with
+#[derive(Debug, Clone)]
+struct Command {
+    texture: Option<TextureId>,
+    draw_mode: DrawMode,
+    vertices: Range<usize>,
+    indices: Range<usize>,
+}
+
+/// Geometry recorded without a [Renderer], so it can be built on worker threads.
+///
+/// It's filled with the same drawing helpers as a renderer, since both are [DrawTarget]s.
+/// Text can be drawn with a [FontSnapshot](crate::text::FontSnapshot) shared between threads,
+/// see [draw_text_cached](crate::draw::draw_text_cached).
+///
+/// Once filled, command buffers are appended to a renderer on the main thread with
+/// [Renderer::append_command_buffer], where they're batched like any other geometry.
+/// They're drawn in the order they're appended, so to get the same result every frame,
+/// append them in a fixed order (for example by the index of the job that filled them),
+/// not in the order the threads finish.
+///
+/// ```ignore
+/// let buffers: Vec<CommandBuffer> = chunks
+///     .par_iter()
+///     .map(|entities| {
+///         let mut buffer = CommandBuffer::new();
+///         for entity in entities {
+///             draw_rectangle(&mut buffer, entity.x, entity.y, 10., 10., RED);
+///         }
+///         buffer
+///     })
+///     .collect();
+///
+/// for buffer in &buffers {
+///     renderer.append_command_buffer(buffer);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CommandBuffer<V = Vertex>
+where V: AsVertex {
+    texture: Option<TextureId>,
+    draw_mode: DrawMode,
+    commands: Vec<Command>,
+    vertices: Vec<V>,
+    indices: Vec<u32>,
+}
+
+impl<V> CommandBuffer<V>
+where V: AsVertex {
+    pub const fn new() -> Self {
+        Self {
+            texture: None,
+            draw_mode: DrawMode::Triangles,
+            commands: vec![],
+            vertices: vec![],
+            indices: vec![],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Remove the recorded geometry and reset the state, keeping the allocations
+    pub fn clear(&mut self) {
+        self.texture = None;
+        self.draw_mode = DrawMode::Triangles;
+        self.commands.clear();
+        self.vertices.clear();
+        self.indices.clear();
+    }
+
+    /// The recorded geometry in submission order: the texture, draw mode, vertices and indices
+    /// of every push
+    pub(crate) fn commands(
+        &self,
+    ) -> impl Iterator<Item = (Option<TextureId>, DrawMode, &[V], &[u32])> {
+        self.commands.iter().map(|command| {
+            (
+                command.texture,
+                command.draw_mode,
+                &self.vertices[command.vertices.clone()],
+                &self.indices[command.indices.clone()],
+            )
+        })
+    }
+}
+
+impl<V> Default for CommandBuffer<V>
+where V: AsVertex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> DrawTarget<V> for CommandBuffer<V>
+where V: AsVertex {
+    fn with_texture(&mut self, texture: Option<&TextureId>) {
+        self.texture = texture.copied();
+    }
+
+    fn with_draw_mode(&mut self, mode: DrawMode) {
+        self.draw_mode = mode;
+    }
+
+    fn push_geometry_indexed<I: AsIndex>(&mut self, vertices: &[V], indices: &[I]) {
+        let vertices_start = self.vertices.len();
+        let indices_start = self.indices.len();
+        self.vertices.extend_from_slice(vertices);
+        self.indices.extend(indices.iter().map(|index| index.to_u32()));
+
+        self.commands.push(Command {
+            texture: self.texture,
+            draw_mode: self.draw_mode,
+            vertices: vertices_start..self.vertices.len(),
+            indices: indices_start..self.indices.len(),
+        });
+    }
+}
+
+#[test]
+fn command_buffer_merge() {
+    use crate::color::{BLUE, RED};
+    use crate::draw::{draw_line, draw_rectangle};
+    use crate::graphics::test_renderer;
+
+    fn assert_send<T: Send>() {}
+    assert_send::<CommandBuffer>();
+
+    let buffers: Vec<CommandBuffer> = std::thread::scope(|scope| {
+        let jobs: Vec<_> = [RED, BLUE]
+            .into_iter()
+            .map(|color| {
+                scope.spawn(move || {
+                    let mut buffer = CommandBuffer::new();
+                    draw_rectangle(&mut buffer, 0., 0., 2., 2., color);
+                    draw_line(&mut buffer, 0., 0., 2., 2., 1., color);
+                    buffer
+                })
+            })
+            .collect();
+        jobs.into_iter().map(|job| job.join().unwrap()).collect()
+    });
+    assert_eq!(buffers[0].commands().count(), 2);
+
+    let (mut backend, mut renderer, _target) = test_renderer(4);
+    renderer.with_stats(true);
+
+    for buffer in &buffers {
+        renderer.append_command_buffer(buffer);
+    }
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let stats = renderer.take_frame_stats();
+    assert_eq!(stats.draw_calls, 1);
+    assert_eq!(stats.vertices, 16);
+
+    let mut buffer = buffers[0].clone();
+    buffer.clear();
+    assert!(buffer.is_empty());
+}
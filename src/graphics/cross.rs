@@ -0,0 +1,1048 @@
+//! Cross-compilation of a single shader source into every [ShaderSource] variant, enabled with the
+//! `cross-compile` feature.
+//!
+//! Shaders are written once, in the GLSL ES 100 dialect the built-in shaders use (`attribute`,
+//! `varying`, `texture2D`, `gl_FragColor`), since it's the lowest common denominator: WebGL1 can't
+//! run anything newer. [cross_compile] then produces GLSL 330 and a Metal program from it:
+//! ```ignore
+//! let shader = cross_compile::<Vertex>(VERTEX, FRAGMENT)?;
+//! let material = load_material(backend, renderer, shader.source(backend), params)?;
+//! ```
+//! The translation is cheap enough to run at startup, but the output is plain source, so it can
+//! just as well be generated offline (in a build script, for example) and embedded.
+//!
+//! This is a source-to-source translation, not a full GLSL compiler, so it has some restrictions:
+//! - attributes, uniforms, varyings and samplers can only be used in `main`, helper functions have
+//!   to receive them as arguments. Helpers that touch them are rejected
+//! - samplers are `sampler2D`s, sampled directly with `texture2D(Sampler, uv)`
+//! - a single color output, `gl_FragColor`
+//!
+//! Everything else is copied with GLSL types and functions renamed to their Metal counterparts.
+//! Anything it doesn't understand is reported as [Error::CrossCompileError].
+//!
+//! Nothing is type checked: the GLSL 100 output is the input as is, and mistakes the translation
+//! lets through only show up when the backend compiles the shader. The tests compile the Metal
+//! output with the Metal compiler, on macOS.
+
+use std::collections::{HashMap, HashSet};
+
+use miniquad::{Backend, RenderingBackend, ShaderSource, ShaderType, VertexAttribute};
+
+use crate::{graphics::AsVertex, Error};
+
+/// A pair of GLSL sources
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlslSource {
+    pub vertex: String,
+    pub fragment: String,
+}
+
+/// The output of [cross_compile]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrossShader {
+    /// The original sources, unchanged, for GLES2 and WebGL1
+    pub glsl_100: GlslSource,
+    /// For desktop OpenGL core profiles, which don't accept GLSL 100
+    pub glsl_330: GlslSource,
+    /// A Metal program, with `vertexShader` and `fragmentShader` entry points
+    pub msl: String,
+}
+
+impl CrossShader {
+    /// Pick the source the backend can compile, to pass to
+    /// [load_material](crate::graphics::material::load_material)
+    pub fn source(&self, backend: &dyn RenderingBackend) -> ShaderSource<'_> {
+        let info = backend.info();
+        let glsl = match info.glsl_support {
+            support if !support.v100 && support.v330 => &self.glsl_330,
+            _ => &self.glsl_100,
+        };
+
+        match info.backend {
+            Backend::Metal => ShaderSource::Msl { program: &self.msl },
+            Backend::OpenGl => ShaderSource::Glsl {
+                vertex: &glsl.vertex,
+                fragment: &glsl.fragment,
+            },
+        }
+    }
+}
+
+/// Translate GLSL ES 100 vertex and fragment shaders into GLSL 330 and Metal.
+///
+/// Metal identifies vertex attributes by index instead of by name,
+/// so the indices are taken from the [AsVertex::attributes] of `V`.
+pub fn cross_compile<V: AsVertex>(vertex: &str, fragment: &str) -> Result<CrossShader, Error> {
+    let glsl_330 = GlslSource {
+        vertex: glsl_330(ShaderType::Vertex, vertex)?,
+        fragment: glsl_330(ShaderType::Fragment, fragment)?,
+    };
+
+    let (vertex_tokens, _) = tokenize(vertex);
+    let (fragment_tokens, _) = tokenize(fragment);
+    let msl = msl(
+        &Stage::parse(ShaderType::Vertex, &vertex_tokens)?,
+        &Stage::parse(ShaderType::Fragment, &fragment_tokens)?,
+        &V::attributes(),
+    )?;
+
+    Ok(CrossShader {
+        glsl_100: GlslSource {
+            vertex: vertex.to_string(),
+            fragment: fragment.to_string(),
+        },
+        glsl_330,
+        msl,
+    })
+}
+
+fn error(shader_type: ShaderType, line: usize, message: String) -> Error {
+    Error::CrossCompileError {
+        shader_type,
+        line,
+        message,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Ident,
+    Number,
+    Punct,
+    /// A whole preprocessor line
+    Directive,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+    /// Whitespace and comments before the token, kept to preserve the formatting
+    space: &'a str,
+    line: usize,
+}
+
+impl Token<'_> {
+    fn is(&self, text: &str) -> bool {
+        self.kind != Kind::Directive && self.text == text
+    }
+}
+
+/// Split a source into tokens. Multi-character operators are kept as separate characters, since
+/// they're copied as is anyway. Also returns whatever follows the last token
+fn tokenize(source: &str) -> (Vec<Token<'_>>, &str) {
+    let bytes = source.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    let mut line = 1;
+    // Whether there's only whitespace between the last line break and the current position
+    let mut line_start = true;
+
+    loop {
+        let space_start = i;
+        while i < bytes.len() {
+            let rest = &source[i..];
+            if bytes[i] == b'\n' {
+                line += 1;
+                line_start = true;
+                i += 1;
+            } else if bytes[i].is_ascii_whitespace() {
+                i += 1;
+            } else if rest.starts_with("//") {
+                i += rest.find('\n').unwrap_or(rest.len());
+            } else if let Some(comment) = rest.strip_prefix("/*") {
+                let len = comment.find("*/").map_or(rest.len(), |end| end + 4);
+                line += rest[..len].matches('\n').count();
+                i += len;
+            } else {
+                break;
+            }
+        }
+        let space = &source[space_start..i];
+        if i == bytes.len() {
+            return (tokens, space);
+        }
+
+        let start = i;
+        let token_line = line;
+        let kind = if bytes[i] == b'#' && line_start {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'\n') {
+                    line += 1;
+                    i += 1;
+                }
+                i += 1;
+            }
+            Kind::Directive
+        } else if bytes[i].is_ascii_alphabetic() || bytes[i] == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            Kind::Ident
+        } else if bytes[i].is_ascii_digit()
+            || (bytes[i] == b'.' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit))
+        {
+            while i < bytes.len() {
+                match bytes[i] {
+                    b'.' | b'_' => i += 1,
+                    c if c.is_ascii_alphanumeric() => i += 1,
+                    b'+' | b'-' if matches!(bytes[i - 1], b'e' | b'E') => i += 1,
+                    _ => break,
+                }
+            }
+            Kind::Number
+        } else {
+            i += source[i..].chars().next().map_or(1, char::len_utf8);
+            Kind::Punct
+        };
+        line_start = false;
+
+        tokens.push(Token {
+            kind,
+            text: &source[start..i],
+            space,
+            line: token_line,
+        });
+    }
+}
+
+/// The name of a preprocessor directive, like `version` for `#version 100`
+fn directive(text: &str) -> &str {
+    text[1..]
+        .trim_start()
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or_default()
+}
+
+fn glsl_330(shader_type: ShaderType, source: &str) -> Result<String, Error> {
+    let vertex = matches!(shader_type, ShaderType::Vertex);
+    let (tokens, trailing) = tokenize(source);
+
+    let mut header = "#version 330".to_string();
+    if !vertex {
+        header.push_str("\nout vec4 _FragColor;");
+    }
+
+    let mut output = String::with_capacity(source.len());
+    if !tokens
+        .iter()
+        .any(|token| token.kind == Kind::Directive && directive(token.text) == "version")
+    {
+        output.push_str(&header);
+        output.push('\n');
+    }
+
+    for token in &tokens {
+        output.push_str(token.space);
+        output.push_str(match (token.kind, token.text) {
+            (Kind::Directive, text) if directive(text) == "version" => &header,
+            (Kind::Ident, "attribute") => "in",
+            (Kind::Ident, "varying") if vertex => "out",
+            (Kind::Ident, "varying") => "in",
+            (Kind::Ident, "texture2D" | "textureCube") => "texture",
+            (Kind::Ident, "texture2DLod" | "textureCubeLod") => "textureLod",
+            (Kind::Ident, "texture2DProj") => "textureProj",
+            (Kind::Ident, "gl_FragColor") => "_FragColor",
+            (Kind::Ident, "gl_FragData") => {
+                return Err(error(
+                    shader_type,
+                    token.line,
+                    "gl_FragData isn't supported, only gl_FragColor".to_string(),
+                ))
+            }
+            (_, text) => text,
+        });
+    }
+    output.push_str(trailing);
+
+    Ok(output)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Storage {
+    Attribute,
+    Varying,
+    Uniform,
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    storage: Storage,
+    ty: String,
+    name: String,
+    /// The array size, as written in the source
+    array: Option<String>,
+    line: usize,
+}
+
+/// A shader split into its global variables, `main` and everything else
+struct Stage<'t, 'a> {
+    shader_type: ShaderType,
+    variables: Vec<Variable>,
+    samplers: Vec<String>,
+    /// Helper functions, structs, constants and preprocessor directives
+    globals: Vec<&'t [Token<'a>]>,
+    /// Tokens between the braces of `main`, including the closing one
+    main: &'t [Token<'a>],
+}
+
+impl<'t, 'a> Stage<'t, 'a> {
+    fn parse(shader_type: ShaderType, tokens: &'t [Token<'a>]) -> Result<Self, Error> {
+        let mut stage = Stage {
+            shader_type,
+            variables: vec![],
+            samplers: vec![],
+            globals: vec![],
+            main: &[],
+        };
+
+        for statement in statements(tokens) {
+            let first = statement[0];
+            match first.text {
+                "attribute" | "varying" | "uniform" => stage.declaration(statement)?,
+                "precision" => (),
+                "void" if statement.get(1).is_some_and(|token| token.is("main")) => {
+                    let open = statement
+                        .iter()
+                        .position(|token| token.is("{"))
+                        .ok_or_else(|| stage.error(first.line, "main has no body"))?;
+                    stage.main = &statement[open + 1..];
+                }
+                _ => stage.globals.push(statement),
+            }
+        }
+
+        if stage.main.is_empty() {
+            return Err(stage.error(0, "There's no main function"));
+        }
+
+        Ok(stage)
+    }
+
+    fn error(&self, line: usize, message: impl Into<String>) -> Error {
+        error(self.shader_type, line, message.into())
+    }
+
+    /// Parse `storage [precision] type name [\[size\]], ...;`
+    fn declaration(&mut self, statement: &[Token]) -> Result<(), Error> {
+        let storage = match statement[0].text {
+            "attribute" => Storage::Attribute,
+            "varying" => Storage::Varying,
+            _ => Storage::Uniform,
+        };
+
+        let mut tokens = statement[1..]
+            .iter()
+            .filter(|token| !matches!(token.text, "lowp" | "mediump" | "highp"))
+            .peekable();
+        let ty = tokens
+            .next()
+            .filter(|token| token.kind == Kind::Ident)
+            .ok_or_else(|| self.error(statement[0].line, "Expected a type"))?;
+
+        loop {
+            let name = tokens
+                .next()
+                .filter(|token| token.kind == Kind::Ident)
+                .ok_or_else(|| self.error(ty.line, "Expected a variable name"))?;
+
+            let mut array = None;
+            if tokens.next_if(|token| token.is("[")).is_some() {
+                let size = tokens
+                    .by_ref()
+                    .take_while(|token| !token.is("]"))
+                    .map(|token| token.text)
+                    .collect::<String>();
+                array = Some(size);
+            }
+
+            match ty.text {
+                "sampler2D" if storage == Storage::Uniform && array.is_none() => {
+                    self.samplers.push(name.text.to_string())
+                }
+                "sampler2D" | "samplerCube" => {
+                    return Err(self.error(
+                        name.line,
+                        format!(
+                            "Sampler {} isn't supported, only sampler2D uniforms are",
+                            name.text
+                        ),
+                    ))
+                }
+                _ => self.variables.push(Variable {
+                    storage,
+                    ty: ty.text.to_string(),
+                    name: name.text.to_string(),
+                    array,
+                    line: name.line,
+                }),
+            }
+
+            match tokens.next() {
+                Some(token) if token.is(",") => continue,
+                Some(token) if token.is(";") => return Ok(()),
+                _ => return Err(self.error(name.line, "Expected a ',' or ';'")),
+            }
+        }
+    }
+
+    fn variables(&self, storage: Storage) -> impl Iterator<Item = &Variable> {
+        self.variables
+            .iter()
+            .filter(move |variable| variable.storage == storage)
+    }
+}
+
+/// Split tokens into top-level statements: declarations, function definitions and directives
+fn statements<'t, 'a>(tokens: &'t [Token<'a>]) -> Vec<&'t [Token<'a>]> {
+    let mut statements = vec![];
+    let mut start = 0;
+    let mut depth = 0;
+
+    for (ix, token) in tokens.iter().enumerate() {
+        let end = match (token.kind, token.text) {
+            (Kind::Directive, _) => ix == start,
+            (Kind::Punct, "(" | "[" | "{") => {
+                depth += 1;
+                false
+            }
+            (Kind::Punct, ")" | "]") => {
+                depth -= 1;
+                false
+            }
+            // Struct definitions end with a ';' after the brace
+            (Kind::Punct, "}") => {
+                depth -= 1;
+                depth == 0 && !tokens[start].is("struct")
+            }
+            (Kind::Punct, ";") => depth == 0,
+            _ => false,
+        };
+
+        if end {
+            statements.push(&tokens[start..=ix]);
+            start = ix + 1;
+        }
+    }
+    if start < tokens.len() {
+        statements.push(&tokens[start..]);
+    }
+
+    statements
+}
+
+/// GLSL types and functions that are called differently in Metal
+fn msl_name(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "vec2" => "float2",
+        "vec3" => "float3",
+        "vec4" => "float4",
+        "ivec2" => "int2",
+        "ivec3" => "int3",
+        "ivec4" => "int4",
+        "bvec2" => "bool2",
+        "bvec3" => "bool3",
+        "bvec4" => "bool4",
+        "mat2" => "float2x2",
+        "mat3" => "float3x3",
+        "mat4" => "float4x4",
+        "mod" => "_mod",
+        "inversesqrt" => "rsqrt",
+        "dFdx" => "dfdx",
+        "dFdy" => "dfdy",
+        _ => return None,
+    })
+}
+
+/// The Metal type of a uniform, its size and alignment.
+/// Vectors are packed, since miniquad doesn't pad uniforms
+fn msl_uniform(ty: &str) -> Option<(&'static str, usize, usize)> {
+    Some(match ty {
+        "float" => ("float", 4, 4),
+        "vec2" => ("packed_float2", 8, 4),
+        "vec3" => ("packed_float3", 12, 4),
+        "vec4" => ("packed_float4", 16, 4),
+        "int" => ("int", 4, 4),
+        "ivec2" => ("packed_int2", 8, 4),
+        "ivec3" => ("packed_int3", 12, 4),
+        "ivec4" => ("packed_int4", 16, 4),
+        "mat4" => ("float4x4", 64, 16),
+        _ => return None,
+    })
+}
+
+/// The index of the matching closing bracket
+fn closing(tokens: &[Token], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (ix, token) in tokens.iter().enumerate().skip(open) {
+        match token.text {
+            "(" | "[" if token.kind == Kind::Punct => depth += 1,
+            ")" | "]" if token.kind == Kind::Punct => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(ix);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Names of the global variables of a stage, to resolve identifiers in main
+struct Scope<'s> {
+    uniforms: HashMap<&'s str, &'s Variable>,
+    attributes: HashSet<&'s str>,
+    varyings: HashSet<&'s str>,
+    samplers: HashSet<&'s str>,
+}
+
+impl<'s> Scope<'s> {
+    fn new(stage: &'s Stage) -> Self {
+        let names = |storage| {
+            stage
+                .variables(storage)
+                .map(|variable| variable.name.as_str())
+                .collect()
+        };
+
+        Scope {
+            uniforms: stage
+                .variables(Storage::Uniform)
+                .map(|uniform| (uniform.name.as_str(), uniform))
+                .collect(),
+            attributes: names(Storage::Attribute),
+            varyings: names(Storage::Varying),
+            samplers: stage.samplers.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl Scope<'_> {
+    /// Translate tokens of a stage to Metal. Global variables are only resolved in `main`,
+    /// elsewhere they're most likely parameters or locals with the same name
+    fn translate(
+        &self,
+        shader_type: ShaderType,
+        main: bool,
+        tokens: &[Token],
+        output: &mut String,
+    ) -> Result<(), Error> {
+        let fail = |line, message: String| Err(error(shader_type, line, message));
+        let vertex = main && matches!(shader_type, ShaderType::Vertex);
+        let fragment = main && matches!(shader_type, ShaderType::Fragment);
+        // An `out` parameter, whose type still needs to become a reference
+        let mut reference = false;
+
+        let mut ix = 0;
+        while ix < tokens.len() {
+            let token = tokens[ix];
+            ix += 1;
+            let after = ix;
+            let next = |offset: usize| tokens.get(after + offset).map_or("", |token| token.text);
+            output.push_str(token.space);
+
+            // Members, like `.position`, aren't variables
+            if token.kind != Kind::Ident || (ix > 1 && tokens[ix - 2].is(".")) {
+                if token.kind != Kind::Directive
+                    || !matches!(directive(token.text), "version" | "extension")
+                {
+                    output.push_str(token.text);
+                }
+                continue;
+            }
+
+            let name = token.text;
+            match name {
+                "lowp" | "mediump" | "highp" | "in" => (),
+                "out" | "inout" => {
+                    output.push_str("thread");
+                    reference = true;
+                }
+                "gl_Position" if vertex => output.push_str("_out._position"),
+                "gl_FragCoord" if fragment => output.push_str("_in._position"),
+                "gl_FragColor" if fragment => output.push_str("_FragColor"),
+                "discard" if fragment => output.push_str("discard_fragment()"),
+                "return" if vertex && next(0) == ";" => output.push_str("return _out"),
+                "return" if fragment && next(0) == ";" => output.push_str("return _FragColor"),
+                "atan" if next(0) == "(" => {
+                    let arguments = closing(tokens, ix).map_or(&[][..], |end| &tokens[ix..end]);
+                    let mut depth = 0;
+                    let mut two = false;
+                    for token in arguments.iter().filter(|token| token.kind == Kind::Punct) {
+                        match token.text {
+                            "(" | "[" => depth += 1,
+                            ")" | "]" => depth -= 1,
+                            "," if depth == 1 => two = true,
+                            _ => (),
+                        }
+                    }
+                    output.push_str(if two { "atan2" } else { "atan" });
+                }
+                "texture2D" if main => {
+                    let sampler = next(1);
+                    if next(0) != "(" || !self.samplers.contains(sampler) || next(2) != "," {
+                        return fail(
+                            token.line,
+                            "texture2D can only sample a sampler2D uniform directly".to_string(),
+                        );
+                    }
+                    output.push_str(&format!("{}.sample({}Smplr,", sampler, sampler));
+                    ix += 3;
+                }
+                "texture2D" | "texture2DLod" | "texture2DProj" | "textureCube"
+                | "textureCubeLod" => {
+                    return fail(token.line, format!("{} can only be used in main", name));
+                }
+                _ if main && name.starts_with("gl_") => {
+                    return fail(token.line, format!("{} isn't supported", name));
+                }
+                _ if main && self.samplers.contains(name) => {
+                    return fail(
+                        token.line,
+                        format!("{} can only be sampled with texture2D", name),
+                    );
+                }
+                _ if main && self.uniforms.contains_key(name) => {
+                    let uniform = self.uniforms[name];
+                    // Packed vectors are converted, to be usable like any other vector
+                    let packed = msl_uniform(&uniform.ty)
+                        .filter(|(ty, _, _)| ty.starts_with("packed"))
+                        .and_then(|_| msl_name(&uniform.ty));
+                    match packed {
+                        Some(ty) if uniform.array.is_some() && next(0) == "[" => {
+                            let end = closing(tokens, ix).unwrap_or(tokens.len() - 1);
+                            output.push_str(&format!("{}(_uniforms.{}", ty, name));
+                            self.translate(shader_type, main, &tokens[ix..=end], output)?;
+                            output.push(')');
+                            ix = end + 1;
+                        }
+                        Some(ty) if uniform.array.is_none() => {
+                            output.push_str(&format!("{}(_uniforms.{})", ty, name))
+                        }
+                        _ => output.push_str(&format!("_uniforms.{}", name)),
+                    }
+                }
+                _ if vertex && self.attributes.contains(name) => {
+                    output.push_str(&format!("_vertex.{}", name))
+                }
+                _ if vertex && self.varyings.contains(name) => {
+                    output.push_str(&format!("_out.{}", name))
+                }
+                _ if fragment && self.varyings.contains(name) => {
+                    output.push_str(&format!("_in.{}", name))
+                }
+                _ => {
+                    output.push_str(msl_name(name).unwrap_or(name));
+                    if reference {
+                        output.push('&');
+                        reference = false;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn msl(vertex: &Stage, fragment: &Stage, attributes: &[VertexAttribute]) -> Result<String, Error> {
+    let builtins = crate::graphics::renderer::shader::uniforms();
+
+    // Uniforms and samplers of both stages, in the order the shaders declare them
+    let mut uniforms = Vec::<&Variable>::new();
+    let mut samplers = vec!["Texture"];
+    for stage in [vertex, fragment] {
+        for uniform in stage.variables(Storage::Uniform) {
+            match uniforms.iter().find(|other| other.name == uniform.name) {
+                Some(other) if other.ty != uniform.ty || other.array != uniform.array => {
+                    return Err(stage.error(
+                        uniform.line,
+                        format!("Uniform {} is declared with another type", uniform.name),
+                    ))
+                }
+                Some(_) => (),
+                None => uniforms.push(uniform),
+            }
+        }
+        for sampler in &stage.samplers {
+            if !samplers.contains(&sampler.as_str()) {
+                samplers.push(sampler);
+            }
+        }
+    }
+
+    for varying in fragment.variables(Storage::Varying) {
+        if !vertex
+            .variables(Storage::Varying)
+            .any(|other| other.name == varying.name && other.ty == varying.ty)
+        {
+            return Err(fragment.error(
+                varying.line,
+                format!(
+                    "Varying {} isn't declared by the vertex shader",
+                    varying.name
+                ),
+            ));
+        }
+    }
+
+    let mut output = "#include <metal_stdlib>\nusing namespace metal;\n\n".to_string();
+    // fmod truncates, while GLSL mod floors
+    output.push_str(
+        "template <typename T, typename U>\nT _mod(T x, U y) { return x - y * floor(x / y); }\n",
+    );
+
+    // Helpers shared by both stages (from a common include, for example) are only emitted once
+    let mut globals = HashSet::new();
+    for stage in [vertex, fragment] {
+        let scope = Scope::new(stage);
+        for global in &stage.globals {
+            let key = global
+                .iter()
+                .map(|token| token.text)
+                .collect::<Vec<_>>()
+                .join(" ");
+            if !globals.insert(key) {
+                continue;
+            }
+
+            // Only global constants live in the constant address space
+            if global[0].is("const") {
+                output.push_str(global[0].space);
+                output.push_str("constant");
+                scope.translate(stage.shader_type, false, &global[1..], &mut output)?;
+            } else {
+                scope.translate(stage.shader_type, false, global, &mut output)?;
+            }
+        }
+    }
+
+    output.push_str("\n\nstruct Uniforms\n{\n");
+    for (name, uniform_type) in &builtins {
+        let ty = match uniform_type {
+            miniquad::UniformType::Mat4 => "float4x4",
+            _ => "float4",
+        };
+        output.push_str(&format!("    {} {};\n", ty, name));
+    }
+    let mut offset = builtins
+        .iter()
+        .map(|(_, uniform_type)| uniform_type.size())
+        .sum::<usize>();
+    for uniform in uniforms
+        .iter()
+        .filter(|uniform| builtins.iter().all(|(name, _)| *name != uniform.name))
+    {
+        let stage = if vertex
+            .variables
+            .iter()
+            .any(|other| other.name == uniform.name)
+        {
+            vertex
+        } else {
+            fragment
+        };
+        let (ty, size, align) = msl_uniform(&uniform.ty).ok_or_else(|| {
+            stage.error(
+                uniform.line,
+                format!("Uniform type {} isn't supported", uniform.ty),
+            )
+        })?;
+        if offset % align != 0 {
+            return Err(stage.error(
+                uniform.line,
+                format!(
+                    "{} isn't aligned to {} bytes, declare it before smaller uniforms",
+                    uniform.name, align
+                ),
+            ));
+        }
+
+        match &uniform.array {
+            Some(count) => {
+                output.push_str(&format!("    {} {}[{}];\n", ty, uniform.name, count));
+                // The size might be a define, in that case the alignment can't be checked
+                offset += size * count.parse::<usize>().unwrap_or(1);
+            }
+            None => {
+                output.push_str(&format!("    {} {};\n", ty, uniform.name));
+                offset += size;
+            }
+        }
+    }
+    output.push_str("};\n");
+
+    let has_attributes = vertex.variables(Storage::Attribute).next().is_some();
+    if has_attributes {
+        output.push_str("\nstruct Vertex\n{\n");
+        for attribute in vertex.variables(Storage::Attribute) {
+            let index = attributes
+                .iter()
+                .position(|other| other.name == attribute.name)
+                .ok_or_else(|| {
+                    vertex.error(
+                        attribute.line,
+                        format!("{} isn't an attribute of the vertex type", attribute.name),
+                    )
+                })?;
+            let ty = msl_name(&attribute.ty).unwrap_or(&attribute.ty);
+            output.push_str(&format!(
+                "    {} {} [[attribute({})]];\n",
+                ty, attribute.name, index
+            ));
+        }
+        output.push_str("};\n");
+    }
+
+    output.push_str("\nstruct RasterizerData\n{\n    float4 _position [[position]];\n");
+    for (location, varying) in vertex.variables(Storage::Varying).enumerate() {
+        let ty = msl_name(&varying.ty).unwrap_or(&varying.ty);
+        match &varying.array {
+            Some(_) => {
+                return Err(vertex.error(varying.line, "Varying arrays aren't supported"));
+            }
+            None => output.push_str(&format!(
+                "    {} {} [[user(locn{})]];\n",
+                ty, varying.name, location
+            )),
+        }
+    }
+    output.push_str("};\n");
+
+    let parameters = |stage: &Stage| {
+        let mut parameters = vec!["constant Uniforms& _uniforms [[buffer(0)]]".to_string()];
+        for (index, sampler) in samplers.iter().enumerate() {
+            if stage.samplers.iter().any(|other| other == sampler) {
+                parameters.push(format!(
+                    "texture2d<float> {0} [[texture({1})]], sampler {0}Smplr [[sampler({1})]]",
+                    sampler, index
+                ));
+            }
+        }
+        parameters.join(",\n    ")
+    };
+
+    output.push_str("\nvertex RasterizerData vertexShader(\n    ");
+    if has_attributes {
+        output.push_str("Vertex _vertex [[stage_in]],\n    ");
+    }
+    output.push_str(&parameters(vertex));
+    output.push_str(")\n{\n    RasterizerData _out;");
+    stage_main(vertex, &mut output)?;
+    output.push_str("    return _out;\n}\n");
+
+    output
+        .push_str("\nfragment float4 fragmentShader(\n    RasterizerData _in [[stage_in]],\n    ");
+    output.push_str(&parameters(fragment));
+    output.push_str(")\n{\n    float4 _FragColor = float4(0.0);");
+    stage_main(fragment, &mut output)?;
+    output.push_str("    return _FragColor;\n}\n");
+
+    Ok(output)
+}
+
+/// Translate the body of main, without its closing brace
+fn stage_main(stage: &Stage, output: &mut String) -> Result<(), Error> {
+    let (close, body) = stage.main.split_last().expect("main has a closing brace");
+    Scope::new(stage).translate(stage.shader_type, true, body, output)?;
+    output.push_str(close.space.trim_end_matches(' '));
+    Ok(())
+}
+
+#[test]
+fn cross_compile_test() {
+    use crate::graphics::{
+        backend::SoftwareBackend, material::reflection::reflect_glsl,
+        material::reflection::reflect_msl, Vertex,
+    };
+
+    const VERTEX: &str = r#"#version 100
+    precision highp float;
+
+    attribute vec3 position;
+    attribute vec2 texcoord;
+    attribute vec4 color0;
+
+    varying lowp vec4 color;
+    varying vec2 uv;
+
+    uniform mat4 Model;
+    uniform mat4 Projection;
+    uniform vec2 Offset;
+
+    void main() {
+        gl_Position = Projection * Model * vec4(position.xy + Offset, position.z, 1);
+        color = color0 / 255.0;
+        uv = texcoord;
+    }
+    "#;
+
+    const FRAGMENT: &str = r#"#version 100
+    precision mediump float;
+
+    varying lowp vec4 color;
+    varying vec2 uv;
+
+    uniform sampler2D Texture;
+    uniform sampler2D Mask;
+    uniform vec4 Tints[2];
+    uniform float Cutoff;
+
+    // Shared with other shaders
+    const float SCALE = 2.0;
+
+    float luma(vec3 rgb) {
+        return dot(rgb, vec3(0.299, 0.587, 0.114));
+    }
+
+    void split(vec4 color, out vec3 rgb, inout float alpha) {
+        rgb = color.rgb;
+        alpha *= color.a;
+    }
+
+    void main() {
+        vec4 base = texture2D(Texture, uv * SCALE) * color;
+        if (texture2D(Mask, uv).r < Cutoff) {
+            discard;
+        }
+        vec3 rgb;
+        float alpha = 1.0;
+        split(base, rgb, alpha);
+        gl_FragColor = vec4(mix(rgb, Tints[int(mod(gl_FragCoord.x, 2.0))].rgb, luma(rgb)), alpha);
+    }
+    "#;
+
+    let shader = cross_compile::<Vertex>(VERTEX, FRAGMENT).unwrap();
+    assert_eq!(shader.glsl_100.vertex, VERTEX);
+
+    let glsl = &shader.glsl_330;
+    assert!(glsl.vertex.starts_with("#version 330"));
+    assert!(glsl.vertex.contains("in vec3 position;"));
+    assert!(glsl.vertex.contains("out lowp vec4 color;"));
+    assert!(glsl.fragment.contains("out vec4 _FragColor;"));
+    assert!(glsl.fragment.contains("in vec2 uv;"));
+    assert!(glsl.fragment.contains("texture(Mask, uv)"));
+    assert!(glsl.fragment.contains("_FragColor = vec4("));
+    assert!(!glsl.fragment.contains("texture2D"));
+
+    let msl = &shader.msl;
+    for line in [
+        "float4x4 Projection;",
+        "packed_float2 Offset;",
+        "packed_float4 Tints[2];",
+        "float3 position [[attribute(0)]];",
+        "float4 color0 [[attribute(2)]];",
+        "float4 color [[user(locn0)]];",
+        "texture2d<float> Mask [[texture(1)]], sampler MaskSmplr [[sampler(1)]]",
+        "_out._position = _uniforms.Projection * _uniforms.Model * \
+         float4(_vertex.position.xy + float2(_uniforms.Offset), _vertex.position.z, 1);",
+        "_out.color = _vertex.color0 / 255.0;",
+        "constant float SCALE = 2.0;",
+        "void split(float4 color, thread float3& rgb, thread float& alpha)",
+        "float4 base = Texture.sample(TextureSmplr, _in.uv * SCALE) * _in.color;",
+        "discard_fragment();",
+        "float4(_uniforms.Tints[int(_mod(_in._position.x, 2.0))]).rgb",
+    ] {
+        assert!(msl.contains(line), "{} not in\n{}", line, msl);
+    }
+    // Only the fragment shader samples textures
+    let vertex_shader = &msl[msl.find("vertexShader").unwrap()..msl.find(")\n{").unwrap()];
+    assert!(!vertex_shader.contains("texture"));
+    #[cfg(target_os = "macos")]
+    compile_msl(msl);
+
+    // Both stages agree on the material uniforms and textures
+    let glsl_reflection = reflect_glsl(&[VERTEX, FRAGMENT]);
+    let msl_reflection = reflect_msl(msl);
+    assert_eq!(glsl_reflection.textures, msl_reflection.textures);
+    assert_eq!(
+        format!("{:?}", glsl_reflection.uniforms),
+        format!("{:?}", msl_reflection.uniforms)
+    );
+
+    let backend = SoftwareBackend::new(1, 1);
+    assert!(matches!(
+        shader.source(&backend),
+        ShaderSource::Glsl { vertex, .. } if vertex == VERTEX
+    ));
+
+    let errors = [
+        (VERTEX.replace("texcoord", "uv0"), FRAGMENT.to_string(), 5),
+        (
+            VERTEX.to_string(),
+            FRAGMENT.replace("sampler2D Mask", "samplerCube Mask"),
+            8,
+        ),
+        (
+            VERTEX.to_string(),
+            FRAGMENT.replace(
+                "uniform float Cutoff;",
+                "uniform float Cutoff;\nuniform mat4 M;",
+            ),
+            11,
+        ),
+        (
+            VERTEX.to_string(),
+            FRAGMENT.replace("gl_FragColor", "gl_FragData[0]"),
+            0,
+        ),
+    ];
+    for (vertex, fragment, line) in errors {
+        match cross_compile::<Vertex>(&vertex, &fragment) {
+            Err(Error::CrossCompileError {
+                line: error_line, ..
+            }) if line > 0 => {
+                assert_eq!(error_line, line)
+            }
+            Err(Error::CrossCompileError { .. }) => (),
+            other => panic!("Expected an error, got {:?}", other),
+        }
+    }
+}
+
+
+#[test]
+#[cfg(target_os = "macos")]
+fn cross_compile_default_shaders() {
+    use crate::graphics::{renderer::shader, Vertex};
+
+    let shader = cross_compile::<Vertex>(shader::VERTEX, shader::FRAGMENT).unwrap();
+    compile_msl(&shader.msl);
+}
+
+/// Compile a Metal program with the Metal compiler of Xcode, and panic with its errors
+#[cfg(all(test, target_os = "macos"))]
+fn compile_msl(program: &str) {
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Tests run in parallel, every program gets its own file
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        "macroquad_abstractions_{}_{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    );
+    let source = std::env::temp_dir().join(format!("{}.metal", name));
+    let air = std::env::temp_dir().join(format!("{}.air", name));
+    std::fs::write(&source, program).unwrap();
+
+    let output = Command::new("xcrun")
+        .args(["-sdk", "macosx", "metal", "-c"])
+        .arg(&source)
+        .arg("-o")
+        .arg(&air)
+        .output()
+        .expect("The Metal compiler (xcrun metal) is needed to check Metal programs");
+    let _ = std::fs::remove_file(&source);
+    let _ = std::fs::remove_file(&air);
+
+    assert!(
+        output.status.success(),
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stderr),
+        program
+    );
+}
@@ -0,0 +1,148 @@
+//! Per-instance data for instanced rendering
+
+use std::{fmt::Debug, marker::PhantomData};
+
+use glam::{Mat4, Vec3};
+use miniquad::{
+    BufferId, BufferSource, BufferType, BufferUsage, RenderingBackend, VertexAttribute,
+    VertexFormat,
+};
+
+use crate::color::Color;
+
+/// Per-instance data trait, the instanced counterpart of [AsVertex](super::AsVertex).
+///
+/// Instance attributes are always read from the second vertex buffer, so the `buffer_index` of the
+/// returned attributes is ignored.
+///
+/// # Safety
+/// The desired type has to be [`repr(C)`], and all its fields have to implement [`ToBytes`](crate::tobytes::ToBytes),
+/// since it will be casted to bytes in the graphics pipeline after.
+pub unsafe trait AsInstance
+where
+    Self: Clone + Copy + Debug + PartialEq + 'static,
+{
+    /// Get [`VertexAttribute`]s of this instance type. This is required when constructing pipelines
+    fn attributes() -> Vec<VertexAttribute>;
+}
+
+/// The default instance data: a model matrix and a color, applied on top of the current model matrix
+/// and the vertex color.
+///
+/// It's used by the default instanced pipeline. Matrices are passed as 4 column attributes
+/// (`instance_model0` to `instance_model3`), since Metal doesn't support matrix vertex attributes.
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct Instance {
+    pub model: Mat4,
+    pub color: [u8; 4],
+}
+
+impl Instance {
+    pub fn new(model: Mat4, color: Color) -> Instance {
+        Instance {
+            model,
+            color: color.into(),
+        }
+    }
+
+    pub fn from_translation(translation: Vec3, color: Color) -> Instance {
+        Instance::new(Mat4::from_translation(translation), color)
+    }
+}
+
+unsafe impl AsInstance for Instance {
+    fn attributes() -> Vec<VertexAttribute> {
+        vec![
+            VertexAttribute::with_buffer("instance_model0", VertexFormat::Float4, 1),
+            VertexAttribute::with_buffer("instance_model1", VertexFormat::Float4, 1),
+            VertexAttribute::with_buffer("instance_model2", VertexFormat::Float4, 1),
+            VertexAttribute::with_buffer("instance_model3", VertexFormat::Float4, 1),
+            VertexAttribute::with_buffer("instance_color", VertexFormat::Byte4, 1),
+        ]
+    }
+}
+
+/// A GPU buffer of per-instance data, used with [Renderer::draw_instanced](super::Renderer::draw_instanced).
+///
+/// The buffer grows when updated with more instances than it can hold, but never shrinks.
+/// It isn't freed automatically, use [InstanceBuffer::delete] when it isn't needed anymore.
+#[derive(Debug, PartialEq)]
+pub struct InstanceBuffer<I>
+where
+    I: AsInstance,
+{
+    buffer: BufferId,
+    capacity: usize,
+    len: usize,
+    _m: PhantomData<I>,
+}
+
+impl<I> InstanceBuffer<I>
+where
+    I: AsInstance,
+{
+    /// Create an empty instance buffer, that can hold `capacity` instances before growing
+    pub fn new(backend: &mut dyn RenderingBackend, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+
+        Self {
+            buffer: Self::new_buffer(backend, capacity),
+            capacity,
+            len: 0,
+            _m: PhantomData,
+        }
+    }
+
+    /// Create an instance buffer, filled with the provided instances
+    pub fn from_slice(backend: &mut dyn RenderingBackend, instances: &[I]) -> Self {
+        let mut buffer = Self::new(backend, instances.len());
+        buffer.update(backend, instances);
+        buffer
+    }
+
+    fn new_buffer(backend: &mut dyn RenderingBackend, capacity: usize) -> BufferId {
+        backend.new_buffer(
+            BufferType::VertexBuffer,
+            BufferUsage::Dynamic,
+            BufferSource::empty::<I>(capacity),
+        )
+    }
+
+    /// Replace the content of this buffer
+    pub fn update(&mut self, backend: &mut dyn RenderingBackend, instances: &[I]) {
+        if instances.len() > self.capacity {
+            backend.delete_buffer(self.buffer);
+
+            self.capacity = instances.len().next_power_of_two();
+            self.buffer = Self::new_buffer(backend, self.capacity);
+        }
+
+        if !instances.is_empty() {
+            backend.buffer_update(self.buffer, BufferSource::slice(instances));
+        }
+        self.len = instances.len();
+    }
+
+    pub const fn buffer(&self) -> BufferId {
+        self.buffer
+    }
+
+    /// The amount of instances in this buffer
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Delete the GPU buffer of this instance buffer
+    pub fn delete(self, backend: &mut dyn RenderingBackend) {
+        backend.delete_buffer(self.buffer);
+    }
+}
@@ -1,24 +1,31 @@
 //! Custom materials - shaders, uniforms.
 
-use std::marker::PhantomData;
+use std::{marker::PhantomData, time::SystemTime};
 
 use crate::{tobytes::ToBytes, Error};
-use miniquad::{PipelineParams, RenderingBackend, TextureId, UniformDesc};
+use miniquad::{
+    PipelineParams, RenderingBackend, ShaderError, ShaderSource, ShaderType, TextureId, UniformDesc,
+};
 
-use super::AsVertex;
-use super::GlPipeline;
+use super::{AsInstance, AsVertex};
+use super::{GlPipeline, UniformHandle, UniformValue};
+use super::renderer::ReleaseQueue;
 
 use super::Renderer;
 
+use preprocessor::{preprocess_shader, PreprocessorConfig, SourceMap};
+use reflection::{reflect_glsl, reflect_msl};
+
 /// A material with custom shaders and uniforms, textures and pipeline params
 ///
 /// ### Warning
 /// This is essentially an abstraction of [miniquad::Pipeline] for a specific renderer.
 /// 2 things can go wrong however:
-/// 1. This material provides will not clear itself after, so this memory management is after you
-///    (A renderer only has 32 possible pipeline slots, not that many)
-/// 2. A material is inherently bound to a specific renderer from which you created it. That means that if you
-///    try to use a material on a renderer that doesn't have it - it will panic.  
+/// 1. This material will not clear itself after, so this memory management is after you.
+///    Delete it with [Renderer::delete_pipeline], or turn it into an [OwnedMaterial]
+///    with [Material::into_owned], which deletes it once dropped
+/// 2. A material is inherently bound to a specific renderer from which you created it. Using it on
+///    another renderer is caught by a debug assertion, release builds ignore it with a warning
 #[derive(Clone, PartialEq)]
 pub struct Material<V>
 where V: AsVertex {
@@ -45,6 +52,7 @@ where V: AsVertex {
     /// Set GPU uniform value for this material.
     /// "name" should be from "uniforms" list used for material creation.
     /// Otherwise uniform value would be silently ignored.
+    /// Use [Material::try_set_uniform] to get an error instead.
     pub fn set_uniform<T>(&self, renderer: &mut Renderer<V>, name: &str, uniform: T) {
         renderer.set_uniform(&self.pipeline, name, uniform);
     }
@@ -62,9 +70,179 @@ where V: AsVertex {
         renderer.set_texture(&self.pipeline, name, *texture);
     }
 
+    /// Set a uniform, returning an error if it doesn't exist or is declared with another type,
+    /// see [Renderer::try_set_uniform]
+    pub fn try_set_uniform<T: UniformValue>(
+        &self,
+        renderer: &mut Renderer<V>,
+        name: &str,
+        uniform: T,
+    ) -> Result<(), Error> {
+        renderer.try_set_uniform(&self.pipeline, name, uniform)
+    }
+
+    /// Set the elements of a uniform array, see [Renderer::try_set_uniform_array]
+    pub fn try_set_uniform_array<T: UniformValue>(
+        &self,
+        renderer: &mut Renderer<V>,
+        name: &str,
+        uniform: &[T],
+    ) -> Result<(), Error> {
+        renderer.try_set_uniform_array(&self.pipeline, name, uniform)
+    }
+
+    /// Look up a uniform once, so it can be set in hot loops with [Renderer::set_uniform_handle]
+    /// without searching it by name
+    pub fn uniform_handle<T: UniformValue>(
+        &self,
+        renderer: &Renderer<V>,
+        name: &str,
+    ) -> Result<UniformHandle<V, T>, Error> {
+        renderer.uniform_handle(&self.pipeline, name)
+    }
+
+    /// Set all uniforms of a [Uniforms] struct at once
+    pub fn set_uniforms<U: Uniforms>(&self, renderer: &mut Renderer<V>, uniforms: &U) {
+        uniforms.set_uniforms(renderer, &self.pipeline);
+    }
+
     pub fn pipeline(&self) -> &GlPipeline<V> {
         &self.pipeline
     }
+
+    /// Make the material delete its pipeline (and shader) when dropped
+    pub fn into_owned(self, renderer: &Renderer<V>) -> OwnedMaterial<V> {
+        OwnedMaterial {
+            material: self,
+            release: Some(renderer.release_queue()),
+        }
+    }
+}
+
+/// A [Material] that deletes its pipeline when dropped, see [Material::into_owned].
+///
+/// Dropping it only queues the pipeline, the renderer deletes it at the end of its next
+/// [Renderer::draw], so it's fine to drop the material right after drawing with it.
+/// To delete it right away, return it to the renderer with [OwnedMaterial::release]
+pub struct OwnedMaterial<V>
+where V: AsVertex {
+    material: Material<V>,
+    release: Option<ReleaseQueue<V>>,
+}
+
+impl<V> OwnedMaterial<V>
+where V: AsVertex {
+    /// Delete the pipeline now, see [Renderer::delete_pipeline]
+    pub fn release(mut self, renderer: &mut Renderer<V>) {
+        self.release = None;
+        renderer.delete_pipeline(self.material.pipeline);
+    }
+}
+
+impl<V> std::ops::Deref for OwnedMaterial<V>
+where V: AsVertex {
+    type Target = Material<V>;
+
+    fn deref(&self) -> &Material<V> {
+        &self.material
+    }
+}
+
+impl<V> std::fmt::Debug for OwnedMaterial<V>
+where V: AsVertex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OwnedMaterial").finish()
+    }
+}
+
+impl<V> Drop for OwnedMaterial<V>
+where V: AsVertex {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            release
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .push(self.material.pipeline);
+        }
+    }
+}
+
+/// A struct of material uniforms, which keeps [MaterialParams::uniforms] in sync with the values
+/// set with [Material::set_uniforms]. Usually derived with `#[derive(Uniforms)]`:
+/// ```ignore
+/// #[derive(Uniforms)]
+/// struct Light {
+///     #[uniform(name = "LightColor")]
+///     color: Vec4,
+///     intensity: f32,
+/// }
+///
+/// let material = load_material(backend, renderer, shader, MaterialParams {
+///     uniforms: Light::uniforms(),
+///     ..Default::default()
+/// })?;
+/// material.set_uniforms(renderer, &light);
+/// ```
+pub trait Uniforms {
+    /// Descriptions of the uniforms, for [MaterialParams::uniforms]
+    fn uniforms() -> Vec<UniformDesc>;
+
+    /// Set every uniform on a pipeline
+    fn set_uniforms<V: AsVertex>(&self, renderer: &mut Renderer<V>, pipeline: &GlPipeline<V>);
+}
+
+/// Run the [preprocessor] on a shader before creating (or replacing) a pipeline with it,
+/// add uniforms and textures found by [reflection] to the declared ones,
+/// and point compilation errors back to the original files and lines
+fn make_material_pipeline<T>(
+    shader: ShaderSource,
+    config: &PreprocessorConfig,
+    uniforms: Vec<UniformDesc>,
+    textures: Vec<String>,
+    make_pipeline: impl FnOnce(ShaderSource, Vec<UniformDesc>, Vec<String>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let map_error = |err, vertex: &SourceMap, fragment: &SourceMap| match err {
+        Error::ShaderError(ShaderError::CompilationError {
+            shader_type,
+            error_message,
+        }) => {
+            let source_map = match shader_type {
+                ShaderType::Vertex => vertex,
+                ShaderType::Fragment => fragment,
+            };
+            Error::ShaderError(ShaderError::CompilationError {
+                shader_type,
+                error_message: source_map.map_log(&error_message),
+            })
+        }
+        err => err,
+    };
+
+    match shader {
+        ShaderSource::Glsl { vertex, fragment } => {
+            let vertex = preprocess_shader("vertex", vertex, config)?;
+            let fragment = preprocess_shader("fragment", fragment, config)?;
+            let (uniforms, textures) = reflect_glsl(&[&vertex.source, &fragment.source])
+                .merge(uniforms, textures);
+
+            let shader = ShaderSource::Glsl {
+                vertex: &vertex.source,
+                fragment: &fragment.source,
+            };
+            make_pipeline(shader, uniforms, textures)
+            .map_err(|err| map_error(err, &vertex.source_map, &fragment.source_map))
+        }
+        ShaderSource::Msl { program } => {
+            let program = preprocess_shader("program", program, config)?;
+            let (uniforms, textures) = reflect_msl(&program.source).merge(uniforms, textures);
+
+            let shader = ShaderSource::Msl {
+                program: &program.source,
+            };
+            make_pipeline(shader, uniforms, textures)
+            .map_err(|err| map_error(err, &program.source_map, &program.source_map))
+        }
+    }
 }
 
 /// Params used for material loading.
@@ -76,37 +254,228 @@ pub struct MaterialParams {
     /// Things like blending, culling, depth dest
     pub pipeline_params: PipelineParams,
 
-    /// List of custom uniforms used in this material
+    /// List of custom uniforms used in this material.
+    /// Uniforms declared in the shader are found automatically (see [reflection]),
+    /// the ones listed here override them
     pub uniforms: Vec<UniformDesc>,
 
-    /// List of textures used in this material
+    /// List of textures used in this material. Like uniforms, they're found automatically as well
     pub textures: Vec<String>,
+
+    /// Includes and defines for the shader [preprocessor]
+    pub preprocessor: PreprocessorConfig,
 }
 
 /// Create a new material on the specified renderer, with specified shader source and material params
 ///
 /// ### Warning
 /// 1. Materials are essentially pipelines, with no cleanup guarantees. Its your responsibility
-///    to properly clean it after use.
+///    to properly clean it after use (or to use [Material::into_owned]).
 /// 2. Given materials can only be used with renderers with which you created said materials.
-///    Since pipelines (from said materials) are renderer bound, using them on another renderer will cause a panic
+///    Since pipelines (from said materials) are renderer bound, using them on another renderer
+///    is a bug, caught by a debug assertion
 pub fn load_material<V: AsVertex>(
     backend: &mut dyn RenderingBackend,
     renderer: &mut Renderer<V>,
-    shader: miniquad::ShaderSource,
+    shader: ShaderSource,
     params: MaterialParams,
 ) -> Result<Material<V>, Error> {
-    let pipeline = renderer.make_pipeline(
-        backend,
+    let pipeline = make_material_pipeline(
         shader,
-        params.pipeline_params,
+        &params.preprocessor,
         params.uniforms,
         params.textures,
+        |shader, uniforms, textures| {
+            renderer.make_pipeline(backend, shader, params.pipeline_params, uniforms, textures)
+        },
     )?;
 
     Ok(Material::from_pipeline(pipeline))
 }
 
+/// Create a new instanced material, for drawing with [Renderer::draw_instanced] with instances of type `I`.
+///
+/// The same warnings as with [load_material] apply. Additionally, this material can only be used with
+/// [Renderer::draw_instanced] and instance buffers of the same instance type.
+pub fn load_instanced_material<V: AsVertex, I: AsInstance>(
+    backend: &mut dyn RenderingBackend,
+    renderer: &mut Renderer<V>,
+    shader: ShaderSource,
+    params: MaterialParams,
+) -> Result<Material<V>, Error> {
+    let pipeline = make_material_pipeline(
+        shader,
+        &params.preprocessor,
+        params.uniforms,
+        params.textures,
+        |shader, uniforms, textures| {
+            renderer.make_instanced_pipeline::<I>(
+                backend,
+                shader,
+                params.pipeline_params,
+                uniforms,
+                textures,
+            )
+        },
+    )?;
+
+    Ok(Material::from_pipeline(pipeline))
+}
+
+/// Paths of shader files, see [load_material_from_files]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShaderPaths {
+    Glsl { vertex: String, fragment: String },
+    Msl { program: String },
+}
+
+impl ShaderPaths {
+    fn paths(&self) -> Vec<&str> {
+        match self {
+            ShaderPaths::Glsl { vertex, fragment } => vec![vertex, fragment],
+            ShaderPaths::Msl { program } => vec![program],
+        }
+    }
+
+    fn load(&self) -> Result<Vec<String>, Error> {
+        self.paths()
+            .into_iter()
+            .map(|path| {
+                crate::fs::load_string(path).map_err(|kind| Error::FileError {
+                    kind,
+                    path: path.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    fn source<'a>(&self, sources: &'a [String]) -> ShaderSource<'a> {
+        match self {
+            ShaderPaths::Glsl { .. } => ShaderSource::Glsl {
+                vertex: &sources[0],
+                fragment: &sources[1],
+            },
+            ShaderPaths::Msl { .. } => ShaderSource::Msl {
+                program: &sources[0],
+            },
+        }
+    }
+
+    /// Modification times of the files, [None] where they're unavailable (like on the web)
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.paths()
+            .into_iter()
+            .map(|path| {
+                #[cfg(not(target_os = "android"))]
+                let path = match crate::fs::get_pc_assets_folder() {
+                    Some(pc_assets) => format!("{pc_assets}/{path}"),
+                    None => path.to_string(),
+                };
+
+                std::fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .collect()
+    }
+}
+
+/// A material loaded from shader files with [load_material_from_files], which can be reloaded
+/// in place when they change, to iterate on shaders without restarting.
+///
+/// The material itself (and its [GlPipeline]) stays the same after reloading, along with the values
+/// of its uniforms and textures. Only the shader files themselves are watched, not their includes.
+pub struct ReloadableMaterial<V>
+where V: AsVertex {
+    material: Material<V>,
+    paths: ShaderPaths,
+    uniforms: Vec<UniformDesc>,
+    textures: Vec<String>,
+    preprocessor: PreprocessorConfig,
+    modified: Vec<Option<SystemTime>>,
+}
+
+impl<V> ReloadableMaterial<V>
+where V: AsVertex {
+    pub fn material(&self) -> &Material<V> {
+        &self.material
+    }
+
+    pub fn paths(&self) -> &ShaderPaths {
+        &self.paths
+    }
+
+    /// Reload the shader files and recompile the material.
+    ///
+    /// If loading or compilation fails, the error is returned, and the material keeps using
+    /// its previous shader
+    pub fn reload(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        renderer: &mut Renderer<V>,
+    ) -> Result<(), Error> {
+        self.modified = self.paths.modified();
+
+        let sources = self.paths.load()?;
+        make_material_pipeline(
+            self.paths.source(&sources),
+            &self.preprocessor,
+            self.uniforms.clone(),
+            self.textures.clone(),
+            |shader, uniforms, textures| {
+                let pipeline = self.material.pipeline;
+                renderer.replace_pipeline(backend, &pipeline, shader, uniforms, textures)
+            },
+        )
+    }
+
+    /// Reload the material if any of its shader files were modified since the last (re)load.
+    /// Returns whether it was reloaded.
+    ///
+    /// A failed reload isn't retried until the files change again.
+    /// Calling this every frame is fine, it only checks file metadata
+    pub fn reload_if_changed(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        renderer: &mut Renderer<V>,
+    ) -> Result<bool, Error> {
+        if self.paths.modified() == self.modified {
+            return Ok(false);
+        }
+
+        self.reload(backend, renderer)?;
+        Ok(true)
+    }
+}
+
+/// Load a material from shader files (see [crate::fs::load_string]), which can be reloaded later
+/// with [ReloadableMaterial::reload_if_changed].
+///
+/// The same warnings as with [load_material] apply
+pub fn load_material_from_files<V: AsVertex>(
+    backend: &mut dyn RenderingBackend,
+    renderer: &mut Renderer<V>,
+    paths: ShaderPaths,
+    params: MaterialParams,
+) -> Result<ReloadableMaterial<V>, Error> {
+    let modified = paths.modified();
+    let sources = paths.load()?;
+
+    let uniforms = params.uniforms.clone();
+    let textures = params.textures.clone();
+    let preprocessor = params.preprocessor.clone();
+    let material = load_material(backend, renderer, paths.source(&sources), params)?;
+
+    Ok(ReloadableMaterial {
+        material,
+        paths,
+        uniforms,
+        textures,
+        preprocessor,
+        modified,
+    })
+}
+
 /// All following macroquad rendering calls will use the given material.
 ///
 /// ### Attention
@@ -118,8 +487,8 @@ pub fn use_material<V: AsVertex>(renderer: &mut Renderer<V>, material: &Material
 
 /// Check whether the provided renderer contains the specified material.
 ///
-/// It's highly important to only use materials given by the same renderers, to avoid
-/// panics
+/// It's highly important to only use materials given by the same renderers,
+/// materials of other renderers are ignored
 pub fn has_material<V: AsVertex>(renderer: &mut Renderer<V>, material: &Material<V>) -> bool {
     renderer.has_pipeline(&material.pipeline)
 }
@@ -134,113 +503,883 @@ pub fn use_default_material(renderer: &mut Renderer) {
     renderer.with_pipeline(None);
 }
 
-// I'm leaving this for now, could be a separate crate feature in the future.
-// mod preprocessor {
-//     type IncludeFilename = String;
-//     type IncludeContent = String;
-
-//     #[derive(Default, Debug, Clone)]
-//     pub struct PreprocessorConfig {
-//         pub includes: Vec<(IncludeFilename, IncludeContent)>,
-//     }
-
-//     impl PreprocessorConfig {}
-
-//     pub fn preprocess_shader(source: &str, config: &PreprocessorConfig) -> String {
-//         let mut res = source.chars().collect::<Vec<_>>();
-
-//         fn find(data: &[char], n: &mut usize, target: &str) -> bool {
-//             if *n >= data.len() {
-//                 return false;
-//             }
-//             let target = target.chars().collect::<Vec<_>>();
-
-//             'outer: for i in *n..data.len() {
-//                 for j in 0..target.len() {
-//                     if data[i + j] != target[j] {
-//                         *n += 1;
-//                         continue 'outer;
-//                     }
-//                 }
-//                 return true;
-//             }
-//             false
-//         }
-
-//         fn skip_character(data: &[char], n: &mut usize, target: char) {
-//             while *n < data.len() && data[*n] == target {
-//                 *n += 1;
-//             }
-//         }
-
-//         let mut i = 0;
-//         while find(&res, &mut i, "#include") {
-//             let directive_start_ix = i;
-//             i += "#include".len();
-//             skip_character(&res, &mut i, ' ');
-//             assert!(res[i] == '\"');
-//             i += 1;
-//             let filename_start_ix = i;
-//             find(&res, &mut i, "\"");
-//             let filename_end_ix = i;
-//             let filename = res[filename_start_ix..filename_end_ix]
-//                 .iter()
-//                 .cloned()
-//                 .collect::<String>();
-
-//             let include_content = config
-//                 .includes
-//                 .iter()
-//                 .find(|(name, _)| name == &filename)
-//                 .unwrap_or_else(|| panic!(
-//                     "Include file {} in not on \"includes\" list",
-//                     filename
-//                 ));
-
-//             let _ = res
-//                 .splice(
-//                     directive_start_ix..filename_end_ix + 1,
-//                     include_content.1.chars(),
-//                 )
-//                 .collect::<Vec<_>>();
-//         }
-
-//         res.into_iter().collect()
-//     }
-
-//     #[test]
-//     fn preprocessor_test() {
-//         let shader_string = r#"
-// #version blah blah
-
-// asd
-// asd
-
-// #include "hello.glsl"
-
-// qwe
-// "#;
-
-//         let preprocessed = r#"
-// #version blah blah
-
-// asd
-// asd
-
-// iii
-// jjj
-
-// qwe
-// "#;
-
-//         let result = preprocess_shader(
-//             shader_string,
-//             &PreprocessorConfig {
-//                 includes: vec![("hello.glsl".to_string(), "iii\njjj".to_string())],
-//                 ..Default::default()
-//             },
-//         );
-
-//         assert_eq!(result, preprocessed);
-//     }
-// }
+/// A small shader preprocessor, which resolves `#include "file"` directives and injects `#define`s.
+///
+/// [load_material] runs it on every shader, so snippets (lighting, noise and so on) can be shared
+/// between materials:
+/// ```ignore
+/// let material = load_material(backend, renderer, shader, MaterialParams {
+///     preprocessor: PreprocessorConfig {
+///         includes: vec![("lighting.glsl".to_string(), LIGHTING.to_string())],
+///         defines: vec![("MAX_LIGHTS".to_string(), "4".to_string())],
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// })?;
+/// ```
+///
+/// Included files can include other files. A file containing `#pragma once` is only included once.
+/// `#include <...>` directives (like `#include <metal_stdlib>`) are left as is.
+///
+/// Lines of the preprocessed source are tracked in a [SourceMap], so shader compilation errors
+/// point to the file and line they come from.
+pub mod preprocessor {
+    use crate::Error;
+
+    type IncludeFilename = String;
+    type IncludeContent = String;
+
+    #[derive(Default, Debug, Clone)]
+    pub struct PreprocessorConfig {
+        /// In-memory includes. They're looked up before the filesystem
+        pub includes: Vec<(IncludeFilename, IncludeContent)>,
+
+        /// Load includes that aren't in [PreprocessorConfig::includes] with [crate::fs::load_string].
+        /// Paths are used as is, not relative to the including file
+        pub filesystem: bool,
+
+        /// `(name, value)` pairs, injected as `#define name value` right after the `#version` directive
+        /// (or at the very top, if there's none)
+        pub defines: Vec<(String, String)>,
+    }
+
+    /// The file and line (starting from 1) a line of preprocessed source comes from
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SourceLocation {
+        pub file: String,
+        pub line: usize,
+    }
+
+    /// Maps lines of preprocessed source back to their files
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    pub struct SourceMap {
+        lines: Vec<SourceLocation>,
+    }
+
+    impl SourceMap {
+        /// Find where a line (starting from 1) of the preprocessed source comes from
+        pub fn locate(&self, line: usize) -> Option<&SourceLocation> {
+            line.checked_sub(1).and_then(|ix| self.lines.get(ix))
+        }
+
+        /// Replace line references of a shader compiler log with the original files and lines.
+        ///
+        /// GLSL compilers report lines as `0:LINE` and Metal as `program_source:LINE`,
+        /// both are rewritten into `FILE:LINE`
+        pub fn map_log(&self, log: &str) -> String {
+            let mut mapped = String::with_capacity(log.len());
+            let mut rest = log;
+
+            while let Some((ix, prefix)) = ["0:", "program_source:"]
+                .iter()
+                .filter_map(|prefix| rest.find(prefix).map(|ix| (ix, *prefix)))
+                .min()
+            {
+                let digits = rest[ix + prefix.len()..]
+                    .bytes()
+                    .take_while(u8::is_ascii_digit)
+                    .count();
+                let inside_word = rest[..ix]
+                    .bytes()
+                    .last()
+                    .is_some_and(|byte| byte.is_ascii_alphanumeric());
+                let location = rest[ix + prefix.len()..ix + prefix.len() + digits]
+                    .parse()
+                    .ok()
+                    .and_then(|line| self.locate(line))
+                    .filter(|_| !inside_word);
+
+                let end = ix + prefix.len() + digits;
+                mapped.push_str(&rest[..ix]);
+                match location {
+                    Some(location) => {
+                        mapped.push_str(&format!("{}:{}", location.file, location.line))
+                    }
+                    None => mapped.push_str(&rest[ix..end]),
+                }
+                rest = &rest[end..];
+            }
+            mapped.push_str(rest);
+
+            mapped
+        }
+    }
+
+    /// A preprocessed shader, along with the origins of its lines
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct Preprocessed {
+        pub source: String,
+        pub source_map: SourceMap,
+    }
+
+    /// Preprocess a shader. Errors and the [SourceMap] refer to the shader itself as `name`
+    pub fn preprocess_shader(
+        name: &str,
+        source: &str,
+        config: &PreprocessorConfig,
+    ) -> Result<Preprocessed, Error> {
+        let mut preprocessor = Preprocessor {
+            config,
+            output: Vec::new(),
+            source_map: SourceMap::default(),
+            stack: Vec::new(),
+            once: Vec::new(),
+        };
+
+        // #version has to be the first directive, so defines go right after it
+        let version = source
+            .lines()
+            .position(|line| !line.trim().is_empty())
+            .filter(|&ix| {
+                source.lines().nth(ix).unwrap().trim_start().starts_with("#version")
+            });
+        let (head, body_start) = match version {
+            Some(ix) => (ix + 1, ix + 1),
+            None => (0, 0),
+        };
+
+        preprocessor.stack.push(name.to_string());
+        for (ix, line) in source.lines().take(head).enumerate() {
+            preprocessor.push_line(line, name, ix + 1);
+        }
+        for (ix, (define, value)) in config.defines.iter().enumerate() {
+            preprocessor.push_line(&format!("#define {} {}", define, value), "<defines>", ix + 1);
+        }
+        preprocessor.process(name, source, body_start)?;
+
+        let mut output = preprocessor.output.join("\n");
+        if source.ends_with('\n') {
+            output.push('\n');
+        }
+
+        Ok(Preprocessed {
+            source: output,
+            source_map: preprocessor.source_map,
+        })
+    }
+
+    struct Preprocessor<'a> {
+        config: &'a PreprocessorConfig,
+        output: Vec<String>,
+        source_map: SourceMap,
+        /// Files being included, to catch recursive includes
+        stack: Vec<String>,
+        /// Files with `#pragma once` which were already included
+        once: Vec<String>,
+    }
+
+    impl Preprocessor<'_> {
+        fn push_line(&mut self, line: &str, file: &str, number: usize) {
+            self.output.push(line.to_string());
+            self.source_map.lines.push(SourceLocation {
+                file: file.to_string(),
+                line: number,
+            });
+        }
+
+        fn process(&mut self, file: &str, source: &str, skip: usize) -> Result<(), Error> {
+            for (ix, line) in source.lines().enumerate().skip(skip) {
+                let error = |message: String| Error::PreprocessorError {
+                    file: file.to_string(),
+                    line: ix + 1,
+                    message,
+                };
+
+                let directive = match line.trim_start().strip_prefix('#') {
+                    Some(directive) => directive.trim_start(),
+                    None => {
+                        self.push_line(line, file, ix + 1);
+                        continue;
+                    }
+                };
+
+                if let Some(pragma) = directive.strip_prefix("pragma") {
+                    if pragma.trim() == "once" {
+                        self.once.push(file.to_string());
+                        continue;
+                    }
+                }
+
+                let Some(include) = directive.strip_prefix("include") else {
+                    self.push_line(line, file, ix + 1);
+                    continue;
+                };
+                let include = include.trim();
+                if include.starts_with('<') {
+                    self.push_line(line, file, ix + 1);
+                    continue;
+                }
+
+                let filename = include
+                    .strip_prefix('"')
+                    .and_then(|include| include.split_once('"'))
+                    .filter(|(_, rest)| {
+                        let rest = rest.trim();
+                        rest.is_empty() || rest.starts_with("//")
+                    })
+                    .map(|(filename, _)| filename)
+                    .ok_or_else(|| error(format!("Malformed include directive: {}", line.trim())))?;
+
+                if self.once.iter().any(|included| included == filename) {
+                    continue;
+                }
+                if self.stack.iter().any(|included| included == filename) {
+                    return Err(error(format!("Recursive include of \"{}\"", filename)));
+                }
+
+                let content = self.load(filename).map_err(error)?;
+                self.stack.push(filename.to_string());
+                self.process(filename, &content, 0)?;
+                self.stack.pop();
+            }
+
+            Ok(())
+        }
+
+        fn load(&self, filename: &str) -> Result<String, String> {
+            if let Some((_, content)) = self
+                .config
+                .includes
+                .iter()
+                .find(|(name, _)| name == filename)
+            {
+                return Ok(content.clone());
+            }
+
+            if !self.config.filesystem {
+                return Err(format!("Include file \"{}\" is not on the includes list", filename));
+            }
+
+            crate::fs::load_string(filename)
+                .map_err(|err| format!("Failed to load include file \"{}\": {:?}", filename, err))
+        }
+    }
+
+    #[test]
+    fn preprocessor_test() {
+        let shader_string = r#"
+#version blah blah
+
+asd
+asd
+
+#include "hello.glsl"
+
+qwe
+"#;
+
+        let preprocessed = r#"
+#version blah blah
+#define LIGHTS 4
+
+asd
+asd
+
+iii
+jjj
+
+qwe
+"#;
+
+        let result = preprocess_shader(
+            "shader",
+            shader_string,
+            &PreprocessorConfig {
+                includes: vec![
+                    (
+                        "hello.glsl".to_string(),
+                        "#pragma once\niii\n#include \"world.glsl\"".to_string(),
+                    ),
+                    (
+                        "world.glsl".to_string(),
+                        "#include \"hello.glsl\"\njjj".to_string(),
+                    ),
+                ],
+                defines: vec![("LIGHTS".to_string(), "4".to_string())],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(result.source, preprocessed);
+
+        let location = |file: &str, line| SourceLocation {
+            file: file.to_string(),
+            line,
+        };
+        assert_eq!(result.source_map.locate(3), Some(&location("<defines>", 1)));
+        assert_eq!(result.source_map.locate(9), Some(&location("world.glsl", 2)));
+        assert_eq!(result.source_map.locate(11), Some(&location("shader", 9)));
+        assert_eq!(
+            result.source_map.map_log("0:9(3): error: `x' undeclared"),
+            "world.glsl:2(3): error: `x' undeclared"
+        );
+    }
+
+    #[test]
+    fn preprocessor_errors() {
+        let config = PreprocessorConfig {
+            includes: vec![("a.glsl".to_string(), "\n#include \"a.glsl\"".to_string())],
+            ..Default::default()
+        };
+
+        let error = preprocess_shader("shader", "#include \"a.glsl\"", &config).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::PreprocessorError { ref file, line: 2, .. } if file == "a.glsl"
+        ));
+
+        let error = preprocess_shader("shader", "x\n#include \"b.glsl\"", &config).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::PreprocessorError { ref file, line: 2, .. } if file == "shader"
+        ));
+    }
+}
+
+/// Discovery of uniforms and textures from shader sources, so they don't have to be declared twice:
+/// in the shader and in [MaterialParams].
+///
+/// [load_material] reflects every shader after [preprocessing](preprocessor). Uniforms and textures
+/// given in [MaterialParams] override the reflected ones with the same name, and ones
+/// the reflection couldn't find are added after them.
+///
+/// With GLSL, every `uniform` declaration of both shaders is picked up (`sampler2D` and
+/// `samplerCube` ones as textures). With Metal, uniforms are the fields of the `Uniforms` struct,
+/// and textures are the `[[texture(n)]]` arguments, ordered by `n`.
+/// The built-in uniforms and the `Texture` sampler are skipped.
+pub mod reflection {
+    use std::collections::HashMap;
+
+    use miniquad::{UniformDesc, UniformType};
+
+    use crate::logging::warn;
+
+    /// Uniforms and textures found in a shader
+    #[derive(Debug, Clone, Default)]
+    pub struct Reflection {
+        pub uniforms: Vec<UniformDesc>,
+        pub textures: Vec<String>,
+    }
+
+    impl Reflection {
+        /// Merge explicitly declared uniforms and textures into the reflected ones.
+        ///
+        /// Explicit declarations replace reflected ones with the same name, and the rest
+        /// are added at the end, so the order of the shader is kept
+        pub fn merge(
+            mut self,
+            uniforms: Vec<UniformDesc>,
+            textures: Vec<String>,
+        ) -> (Vec<UniformDesc>, Vec<String>) {
+            for uniform in uniforms {
+                match self.uniforms.iter_mut().find(|found| found.name == uniform.name) {
+                    Some(found) => *found = uniform,
+                    None => self.uniforms.push(uniform),
+                }
+            }
+            for texture in textures {
+                if !self.textures.contains(&texture) {
+                    self.textures.push(texture);
+                }
+            }
+
+            (self.uniforms, self.textures)
+        }
+
+        fn add_uniform(&mut self, uniform: UniformDesc) {
+            let builtin = super::super::renderer::shader::uniforms()
+                .iter()
+                .any(|(name, _)| *name == uniform.name);
+
+            if !builtin && !self.uniforms.iter().any(|found| found.name == uniform.name) {
+                self.uniforms.push(uniform);
+            }
+        }
+
+        fn add_texture(&mut self, texture: String) {
+            if texture != "Texture" && !self.textures.contains(&texture) {
+                self.textures.push(texture);
+            }
+        }
+    }
+
+    /// Find uniforms and samplers of GLSL shaders
+    pub fn reflect_glsl(sources: &[&str]) -> Reflection {
+        let mut reflection = Reflection::default();
+
+        for source in sources {
+            let source = strip_comments(source);
+            let defines = defines(&source);
+            // Directives aren't terminated with semicolons, so they'd end up in the next statement
+            let code = source
+                .lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            for statement in code.split([';', '{', '}']) {
+                let statement = statement
+                    .replace(',', " , ")
+                    .replace('[', " [ ")
+                    .replace(']', " ] ");
+                let mut tokens = statement.split_whitespace().peekable();
+
+                if tokens.next() != Some("uniform") {
+                    continue;
+                }
+                while tokens
+                    .next_if(|token| matches!(*token, "lowp" | "mediump" | "highp"))
+                    .is_some()
+                {}
+                let Some(ty) = tokens.next() else {
+                    continue;
+                };
+                let declarators = tokens.collect::<Vec<_>>();
+
+                for declarator in declarators.split(|token| *token == ",") {
+                    let (name, count) = match declarator {
+                        [name] => (*name, Some(1)),
+                        [name, "[", size, "]"] => {
+                            let size = defines.get(*size).map_or(*size, |value| value.as_str());
+                            (*name, size.parse().ok())
+                        }
+                        _ => continue,
+                    };
+
+                    if matches!(ty, "sampler2D" | "samplerCube") {
+                        reflection.add_texture(name.to_string());
+                        continue;
+                    }
+
+                    match (glsl_uniform_type(ty), count) {
+                        (Some(uniform_type), Some(count)) => reflection
+                            .add_uniform(UniformDesc::new(name, uniform_type).array(count)),
+                        _ => warn!("Can't reflect uniform {} of type {}", name, ty),
+                    }
+                }
+            }
+        }
+
+        reflection
+    }
+
+    /// Find uniforms (fields of the `Uniforms` struct) and textures of a Metal shader
+    pub fn reflect_msl(program: &str) -> Reflection {
+        let mut reflection = Reflection::default();
+        let program = strip_comments(program);
+        let defines = defines(&program);
+
+        let body = program
+            .find("struct Uniforms")
+            .and_then(|start| {
+                let body = &program[start..];
+                Some(&body[body.find('{')? + 1..body.find('}')?])
+            })
+            .unwrap_or_default();
+
+        for field in body.split(';') {
+            let field = field.replace('[', " [ ").replace(']', " ] ");
+            let (ty, name, count) = match field.split_whitespace().collect::<Vec<_>>()[..] {
+                [ty, name] => (ty, name, Some(1)),
+                [ty, name, "[", size, "]"] => {
+                    let size = defines.get(size).map_or(size, |value| value.as_str());
+                    (ty, name, size.parse().ok())
+                }
+                _ => continue,
+            };
+
+            match (msl_uniform_type(ty), count) {
+                (Some(uniform_type), Some(count)) => {
+                    reflection.add_uniform(UniformDesc::new(name, uniform_type).array(count))
+                }
+                _ => warn!("Can't reflect uniform {} of type {}", name, ty),
+            }
+        }
+
+        // The first texture is the one drawn with the material
+        let mut textures = program
+            .match_indices("[[texture(")
+            .filter_map(|(ix, pattern)| {
+                let name = program[..ix]
+                    .trim_end()
+                    .rsplit(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .next()?;
+                let index = program[ix + pattern.len()..].split(')').next()?;
+                Some((index.trim().parse::<usize>().ok()?, name.to_string()))
+            })
+            .filter(|(index, _)| *index > 0)
+            .collect::<Vec<_>>();
+        textures.sort_by_key(|(index, _)| *index);
+
+        for (_, texture) in textures {
+            reflection.add_texture(texture);
+        }
+
+        reflection
+    }
+
+    fn glsl_uniform_type(ty: &str) -> Option<UniformType> {
+        Some(match ty {
+            "float" => UniformType::Float1,
+            "vec2" => UniformType::Float2,
+            "vec3" => UniformType::Float3,
+            "vec4" => UniformType::Float4,
+            "int" => UniformType::Int1,
+            "ivec2" => UniformType::Int2,
+            "ivec3" => UniformType::Int3,
+            "ivec4" => UniformType::Int4,
+            "mat4" => UniformType::Mat4,
+            _ => return None,
+        })
+    }
+
+    fn msl_uniform_type(ty: &str) -> Option<UniformType> {
+        // Packed vectors have the same size, just without the alignment
+        Some(match ty.strip_prefix("packed_").unwrap_or(ty) {
+            "float" => UniformType::Float1,
+            "float2" => UniformType::Float2,
+            "float3" => UniformType::Float3,
+            "float4" => UniformType::Float4,
+            "int" | "uint" => UniformType::Int1,
+            "int2" | "uint2" => UniformType::Int2,
+            "int3" | "uint3" => UniformType::Int3,
+            "int4" | "uint4" => UniformType::Int4,
+            "float4x4" => UniformType::Mat4,
+            _ => return None,
+        })
+    }
+
+    /// Values of `#define NAME VALUE` directives, to resolve array sizes
+    fn defines(source: &str) -> HashMap<&str, String> {
+        source
+            .lines()
+            .filter_map(|line| {
+                let directive = line.trim_start().strip_prefix('#')?.trim_start();
+                let mut tokens = directive.strip_prefix("define")?.split_whitespace();
+                Some((tokens.next()?, tokens.collect::<Vec<_>>().join(" ")))
+            })
+            .collect()
+    }
+
+    /// Replace comments with whitespace, keeping line breaks
+    fn strip_comments(source: &str) -> String {
+        let mut stripped = String::with_capacity(source.len());
+        let mut rest = source;
+
+        while let Some(start) = rest.find("//").into_iter().chain(rest.find("/*")).min() {
+            stripped.push_str(&rest[..start]);
+            let (end, comment_end) = if rest[start..].starts_with("//") {
+                (rest[start..].find('\n').map_or(rest.len(), |end| start + end), 0)
+            } else {
+                (rest[start..].find("*/").map_or(rest.len(), |end| start + end), 2)
+            };
+
+            let comment = &rest[start..end];
+            stripped.extend(comment.chars().filter(|c| *c == '\n'));
+            stripped.push(' ');
+            rest = &rest[(end + comment_end).min(rest.len())..];
+        }
+        stripped.push_str(rest);
+
+        stripped
+    }
+
+    #[test]
+    fn reflection_test() {
+        let vertex = r#"#version 100
+        #define LIGHTS 4
+        attribute vec3 position;
+        uniform mat4 Model;
+        uniform mat4 Projection;
+        uniform highp vec2 Offset, Scale; // uniform float Commented;
+        uniform vec4 Lights[LIGHTS];
+        /* uniform float Commented; */
+        void main() {}"#;
+        let fragment = r#"#version 100
+        precision mediump float;
+        uniform sampler2D Texture;
+        uniform sampler2D Normals;
+        uniform vec2 Offset;
+        uniform int Mode;
+        void main() {}"#;
+
+        let reflection = reflect_glsl(&[vertex, fragment]);
+        let uniforms = reflection
+            .uniforms
+            .iter()
+            .map(|uniform| {
+                let size = uniform.uniform_type.size();
+                (uniform.name.as_str(), size, uniform.array_count)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uniforms,
+            [("Offset", 8, 1), ("Scale", 8, 1), ("Lights", 16, 4), ("Mode", 4, 1)]
+        );
+        assert_eq!(reflection.textures, ["Normals"]);
+
+        // Explicit declarations override reflected ones
+        let (uniforms, textures) = reflection.merge(
+            vec![
+                UniformDesc::new("Lights", UniformType::Float4).array(8),
+                UniformDesc::new("Extra", UniformType::Float1),
+            ],
+            vec!["Extra".to_string()],
+        );
+        let uniforms = uniforms
+            .iter()
+            .map(|uniform| (uniform.name.as_str(), uniform.array_count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uniforms,
+            [("Offset", 1), ("Scale", 1), ("Lights", 8), ("Mode", 1), ("Extra", 1)]
+        );
+        assert_eq!(textures, ["Normals", "Extra"]);
+
+        let program = r#"
+        struct Uniforms
+        {
+            float4x4 Projection;
+            float4x4 Model;
+            float4 _Time;
+            float4 Tint;
+            float Weights[3];
+        };
+
+        fragment float4 fragmentShader(
+            RasterizerData in [[stage_in]],
+            texture2d<float> tex [[texture(0)]],
+            texture2d<float> Mask [[texture(2)]],
+            texture2d<float> Normals [[texture(1)]])
+        {}"#;
+
+        let reflection = reflect_msl(program);
+        let uniforms = reflection
+            .uniforms
+            .iter()
+            .map(|uniform| (uniform.name.as_str(), uniform.array_count))
+            .collect::<Vec<_>>();
+        assert_eq!(uniforms, [("Tint", 1), ("Weights", 3)]);
+        assert_eq!(reflection.textures, ["Normals", "Mask"]);
+
+        // `Texture` is reserved for the texture drawn with the material
+        let mut backend = crate::graphics::backend::SoftwareBackend::new(1, 1);
+        let mut renderer: crate::graphics::Renderer =
+            crate::graphics::Renderer::new(&mut backend, 100, 100);
+        let material = super::load_material(
+            &mut backend,
+            &mut renderer,
+            miniquad::ShaderSource::Glsl {
+                vertex: "",
+                fragment: "",
+            },
+            super::MaterialParams {
+                textures: vec!["Texture".to_string()],
+                ..Default::default()
+            },
+        );
+        assert!(matches!(material, Err(crate::Error::ReservedName(_))));
+    }
+}
+
+#[test]
+fn material_hot_reload() {
+    use super::backend::{Command, RecordingBackend, SoftwareBackend};
+    use super::{test_renderer, Vertex};
+    use crate::color::WHITE;
+
+    let dir = std::env::temp_dir().join(format!("macroquad_hot_reload_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let vertex = dir.join("shader.vert");
+    let fragment = dir.join("shader.frag");
+    std::fs::write(&vertex, "void main() {}").unwrap();
+    std::fs::write(&fragment, "void main() {}").unwrap();
+
+    let (mut backend, mut renderer, _target) = test_renderer(1);
+
+    let mut material = load_material_from_files(
+        &mut backend,
+        &mut renderer,
+        ShaderPaths::Glsl {
+            vertex: vertex.to_str().unwrap().to_string(),
+            fragment: fragment.to_str().unwrap().to_string(),
+        },
+        MaterialParams {
+            uniforms: vec![UniformDesc::new("Scale", miniquad::UniformType::Float1)],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    material.material().set_uniform(&mut renderer, "Scale", 2.0f32);
+    assert!(!material.reload_if_changed(&mut backend, &mut renderer).unwrap());
+
+    // Draw with the material, returning the pipeline and the uniforms it was drawn with
+    let draw = |backend: &mut RecordingBackend<SoftwareBackend>,
+                renderer: &mut Renderer,
+                material: &Material<Vertex>| {
+        use_material(renderer, material);
+        renderer.push_geometry(&[Vertex::new(0., 0., 0., 0., 0., WHITE); 3], &[0, 1, 2]);
+        backend.clear_commands();
+        renderer.draw(backend, glam::Mat4::IDENTITY);
+
+        let pipeline = backend.commands().iter().find_map(|command| match command {
+            Command::ApplyPipeline { pipeline } => Some(*pipeline),
+            _ => None,
+        });
+        let uniforms = backend.commands().iter().find_map(|command| match command {
+            Command::ApplyUniforms { bytes } => Some(bytes.clone()),
+            _ => None,
+        });
+        (pipeline.unwrap(), uniforms.unwrap())
+    };
+
+    let (pipeline, _) = draw(&mut backend, &mut renderer, material.material());
+
+    // The material is the same, but its pipeline is new, with the same uniform values
+    material.reload(&mut backend, &mut renderer).unwrap();
+    let (reloaded, uniforms) = draw(&mut backend, &mut renderer, material.material());
+    assert_ne!(reloaded, pipeline);
+    assert!(uniforms.ends_with(&2.0f32.to_ne_bytes()));
+
+    // A failed reload keeps the previous pipeline
+    std::fs::remove_file(&vertex).unwrap();
+    assert!(matches!(
+        material.reload(&mut backend, &mut renderer),
+        Err(Error::FileError { .. })
+    ));
+    assert!(has_material(&mut renderer, material.material()));
+    assert_eq!(draw(&mut backend, &mut renderer, material.material()).0, reloaded);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn material_derive_macros() {
+    use super::backend::Command;
+    use super::{test_renderer, DrawVertex, Vertex};
+    use crate::color::WHITE;
+    use glam::{Vec2, Vec3, Vec4};
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq, super::AsVertex, DrawVertex)]
+    struct DerivedVertex {
+        position: Vec3,
+        #[vertex(name = "texcoord")]
+        uv: Vec2,
+        #[vertex(name = "color0")]
+        color: [u8; 4],
+        normal: glam::Vec4,
+    }
+
+    let attributes = |attributes: Vec<miniquad::VertexAttribute>| {
+        attributes
+            .into_iter()
+            .map(|attribute| (attribute.name, attribute.format))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        attributes(DerivedVertex::attributes()),
+        attributes(Vertex::attributes())
+    );
+
+    let vertex = DerivedVertex {
+        position: Vec3::new(1., 2., 3.),
+        uv: Vec2::ZERO,
+        color: [255; 4],
+        normal: glam::Vec4::ZERO,
+    };
+    let translation = glam::Mat4::from_translation(Vec3::ONE);
+    assert_eq!(
+        vertex.transformed(&translation).map(|vertex| vertex.position),
+        Some(Vec3::new(2., 3., 4.))
+    );
+    assert_eq!(
+        DerivedVertex::from_parts(Vec3::new(1., 2., 3.), Vec2::ZERO, WHITE),
+        vertex
+    );
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Debug, PartialEq, super::AsVertex, DrawVertex)]
+    struct FlatVertex {
+        position: [f32; 2],
+        #[vertex(name = "color0")]
+        color: Vec4,
+        layer: f32,
+    }
+    assert_eq!(
+        FlatVertex::from_parts(Vec3::new(1., 2., 3.), Vec2::ONE, WHITE),
+        FlatVertex {
+            position: [1., 2.],
+            color: Vec4::ONE,
+            layer: 0.,
+        }
+    );
+
+    // The drawing helpers can build derived vertices
+    let (mut software, mut renderer, target) = test_renderer::<DerivedVertex>(1);
+    crate::draw::draw_rectangle(&mut renderer, -1., -1., 2., 2., crate::color::RED);
+    renderer.draw(&mut software, glam::Mat4::IDENTITY);
+    let image = crate::texture::Image::from_texture(&mut software, &target.texture);
+    assert_eq!(image.get_image_data(), [<[u8; 4]>::from(crate::color::RED)]);
+
+    #[derive(super::Uniforms)]
+    struct Light {
+        #[uniform(name = "LightColor")]
+        color: Vec4,
+        intensity: f32,
+        offsets: [Vec2; 2],
+    }
+
+    let descs = Light::uniforms()
+        .into_iter()
+        .map(|desc| (desc.name, desc.uniform_type.size(), desc.array_count))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        descs,
+        [
+            ("LightColor".to_string(), 16, 1),
+            ("intensity".to_string(), 4, 1),
+            ("offsets".to_string(), 8, 2)
+        ]
+    );
+
+    let (mut backend, mut renderer, _target) = test_renderer(1);
+
+    let material = load_material(
+        &mut backend,
+        &mut renderer,
+        ShaderSource::Glsl {
+            vertex: "",
+            fragment: "",
+        },
+        MaterialParams {
+            uniforms: Light::uniforms(),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    material.set_uniforms(
+        &mut renderer,
+        &Light {
+            color: Vec4::ONE,
+            intensity: 2.,
+            offsets: [Vec2::X, Vec2::Y],
+        },
+    );
+
+    use_material(&mut renderer, &material);
+    renderer.push_geometry(&[Vertex::new(0., 0., 0., 0., 0., WHITE); 3], &[0, 1, 2]);
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let uniforms = backend.commands().iter().find_map(|command| match command {
+        Command::ApplyUniforms { bytes } => Some(bytes.clone()),
+        _ => None,
+    });
+    let expected: Vec<u8> = [1., 1., 1., 1., 2., 1., 0., 0., 1.]
+        .iter()
+        .flat_map(|value: &f32| value.to_ne_bytes())
+        .collect();
+    assert!(uniforms.unwrap().ends_with(&expected));
+}
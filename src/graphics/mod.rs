@@ -9,14 +9,50 @@ use miniquad::{VertexAttribute, VertexFormat};
 mod renderer;
 pub use renderer::*;
 
+mod instance;
+pub use instance::*;
+
+mod stats;
+pub use stats::*;
+
+mod command_buffer;
+pub use command_buffer::*;
+
 pub mod camera;
 pub use camera::{Camera, Camera2D, Camera3D};
 
 pub mod material;
-pub use material::{use_default_material, use_material, Material, MaterialParams};
+pub use material::{
+    use_default_material, use_material, Material, MaterialParams, OwnedMaterial, Uniforms,
+};
+
+pub mod postprocess;
+pub use postprocess::{Effect, PostProcessChain};
+
+#[cfg(any(test, feature = "unstable-software-backend"))]
+pub mod backend;
+
+#[cfg(feature = "cross-compile")]
+pub mod cross;
+
+/// `#[derive(AsVertex)]`, `#[derive(DrawVertex)]` and `#[derive(Uniforms)]`,
+/// see [macroquad_abstractions_macro]
+pub use macroquad_abstractions_macro::{AsVertex, DrawVertex, Uniforms};
 
 /// A vertex trait that you can implement on any type you want to turn into a Vertex.
 ///
+/// Instead of implementing it by hand, it can be derived with `#[derive(AsVertex)]`, which maps
+/// field types to [VertexFormat]s and checks the struct layout:
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Clone, Copy, Debug, PartialEq, AsVertex)]
+/// struct ColorVertex {
+///     position: Vec2,
+///     #[vertex(name = "color0")]
+///     color: [u8; 4],
+/// }
+/// ```
+///
 /// # Safety
 /// The desired type has to be [`repr(C)`], and all its fields have to implement [`ToBytes`],
 /// since it will be casted to bytes in the graphics pipeline after.
@@ -26,6 +62,14 @@ where
 {
     /// Get [`VertexAttribute`]s of this Vertex. This is required when constructing pipelines
     fn attributes() -> Vec<VertexAttribute>;
+
+    /// This vertex with its position transformed by a model matrix, or `None` if it has none.
+    ///
+    /// Used to bake transforms into the geometry, see [Renderer::with_baked_transforms].
+    /// `#[derive(AsVertex)]` implements it for a `position` attribute of 2 or 3 floats.
+    fn transformed(&self, _matrix: &glam::Mat4) -> Option<Self> {
+        None
+    }
 }
 
 #[repr(C)]
@@ -70,13 +114,236 @@ unsafe impl AsVertex for Vertex {
             VertexAttribute::new("normal", VertexFormat::Float4),
         ]
     }
+
+    fn transformed(&self, matrix: &glam::Mat4) -> Option<Self> {
+        Some(Vertex {
+            position: matrix.transform_point3(self.position),
+            ..*self
+        })
+    }
+}
+
+/// A vertex for 2D drawing: a 2D position, texture coordinates and a color.
+///
+/// It's less than half the size of [Vertex], so there's less to upload every frame.
+/// A [Renderer] with this vertex has default pipelines with shaders of its own,
+/// which are picked for any vertex with the same attributes.
+#[repr(C)]
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub struct Vertex2D {
+    pub position: Vec2,
+    pub uv: Vec2,
+    pub color: [u8; 4],
+}
+
+impl Vertex2D {
+    pub fn new(x: f32, y: f32, u: f32, v: f32, color: Color) -> Vertex2D {
+        Vertex2D {
+            position: vec2(x, y),
+            uv: vec2(u, v),
+            color: color.into(),
+        }
+    }
+
+    pub fn new2(position: Vec2, uv: Vec2, color: Color) -> Vertex2D {
+        Vertex2D {
+            position,
+            uv,
+            color: color.into(),
+        }
+    }
+}
+
+unsafe impl AsVertex for Vertex2D {
+    fn attributes() -> Vec<VertexAttribute> {
+        vec![
+            VertexAttribute::new("position", VertexFormat::Float2),
+            VertexAttribute::new("texcoord", VertexFormat::Float2),
+            VertexAttribute::new("color0", VertexFormat::Byte4),
+        ]
+    }
+
+    fn transformed(&self, matrix: &glam::Mat4) -> Option<Self> {
+        Some(Vertex2D {
+            position: matrix.transform_point3(self.position.extend(0.)).truncate(),
+            ..*self
+        })
+    }
+}
+
+/// A vertex the drawing helpers of [crate::draw] can build, from a position,
+/// texture coordinates and a color.
+///
+/// With it, a [Renderer] with a custom vertex type can still draw shapes, textures and text.
+/// It can be derived with `#[derive(DrawVertex)]`, which fills the fields with the `position`,
+/// `texcoord` and `color0` attributes, and leaves the rest to their [Default]:
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Clone, Copy, Debug, PartialEq, AsVertex, DrawVertex)]
+/// struct LitVertex {
+///     position: Vec3,
+///     #[vertex(name = "texcoord")]
+///     uv: Vec2,
+///     #[vertex(name = "color0")]
+///     color: [u8; 4],
+///     light: f32,
+/// }
+/// ```
+pub trait DrawVertex: AsVertex {
+    /// Vertices without a z coordinate, like [Vertex2D], ignore it
+    fn from_parts(position: Vec3, uv: Vec2, color: Color) -> Self;
+}
+
+impl DrawVertex for Vertex {
+    fn from_parts(position: Vec3, uv: Vec2, color: Color) -> Self {
+        Vertex::new2(position, uv, color)
+    }
+}
+
+impl DrawVertex for Vertex2D {
+    fn from_parts(position: Vec3, uv: Vec2, color: Color) -> Self {
+        Vertex2D::new2(position.truncate(), uv, color)
+    }
 }
 
-pub struct Mesh<V>
+/// An index type that can be used with [Renderer] and [Mesh]. Implemented for [u16] and [u32]
+pub trait AsIndex
+where
+    Self: Clone + Copy + Debug + PartialEq,
+{
+    /// The [IndexFormat] this type corresponds to
+    const FORMAT: IndexFormat;
+
+    fn to_u32(self) -> u32;
+}
+
+impl AsIndex for u16 {
+    const FORMAT: IndexFormat = IndexFormat::U16;
+
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+}
+
+impl AsIndex for u32 {
+    const FORMAT: IndexFormat = IndexFormat::U32;
+
+    fn to_u32(self) -> u32 {
+        self
+    }
+}
+
+/// The element type of index buffers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IndexFormat {
+    /// 16-bit indices. Only 65536 vertices can be addressed in a single draw call
+    #[default]
+    U16,
+    /// 32-bit indices. Not available on WebGL1 without the `OES_element_index_uint` extension
+    U32,
+}
+
+impl IndexFormat {
+    /// Size of a single index in bytes
+    pub const fn size(self) -> usize {
+        match self {
+            IndexFormat::U16 => 2,
+            IndexFormat::U32 => 4,
+        }
+    }
+
+    /// The maximum amount of vertices a single draw call can address with this format
+    pub const fn max_vertices(self) -> usize {
+        match self {
+            IndexFormat::U16 => u16::MAX as usize + 1,
+            // Saturating, for targets where usize is 32 bits
+            IndexFormat::U32 => (u32::MAX as usize).saturating_add(1),
+        }
+    }
+}
+
+pub struct Mesh<V, I = u16>
 where
     V: AsVertex,
+    I: AsIndex,
 {
     pub vertices: Vec<V>,
-    pub indices: Vec<u16>,
+    pub indices: Vec<I>,
     pub texture: Option<miniquad::TextureId>,
 }
+
+/// A mesh, whose vertices and indices are uploaded to the GPU once, into immutable buffers.
+///
+/// Unlike [Mesh], it isn't copied into the batch buffers on every draw, so it's a good fit for geometry
+/// that never changes (static level geometry, imported models). Submit it with
+/// [Renderer::draw_static_mesh]; the current model matrix, pipeline and render pass of the renderer apply.
+///
+/// The buffers aren't freed automatically, use [StaticMesh::delete] when the mesh isn't needed anymore.
+#[derive(Debug, PartialEq)]
+pub struct StaticMesh<V>
+where
+    V: AsVertex,
+{
+    vertex_buffer: miniquad::BufferId,
+    index_buffer: miniquad::BufferId,
+    indices_count: usize,
+    pub texture: Option<miniquad::TextureId>,
+    _m: std::marker::PhantomData<V>,
+}
+
+impl<V> StaticMesh<V>
+where
+    V: AsVertex,
+{
+    pub fn new<I: AsIndex>(
+        backend: &mut dyn miniquad::RenderingBackend,
+        vertices: &[V],
+        indices: &[I],
+        texture: Option<miniquad::TextureId>,
+    ) -> Self {
+        let vertex_buffer = backend.new_buffer(
+            miniquad::BufferType::VertexBuffer,
+            miniquad::BufferUsage::Immutable,
+            miniquad::BufferSource::slice(vertices),
+        );
+        let index_buffer = backend.new_buffer(
+            miniquad::BufferType::IndexBuffer,
+            miniquad::BufferUsage::Immutable,
+            miniquad::BufferSource::slice(indices),
+        );
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            indices_count: indices.len(),
+            texture,
+            _m: std::marker::PhantomData,
+        }
+    }
+
+    /// Upload a [Mesh] to the GPU
+    pub fn from_mesh<I: AsIndex>(
+        backend: &mut dyn miniquad::RenderingBackend,
+        mesh: &Mesh<V, I>,
+    ) -> Self {
+        Self::new(backend, &mesh.vertices, &mesh.indices, mesh.texture)
+    }
+
+    pub const fn vertex_buffer(&self) -> miniquad::BufferId {
+        self.vertex_buffer
+    }
+
+    pub const fn index_buffer(&self) -> miniquad::BufferId {
+        self.index_buffer
+    }
+
+    pub const fn indices_count(&self) -> usize {
+        self.indices_count
+    }
+
+    /// Delete the GPU buffers of this mesh
+    pub fn delete(self, backend: &mut dyn miniquad::RenderingBackend) {
+        backend.delete_buffer(self.vertex_buffer);
+        backend.delete_buffer(self.index_buffer);
+    }
+}
@@ -0,0 +1,834 @@
+//! Post-processing: a chain of full-screen passes, applied to an already rendered image.
+//!
+//! ```ignore
+//! let mut post_process = PostProcessChain::new(vec![
+//!     Effect::Bloom { threshold: 0.8, intensity: 1.0, radius: 8.0 },
+//!     Effect::Vignette { intensity: 0.5, radius: 0.75, softness: 0.45 },
+//! ]);
+//!
+//! // Render the scene into `scene`, then draw it to the screen with all effects applied
+//! renderer.with_render_pass(Some(scene.render_pass.render_pass));
+//! // ...
+//! renderer.draw(backend, projection);
+//! post_process.apply(backend, renderer, scene.texture, None)?;
+//! ```
+
+use std::collections::HashMap;
+
+use miniquad::{
+    Backend, RenderPass, RenderingBackend, ShaderSource, TextureId, UniformDesc,
+    UniformType,
+};
+
+use crate::{
+    color::{Color, WHITE},
+    texture::{new_render_target, RenderTarget},
+    Error,
+};
+
+use super::{
+    material::{load_material, MaterialParams},
+    BlendMode, Material, Renderer, Vertex,
+};
+
+/// A post-processing effect of a [PostProcessChain]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// A separable gaussian blur, `radius` is in pixels
+    GaussianBlur { radius: f32 },
+    /// Parts of the image brighter than `threshold` (from 0 to 1) are blurred by `radius` pixels,
+    /// and added back on top of the image, multiplied by `intensity`.
+    /// The blur is done at half resolution
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        radius: f32,
+    },
+    /// Color adjustments: the color is multiplied by `tint`, then `brightness` is added to it,
+    /// and finally `contrast` and `saturation` are applied (1 keeps the color as is)
+    ColorGrading {
+        tint: Color,
+        brightness: f32,
+        contrast: f32,
+        saturation: f32,
+    },
+    /// Darkens the image towards its corners. Pixels further than `radius` from the center
+    /// (in texture coordinates) are darkened by `intensity`, with a `softness` wide transition
+    Vignette {
+        intensity: f32,
+        radius: f32,
+        softness: f32,
+    },
+    /// An old CRT monitor look: the image is bent by `curvature`, and crossed by `scanline_count`
+    /// horizontal scanlines, which darken it by `scanline_intensity`
+    CrtScanlines {
+        curvature: f32,
+        scanline_intensity: f32,
+        scanline_count: f32,
+    },
+    /// A custom full-screen pass. The material receives the image as `Texture`, and is drawn over
+    /// a quad with texture coordinates from 0 to 1 (see [VERTEX] for a suitable vertex shader).
+    ///
+    /// It has to be created on the same renderer the chain is applied with
+    Custom(Material<Vertex>),
+}
+
+/// Built-in full-screen passes, effects consist of one or more of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Pass {
+    Blur,
+    Threshold,
+    BloomCombine,
+    ColorGrading,
+    Vignette,
+    CrtScanlines,
+}
+
+impl Pass {
+    fn shader(self, backend: Backend) -> ShaderSource<'static> {
+        match backend {
+            Backend::OpenGl => ShaderSource::Glsl {
+                vertex: VERTEX,
+                fragment: match self {
+                    Pass::Blur => shader::BLUR,
+                    Pass::Threshold => shader::THRESHOLD,
+                    Pass::BloomCombine => shader::BLOOM_COMBINE,
+                    Pass::ColorGrading => shader::COLOR_GRADING,
+                    Pass::Vignette => shader::VIGNETTE,
+                    Pass::CrtScanlines => shader::CRT_SCANLINES,
+                },
+            },
+            Backend::Metal => ShaderSource::Msl {
+                program: match self {
+                    Pass::Blur => shader::METAL_BLUR,
+                    Pass::Threshold => shader::METAL_THRESHOLD,
+                    Pass::BloomCombine => shader::METAL_BLOOM_COMBINE,
+                    Pass::ColorGrading => shader::METAL_COLOR_GRADING,
+                    Pass::Vignette => shader::METAL_VIGNETTE,
+                    Pass::CrtScanlines => shader::METAL_CRT_SCANLINES,
+                },
+            },
+        }
+    }
+
+    fn uniforms(self) -> Vec<UniformDesc> {
+        let uniforms: &[(&str, UniformType)] = match self {
+            Pass::Blur => &[("Direction", UniformType::Float2)],
+            Pass::Threshold => &[("Threshold", UniformType::Float1)],
+            Pass::BloomCombine => &[("Intensity", UniformType::Float1)],
+            Pass::ColorGrading => &[
+                ("Tint", UniformType::Float4),
+                ("Brightness", UniformType::Float1),
+                ("Contrast", UniformType::Float1),
+                ("Saturation", UniformType::Float1),
+            ],
+            Pass::Vignette => &[
+                ("Intensity", UniformType::Float1),
+                ("Radius", UniformType::Float1),
+                ("Softness", UniformType::Float1),
+            ],
+            Pass::CrtScanlines => &[
+                ("Curvature", UniformType::Float1),
+                ("ScanlineIntensity", UniformType::Float1),
+                ("ScanlineCount", UniformType::Float1),
+            ],
+        };
+
+        uniforms
+            .iter()
+            .map(|&(name, kind)| UniformDesc::new(name, kind))
+            .collect()
+    }
+
+    fn textures(self) -> Vec<String> {
+        match self {
+            Pass::BloomCombine => vec!["Bloom".to_string()],
+            _ => vec![],
+        }
+    }
+}
+
+/// Intermediate render targets of a chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Target {
+    /// Outputs of effects, used in turns
+    Ping,
+    Pong,
+    /// The horizontal pass of the blur
+    Scratch,
+    /// Half resolution targets for bloom
+    BloomA,
+    BloomB,
+}
+
+impl Target {
+    fn size(self, (width, height): (u32, u32)) -> (u32, u32) {
+        match self {
+            Target::BloomA | Target::BloomB => ((width / 2).max(1), (height / 2).max(1)),
+            _ => (width, height),
+        }
+    }
+}
+
+/// An ordered chain of post-processing effects.
+///
+/// Intermediate render targets are created on demand, and recreated whenever the size of the
+/// output changes (for example when the window is resized). Materials of the built-in effects
+/// are created on the renderer the first time they're needed, so a chain should always be applied
+/// with the same renderer, and cleaned up with [PostProcessChain::delete].
+#[derive(Debug)]
+pub struct PostProcessChain {
+    /// The effects, applied in order
+    pub effects: Vec<Effect>,
+
+    materials: HashMap<Pass, Material<Vertex>>,
+    targets: HashMap<Target, RenderTarget>,
+    size: (u32, u32),
+}
+
+impl PostProcessChain {
+    pub fn new(effects: Vec<Effect>) -> Self {
+        Self {
+            effects,
+            materials: HashMap::new(),
+            targets: HashMap::new(),
+            size: (0, 0),
+        }
+    }
+
+    /// Apply all effects to the `input` texture, and draw the result into `output`
+    /// (or the screen, if it's [None]).
+    ///
+    /// Passes are drawn immediately, so the scene has to be drawn with [Renderer::draw] before.
+    /// Pending draw calls would be drawn with the first pass, so they're rejected with
+    /// [Error::PendingDrawCalls] instead. The draw state of the renderer is restored afterwards.
+    pub fn apply(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        renderer: &mut Renderer,
+        input: TextureId,
+        output: Option<RenderPass>,
+    ) -> Result<(), Error> {
+        if renderer.draw_calls() > 0 {
+            return Err(Error::PendingDrawCalls);
+        }
+
+        let size = match output {
+            Some(pass) => backend.texture_size(backend.render_pass_color_attachments(pass)[0]),
+            None => {
+                let (width, height) = miniquad::window::screen_size();
+                (width as u32, height as u32)
+            }
+        };
+        self.resize(backend, size);
+
+        let state = renderer.take_state();
+        let result = self.apply_effects(backend, renderer, input, output);
+        renderer.restore_state(state);
+
+        result
+    }
+
+    fn apply_effects(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        renderer: &mut Renderer,
+        input: TextureId,
+        output: Option<RenderPass>,
+    ) -> Result<(), Error> {
+        if self.effects.is_empty() {
+            draw_pass(backend, renderer, None, input, output);
+            return Ok(());
+        }
+
+        let mut source = input;
+        let mut targets = [Target::Ping, Target::Pong];
+
+        for ix in 0..self.effects.len() {
+            let last = ix + 1 == self.effects.len();
+            let destination = if last {
+                output
+            } else {
+                Some(self.target(backend, targets[0]).1)
+            };
+
+            match self.effects[ix].clone() {
+                Effect::GaussianBlur { radius } => {
+                    let scratch = self.target(backend, Target::Scratch);
+                    self.blur(backend, renderer, radius, source, scratch.1, destination)?;
+                }
+                Effect::Bloom {
+                    threshold,
+                    intensity,
+                    radius,
+                } => {
+                    let bloom_a = self.target(backend, Target::BloomA);
+                    let bloom_b = self.target(backend, Target::BloomB);
+
+                    let material = self.material(backend, renderer, Pass::Threshold)?;
+                    material.set_uniform(renderer, "Threshold", threshold);
+                    draw_pass(backend, renderer, Some(&material), source, Some(bloom_a.1));
+
+                    // The blur is done at half resolution, so the radius is halved as well
+                    self.blur(
+                        backend,
+                        renderer,
+                        radius / 2.,
+                        bloom_a.0,
+                        bloom_b.1,
+                        Some(bloom_a.1),
+                    )?;
+
+                    let material = self.material(backend, renderer, Pass::BloomCombine)?;
+                    material.set_uniform(renderer, "Intensity", intensity);
+                    material.set_texture(renderer, "Bloom", &bloom_a.0);
+                    draw_pass(backend, renderer, Some(&material), source, destination);
+                }
+                Effect::ColorGrading {
+                    tint,
+                    brightness,
+                    contrast,
+                    saturation,
+                } => {
+                    let material = self.material(backend, renderer, Pass::ColorGrading)?;
+                    material.set_uniform(renderer, "Tint", tint.to_vec());
+                    material.set_uniform(renderer, "Brightness", brightness);
+                    material.set_uniform(renderer, "Contrast", contrast);
+                    material.set_uniform(renderer, "Saturation", saturation);
+                    draw_pass(backend, renderer, Some(&material), source, destination);
+                }
+                Effect::Vignette {
+                    intensity,
+                    radius,
+                    softness,
+                } => {
+                    let material = self.material(backend, renderer, Pass::Vignette)?;
+                    material.set_uniform(renderer, "Intensity", intensity);
+                    material.set_uniform(renderer, "Radius", radius);
+                    material.set_uniform(renderer, "Softness", softness);
+                    draw_pass(backend, renderer, Some(&material), source, destination);
+                }
+                Effect::CrtScanlines {
+                    curvature,
+                    scanline_intensity,
+                    scanline_count,
+                } => {
+                    let material = self.material(backend, renderer, Pass::CrtScanlines)?;
+                    material.set_uniform(renderer, "Curvature", curvature);
+                    material.set_uniform(renderer, "ScanlineIntensity", scanline_intensity);
+                    material.set_uniform(renderer, "ScanlineCount", scanline_count);
+                    draw_pass(backend, renderer, Some(&material), source, destination);
+                }
+                Effect::Custom(material) => {
+                    draw_pass(backend, renderer, Some(&material), source, destination);
+                }
+            }
+
+            if !last {
+                source = self.target(backend, targets[0]).0;
+                targets.swap(0, 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A two pass gaussian blur: horizontal into `scratch`, then vertical into `output`
+    fn blur(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        renderer: &mut Renderer,
+        radius: f32,
+        input: TextureId,
+        scratch: RenderPass,
+        output: Option<RenderPass>,
+    ) -> Result<(), Error> {
+        let material = self.material(backend, renderer, Pass::Blur)?;
+        let (width, height) = backend.texture_size(input);
+        // The kernel reaches 4 texels in both directions, so it's stretched to the radius
+        let step = radius / 4.;
+
+        material.set_uniform(renderer, "Direction", glam::vec2(step / width as f32, 0.));
+        draw_pass(backend, renderer, Some(&material), input, Some(scratch));
+
+        let scratch_texture = backend.render_pass_color_attachments(scratch)[0];
+        material.set_uniform(renderer, "Direction", glam::vec2(0., step / height as f32));
+        draw_pass(backend, renderer, Some(&material), scratch_texture, output);
+
+        Ok(())
+    }
+
+    /// Get the material of a built-in pass, creating it if needed
+    fn material(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        renderer: &mut Renderer,
+        pass: Pass,
+    ) -> Result<Material<Vertex>, Error> {
+        if let Some(material) = self.materials.get(&pass) {
+            return Ok(material.clone());
+        }
+
+        let shader = pass.shader(backend.info().backend);
+        let material = load_material(
+            backend,
+            renderer,
+            shader,
+            MaterialParams {
+                uniforms: pass.uniforms(),
+                textures: pass.textures(),
+                ..Default::default()
+            },
+        )?;
+        self.materials.insert(pass, material.clone());
+
+        Ok(material)
+    }
+
+    /// Get the texture and the render pass of an intermediate target, creating it if needed
+    fn target(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        target: Target,
+    ) -> (TextureId, RenderPass) {
+        let size = target.size(self.size);
+        let target = self
+            .targets
+            .entry(target)
+            .or_insert_with(|| new_render_target(backend, size.0, size.1));
+
+        (target.texture, target.render_pass.render_pass)
+    }
+
+    /// Delete the intermediate targets if the size of the output has changed
+    fn resize(&mut self, backend: &mut dyn RenderingBackend, size: (u32, u32)) {
+        if self.size == size {
+            return;
+        }
+
+        for (_, target) in self.targets.drain() {
+            target.delete(backend);
+        }
+        self.size = size;
+    }
+
+    /// Delete all materials and intermediate targets of this chain
+    pub fn delete(self, backend: &mut dyn RenderingBackend, renderer: &mut Renderer) {
+        for material in self.materials.values() {
+            renderer.delete_pipeline(*material.pipeline());
+        }
+        for (_, target) in self.targets {
+            target.delete(backend);
+        }
+    }
+}
+
+/// Draw a full-screen quad with the `input` texture into `output`
+fn draw_pass(
+    backend: &mut dyn RenderingBackend,
+    renderer: &mut Renderer,
+    material: Option<&Material<Vertex>>,
+    input: TextureId,
+    output: Option<RenderPass>,
+) {
+    let quad = [
+        Vertex::new(-1., -1., 0., 0., 0., WHITE),
+        Vertex::new(1., -1., 0., 1., 0., WHITE),
+        Vertex::new(1., 1., 0., 1., 1., WHITE),
+        Vertex::new(-1., 1., 0., 0., 1., WHITE),
+    ];
+
+    renderer.with_render_pass(output);
+    renderer.with_pipeline(material.map(|material| *material.pipeline()));
+    renderer.with_blend_mode(BlendMode::Replace);
+    renderer.with_texture(Some(&input));
+    renderer.push_geometry(&quad, &[0, 1, 2, 0, 2, 3]);
+    renderer.draw(backend, glam::Mat4::IDENTITY);
+}
+
+/// The GLSL vertex shader of the built-in effects, which passes texture coordinates as `uv`
+/// to the fragment shader
+pub const VERTEX: &str = r#"#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+
+varying mediump vec2 uv;
+
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1);
+    uv = texcoord;
+}"#;
+
+mod shader {
+    pub const BLUR: &str = r#"#version 100
+    precision mediump float;
+
+    varying mediump vec2 uv;
+
+    uniform sampler2D Texture;
+    uniform vec2 Direction;
+
+    void main() {
+        // 9 taps, merged into 5 with linear filtering
+        vec4 color = texture2D(Texture, uv) * 0.2270270270;
+        color += texture2D(Texture, uv + Direction * 1.3846153846) * 0.3162162162;
+        color += texture2D(Texture, uv - Direction * 1.3846153846) * 0.3162162162;
+        color += texture2D(Texture, uv + Direction * 3.2307692308) * 0.0702702703;
+        color += texture2D(Texture, uv - Direction * 3.2307692308) * 0.0702702703;
+        gl_FragColor = color;
+    }"#;
+
+    pub const THRESHOLD: &str = r#"#version 100
+    precision mediump float;
+
+    varying mediump vec2 uv;
+
+    uniform sampler2D Texture;
+    uniform float Threshold;
+
+    void main() {
+        vec4 color = texture2D(Texture, uv);
+        float brightness = max(color.r, max(color.g, color.b));
+        float contribution = max(brightness - Threshold, 0.0) / max(brightness, 0.0001);
+        gl_FragColor = vec4(color.rgb * contribution, 1.0);
+    }"#;
+
+    pub const BLOOM_COMBINE: &str = r#"#version 100
+    precision mediump float;
+
+    varying mediump vec2 uv;
+
+    uniform sampler2D Texture;
+    uniform sampler2D Bloom;
+    uniform float Intensity;
+
+    void main() {
+        vec4 color = texture2D(Texture, uv);
+        vec3 bloom = texture2D(Bloom, uv).rgb;
+        gl_FragColor = vec4(color.rgb + bloom * Intensity, color.a);
+    }"#;
+
+    pub const COLOR_GRADING: &str = r#"#version 100
+    precision mediump float;
+
+    varying mediump vec2 uv;
+
+    uniform sampler2D Texture;
+    uniform vec4 Tint;
+    uniform float Brightness;
+    uniform float Contrast;
+    uniform float Saturation;
+
+    void main() {
+        vec4 color = texture2D(Texture, uv);
+        vec3 rgb = color.rgb * Tint.rgb + Brightness;
+        rgb = (rgb - 0.5) * Contrast + 0.5;
+        float luma = dot(rgb, vec3(0.299, 0.587, 0.114));
+        rgb = mix(vec3(luma), rgb, Saturation);
+        gl_FragColor = vec4(clamp(rgb, 0.0, 1.0), color.a);
+    }"#;
+
+    pub const VIGNETTE: &str = r#"#version 100
+    precision mediump float;
+
+    varying mediump vec2 uv;
+
+    uniform sampler2D Texture;
+    uniform float Intensity;
+    uniform float Radius;
+    uniform float Softness;
+
+    void main() {
+        vec4 color = texture2D(Texture, uv);
+        float vignette = 1.0 - smoothstep(Radius - Softness, Radius, distance(uv, vec2(0.5)));
+        gl_FragColor = vec4(color.rgb * mix(1.0, vignette, Intensity), color.a);
+    }"#;
+
+    pub const CRT_SCANLINES: &str = r#"#version 100
+    precision mediump float;
+
+    varying mediump vec2 uv;
+
+    uniform sampler2D Texture;
+    uniform float Curvature;
+    uniform float ScanlineIntensity;
+    uniform float ScanlineCount;
+
+    void main() {
+        vec2 centered = uv * 2.0 - 1.0;
+        centered += centered * centered.yx * centered.yx * Curvature;
+        vec2 warped = centered * 0.5 + 0.5;
+
+        if (warped.x < 0.0 || warped.x > 1.0 || warped.y < 0.0 || warped.y > 1.0) {
+            gl_FragColor = vec4(0.0, 0.0, 0.0, 1.0);
+            return;
+        }
+
+        vec4 color = texture2D(Texture, warped);
+        float scanline = 0.5 + 0.5 * sin(warped.y * ScanlineCount * 6.2831853);
+        gl_FragColor = vec4(color.rgb * (1.0 - ScanlineIntensity * (1.0 - scanline)), color.a);
+    }"#;
+
+    /// The common part of Metal shaders, with the uniforms of the effect
+    macro_rules! metal {
+        ($uniforms:literal, $fragment:literal) => {
+            concat!(
+                r#"
+#include <metal_stdlib>
+    using namespace metal;
+
+    struct Uniforms
+    {
+        float4x4 Projection;
+        float4x4 Model;
+        float4 _Time;
+"#,
+                $uniforms,
+                r#"
+    };
+
+    struct Vertex
+    {
+        float3 position    [[attribute(0)]];
+        float2 texcoord    [[attribute(1)]];
+    };
+
+    struct RasterizerData
+    {
+        float4 position [[position]];
+        float2 uv [[user(locn0)]];
+    };
+
+    vertex RasterizerData vertexShader(Vertex v [[stage_in]], constant Uniforms& uniforms [[buffer(0)]])
+    {
+        RasterizerData out;
+
+        out.position = uniforms.Projection * uniforms.Model * float4(v.position, 1);
+        out.uv = v.texcoord;
+
+        return out;
+    }
+"#,
+                $fragment
+            )
+        };
+    }
+
+    pub const METAL_BLUR: &str = metal!(
+        "        float2 Direction;",
+        r#"
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], constant Uniforms& uniforms [[buffer(0)]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
+    {
+        float2 direction = uniforms.Direction;
+        float4 color = tex.sample(texSmplr, in.uv) * 0.2270270270;
+        color += tex.sample(texSmplr, in.uv + direction * 1.3846153846) * 0.3162162162;
+        color += tex.sample(texSmplr, in.uv - direction * 1.3846153846) * 0.3162162162;
+        color += tex.sample(texSmplr, in.uv + direction * 3.2307692308) * 0.0702702703;
+        color += tex.sample(texSmplr, in.uv - direction * 3.2307692308) * 0.0702702703;
+        return color;
+    }
+    "#
+    );
+
+    pub const METAL_THRESHOLD: &str = metal!(
+        "        float Threshold;",
+        r#"
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], constant Uniforms& uniforms [[buffer(0)]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
+    {
+        float4 color = tex.sample(texSmplr, in.uv);
+        float brightness = max(color.r, max(color.g, color.b));
+        float contribution = max(brightness - uniforms.Threshold, 0.0) / max(brightness, 0.0001);
+        return float4(color.rgb * contribution, 1.0);
+    }
+    "#
+    );
+
+    pub const METAL_BLOOM_COMBINE: &str = metal!(
+        "        float Intensity;",
+        r#"
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], constant Uniforms& uniforms [[buffer(0)]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]], texture2d<float> bloom [[texture(1)]], sampler bloomSmplr [[sampler(1)]])
+    {
+        float4 color = tex.sample(texSmplr, in.uv);
+        float3 glow = bloom.sample(bloomSmplr, in.uv).rgb;
+        return float4(color.rgb + glow * uniforms.Intensity, color.a);
+    }
+    "#
+    );
+
+    // Tint goes first, so the layout of the struct matches the tightly packed uniforms
+    pub const METAL_COLOR_GRADING: &str = metal!(
+        r#"        float4 Tint;
+        float Brightness;
+        float Contrast;
+        float Saturation;"#,
+        r#"
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], constant Uniforms& uniforms [[buffer(0)]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
+    {
+        float4 color = tex.sample(texSmplr, in.uv);
+        float3 rgb = color.rgb * uniforms.Tint.rgb + uniforms.Brightness;
+        rgb = (rgb - 0.5) * uniforms.Contrast + 0.5;
+        float luma = dot(rgb, float3(0.299, 0.587, 0.114));
+        rgb = mix(float3(luma), rgb, uniforms.Saturation);
+        return float4(clamp(rgb, 0.0, 1.0), color.a);
+    }
+    "#
+    );
+
+    pub const METAL_VIGNETTE: &str = metal!(
+        r#"        float Intensity;
+        float Radius;
+        float Softness;"#,
+        r#"
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], constant Uniforms& uniforms [[buffer(0)]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
+    {
+        float4 color = tex.sample(texSmplr, in.uv);
+        float vignette = 1.0 - smoothstep(uniforms.Radius - uniforms.Softness, uniforms.Radius, distance(in.uv, float2(0.5)));
+        return float4(color.rgb * mix(1.0, vignette, uniforms.Intensity), color.a);
+    }
+    "#
+    );
+
+    pub const METAL_CRT_SCANLINES: &str = metal!(
+        r#"        float Curvature;
+        float ScanlineIntensity;
+        float ScanlineCount;"#,
+        r#"
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], constant Uniforms& uniforms [[buffer(0)]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
+    {
+        float2 centered = in.uv * 2.0 - 1.0;
+        centered += centered * centered.yx * centered.yx * uniforms.Curvature;
+        float2 warped = centered * 0.5 + 0.5;
+
+        if (warped.x < 0.0 || warped.x > 1.0 || warped.y < 0.0 || warped.y > 1.0) {
+            return float4(0.0, 0.0, 0.0, 1.0);
+        }
+
+        float4 color = tex.sample(texSmplr, warped);
+        float scanline = 0.5 + 0.5 * sin(warped.y * uniforms.ScanlineCount * 6.2831853);
+        return float4(color.rgb * (1.0 - uniforms.ScanlineIntensity * (1.0 - scanline)), color.a);
+    }
+    "#
+    );
+}
+
+#[test]
+fn post_process_chain() {
+    use super::backend::Command;
+    use super::test_renderer;
+    use crate::color::{BLACK, RED};
+    use crate::texture::Image;
+
+    let (mut backend, mut renderer, scene) = test_renderer(8);
+    let output = new_render_target(&mut backend, 8, 8);
+    renderer.clear(&mut backend, BLACK);
+    // The left half is red
+    renderer.push_geometry(
+        &[
+            Vertex::new(-1., -1., 0., 0., 0., RED),
+            Vertex::new(0., -1., 0., 0., 0., RED),
+            Vertex::new(0., 1., 0., 0., 0., RED),
+            Vertex::new(-1., 1., 0., 0., 0., RED),
+        ],
+        &[0, 1, 2, 0, 2, 3],
+    );
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let mut chain = PostProcessChain::new(vec![
+        Effect::GaussianBlur { radius: 2. },
+        Effect::Bloom {
+            threshold: 0.5,
+            intensity: 1.,
+            radius: 4.,
+        },
+        Effect::ColorGrading {
+            tint: Color::new(0.25, 0.5, 0.75, 1.),
+            brightness: 0.125,
+            contrast: 1.5,
+            saturation: 2.,
+        },
+        Effect::Vignette {
+            intensity: 0.5,
+            radius: 0.75,
+            softness: 0.45,
+        },
+    ]);
+
+    // The scene has to be drawn first
+    renderer.push_geometry(&[Vertex::new(0., 0., 0., 0., 0., RED); 3], &[0, 1, 2]);
+    assert!(matches!(
+        chain.apply(&mut backend, &mut renderer, scene.texture, None),
+        Err(Error::PendingDrawCalls)
+    ));
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    renderer.with_layer(3);
+    renderer.with_blend_mode(BlendMode::Additive);
+    backend.clear_commands();
+    chain
+        .apply(
+            &mut backend,
+            &mut renderer,
+            scene.texture,
+            Some(output.render_pass.render_pass),
+        )
+        .unwrap();
+
+    // 2 blur passes, 4 bloom passes, a color grading pass and a vignette pass
+    assert_eq!(backend.draw_calls(), 8);
+
+    // The software backend runs every shader as the default one, so the effects are checked
+    // through the uniforms of their passes, which come after the uniforms of the renderer
+    let uniforms = backend
+        .commands()
+        .iter()
+        .filter_map(|command| match command {
+            Command::ApplyUniforms { bytes } => Some(
+                bytes
+                    .chunks_exact(4)
+                    .map(|value| f32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+                    .collect::<Vec<_>>(),
+            ),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(uniforms.len(), 8);
+    // The horizontal blur pass: a 2 pixel radius over 8 pixels
+    assert!(uniforms[0].ends_with(&[2. / 4. / 8., 0.]));
+    assert!(uniforms[6].ends_with(&[0.25, 0.5, 0.75, 1., 0.125, 1.5, 2.]));
+    assert!(uniforms[7].ends_with(&[0.5, 0.75, 0.45]));
+    assert_eq!(chain.targets.len(), 5);
+
+    // The state of the renderer is restored
+    assert_eq!(renderer.get_layer(), 3);
+    assert_eq!(renderer.get_blend_mode(), BlendMode::Additive);
+    assert_eq!(
+        renderer.get_active_render_pass(),
+        Some(scene.render_pass.render_pass)
+    );
+
+    // Every pass is a copy on the software backend, so the output is the scene
+    let expected = Image::from_texture(&mut backend, &scene.texture);
+    let result = Image::from_texture(&mut backend, &output.texture);
+    assert_eq!(
+        super::backend::compare_images(&expected, &result, 0),
+        Some(0)
+    );
+
+    // Intermediate targets are recreated with the size of a new output
+    let smaller = new_render_target(&mut backend, 4, 4);
+    chain
+        .apply(
+            &mut backend,
+            &mut renderer,
+            scene.texture,
+            Some(smaller.render_pass.render_pass),
+        )
+        .unwrap();
+    let bloom = chain.targets[&Target::BloomA].texture;
+    assert_eq!(backend.texture_size(bloom), (2, 2));
+
+    chain.delete(&mut backend, &mut renderer);
+}
@@ -4,53 +4,210 @@ use miniquad::*;
 
 pub use miniquad::{TextureId as MiniquadTexture, UniformDesc};
 
-use crate::{color::Color, logging::warn, tobytes::ToBytes, Error};
-
-use std::{collections::BTreeMap, marker::PhantomData};
-
-pub(crate) use super::{AsVertex, Vertex};
-
-#[derive(Debug, Clone, Copy, PartialEq)]
+use crate::{color::Color, logging::warn, tobytes::ToBytes, utils::Rect, Error, UniformError};
+
+use std::{
+    any::TypeId,
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
+
+pub(crate) use super::{
+    AsIndex, AsInstance, AsVertex, BatchBreak, CommandBuffer, FrameStats, IndexFormat, Instance,
+    InstanceBuffer, StaticMesh, Vertex,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DrawMode {
     Triangles,
     Lines,
 }
 
+/// A pipeline of a specific [Renderer], see [Renderer::make_pipeline]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct GlPipeline<V>
 where V: AsVertex { 
     id: usize,
+    /// The generation of the slot, so the pipeline doesn't resolve to another one
+    /// that took the slot after it was deleted
+    generation: u32,
+    /// The id of the [PipelineStorage] it belongs to
+    storage: u32,
     _m: PhantomData<V>
 }
 
 impl<V> GlPipeline<V>
 where V: AsVertex {
-    const fn new(id: usize) -> Self {
+    const fn new(id: usize, generation: u32, storage: u32) -> Self {
         Self {
             id,
+            generation,
+            storage,
             _m: PhantomData
         }
     }
 }
 
+/// Pipelines of dropped [OwnedMaterial](super::material::OwnedMaterial)s,
+/// waiting to be deleted by their renderer
+pub(crate) type ReleaseQueue<V> = Arc<Mutex<Vec<GlPipeline<V>>>>;
+
+/// A value that can be set as a uniform of a specific [UniformType], with the checked setters
+/// like [Renderer::try_set_uniform] and [UniformHandle]s.
+///
+/// # Safety
+/// The type has to be plain data, laid out exactly as its [UniformValue::UNIFORM_TYPE]
+pub unsafe trait UniformValue: Copy + std::fmt::Debug {
+    const UNIFORM_TYPE: UniformType;
+}
+
+macro_rules! impl_uniform_value {
+    ($($t:ty => $uniform_type:ident),* $(,)?) => {
+        $(
+            unsafe impl UniformValue for $t {
+                const UNIFORM_TYPE: UniformType = UniformType::$uniform_type;
+            }
+        )*
+    };
+}
+
+impl_uniform_value!(
+    f32 => Float1,
+    [f32; 2] => Float2,
+    [f32; 3] => Float3,
+    [f32; 4] => Float4,
+    glam::Vec2 => Float2,
+    glam::Vec3 => Float3,
+    glam::Vec4 => Float4,
+    i32 => Int1,
+    [i32; 2] => Int2,
+    [i32; 3] => Int3,
+    [i32; 4] => Int4,
+    glam::IVec2 => Int2,
+    glam::IVec3 => Int3,
+    glam::IVec4 => Int4,
+    u32 => Int1,
+    [u32; 2] => Int2,
+    [u32; 3] => Int3,
+    [u32; 4] => Int4,
+    glam::UVec2 => Int2,
+    glam::UVec3 => Int3,
+    glam::UVec4 => Int4,
+    glam::Mat4 => Mat4,
+);
+
+/// A uniform of a pipeline, looked up and type checked once with [Renderer::uniform_handle],
+/// so setting it with [Renderer::set_uniform_handle] doesn't search uniforms by name.
+///
+/// Replacing the pipeline (for example, when reloading a material) makes the handle stale
+#[derive(Debug, Clone, Copy)]
+pub struct UniformHandle<V, T>
+where
+    V: AsVertex,
+    T: UniformValue,
+{
+    pipeline: GlPipeline<V>,
+    index: usize,
+    revision: u32,
+    _m: PhantomData<T>,
+}
+
+impl<V, T> UniformHandle<V, T>
+where
+    V: AsVertex,
+    T: UniformValue,
+{
+    pub const fn pipeline(&self) -> GlPipeline<V> {
+        self.pipeline
+    }
+}
+
+/// Where the geometry of a draw call lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Geometry {
+    /// Vertices and indices are in the batch buffers of the renderer, and are uploaded on draw
+    Batched,
+    /// Vertices and indices are already on the GPU, in the buffers of a [StaticMesh]
+    Static {
+        vertex_buffer: BufferId,
+        index_buffer: BufferId,
+    },
+    /// A [StaticMesh], drawn multiple times with per-instance data from an [InstanceBuffer]
+    Instanced {
+        vertex_buffer: BufferId,
+        index_buffer: BufferId,
+        instance_buffer: BufferId,
+        instances: usize,
+    },
+}
+
+/// The clip of a draw call: the scissor rectangle of [Renderer::with_scissor] and the top of the
+/// clip stack ([Renderer::push_clip]), intersected when drawing
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Clip {
+    /// In framebuffer pixels, from the top-left corner of the framebuffer
+    scissor: Option<(i32, i32, i32, i32)>,
+    /// In framebuffer pixels, from the top-left corner of the viewport
+    stack: Option<(i32, i32, i32, i32)>,
+}
+
+impl Clip {
+    /// The scissor rectangle for the backend, from the bottom-left corner of the framebuffer
+    fn resolve(
+        &self,
+        viewport: Option<(i32, i32, i32, i32)>,
+        width: i32,
+        height: i32,
+    ) -> (i32, i32, i32, i32) {
+        let mut rect = (0, 0, width, height);
+        if let Some((x, y, w, h)) = self.scissor {
+            rect = intersect_rect(rect, (x, height - (y + h), w, h));
+        }
+        if let Some((x, y, w, h)) = self.stack {
+            let (vx, vy, _, vh) = viewport.unwrap_or((0, 0, width, height));
+            rect = intersect_rect(rect, (vx + x, vy + vh - (y + h), w, h));
+        }
+        rect
+    }
+}
+
+/// Clip rectangles of [Renderer::push_clip], the last one is the current clip
+type ClipStack = Vec<(i32, i32, i32, i32)>;
+
+/// The intersection of two `(x, y, w, h)` rectangles, empty if they don't overlap
+fn intersect_rect(a: (i32, i32, i32, i32), b: (i32, i32, i32, i32)) -> (i32, i32, i32, i32) {
+    let x = a.0.max(b.0);
+    let y = a.1.max(b.1);
+    let right = (a.0 + a.2).min(b.0 + b.2);
+    let bottom = (a.1 + a.3).min(b.1 + b.3);
+
+    (x, y, (right - x).max(0), (bottom - y).max(0))
+}
+
 struct DrawCall<V>
 where V: AsVertex {
+    geometry: Geometry,
     vertices_count: usize,
     indices_count: usize,
     vertices_start: usize,
     indices_start: usize,
 
-    clip: Option<(i32, i32, i32, i32)>,
+    clip: Clip,
     viewport: Option<(i32, i32, i32, i32)>,
     texture: Option<miniquad::TextureId>,
 
     model: glam::Mat4,
 
     draw_mode: DrawMode,
-    pipeline: GlPipeline<V>,
+    pipeline: DrawPipeline<V>,
     uniforms: Option<Vec<u8>>,
     render_pass: Option<RenderPass>,
     capture: bool,
+    layer: i32,
 }
 
 impl<V> DrawCall<V>
@@ -59,17 +216,21 @@ where V: AsVertex {
         texture: Option<miniquad::TextureId>,
         model: glam::Mat4,
         draw_mode: DrawMode,
-        pipeline: GlPipeline<V>,
+        pipeline: DrawPipeline<V>,
         uniforms: Option<Vec<u8>>,
         render_pass: Option<RenderPass>,
     ) -> Self {
         Self {
+            geometry: Geometry::Batched,
             vertices_start: 0,
             indices_start: 0,
             vertices_count: 0,
             indices_count: 0,
             viewport: None,
-            clip: None,
+            clip: Clip {
+                scissor: None,
+                stack: None,
+            },
             texture,
             model,
             draw_mode,
@@ -77,28 +238,85 @@ where V: AsVertex {
             uniforms,
             render_pass,
             capture: false,
+            layer: 0,
         }
     }
+
+    /// Clear the geometry of this draw call, once it's drawn
+    fn reset(&mut self) {
+        self.geometry = Geometry::Batched;
+        self.vertices_count = 0;
+        self.indices_count = 0;
+        self.vertices_start = 0;
+        self.indices_start = 0;
+    }
+
+    /// Check whether another draw call can be appended to this one
+    fn can_merge(&self, other: &Self, max_vertices: usize, max_indices: usize) -> bool {
+        self.geometry == Geometry::Batched
+            && other.geometry == Geometry::Batched
+            && self.texture == other.texture
+            && self.clip == other.clip
+            && self.viewport == other.viewport
+            && self.model == other.model
+            && self.pipeline == other.pipeline
+            && self.uniforms == other.uniforms
+            && self.render_pass == other.render_pass
+            && self.draw_mode == other.draw_mode
+            && self.capture == other.capture
+            && self.layer == other.layer
+            && self.vertices_count + other.vertices_count <= max_vertices
+            && self.indices_count + other.indices_count <= max_indices
+    }
 }
 
-struct RendererState<V>
+pub(crate) struct RendererState<V>
 where V: AsVertex {
     texture: Option<miniquad::TextureId>,
     draw_mode: DrawMode,
-    clip: Option<(i32, i32, i32, i32)>,
+    clip: Clip,
+    /// The clip stack of the current render pass
+    clip_stack: ClipStack,
+    /// Clip stacks of the other render passes, until they're active again
+    clip_stacks: Vec<(Option<RenderPass>, ClipStack)>,
     viewport: Option<(i32, i32, i32, i32)>,
     model_stack: Vec<glam::Mat4>,
     pipeline: Option<GlPipeline<V>>,
     depth_test_enable: bool,
+    bake_transforms: bool,
+    blend_mode: BlendMode,
+    stencil_mode: StencilMode,
 
     break_batching: bool,
 
     render_pass: Option<RenderPass>,
     capture: bool,
+    layer: i32,
 }
 
 impl<V> RendererState<V>
 where V: AsVertex {
+    fn new() -> Self {
+        Self {
+            clip: Clip::default(),
+            clip_stack: vec![],
+            clip_stacks: vec![],
+            viewport: None,
+            texture: None,
+            model_stack: vec![glam::Mat4::IDENTITY],
+            draw_mode: DrawMode::Triangles,
+            pipeline: None,
+            break_batching: false,
+            depth_test_enable: false,
+            bake_transforms: false,
+            blend_mode: BlendMode::Alpha,
+            stencil_mode: StencilMode::Disabled,
+            render_pass: None,
+            capture: false,
+            layer: 0,
+        }
+    }
+
     fn model(&self) -> glam::Mat4 {
         *self.model_stack.last().unwrap()
     }
@@ -116,15 +334,171 @@ struct Uniform {
 struct PipelineExt<V>
 where V: AsVertex {
     pipeline: miniquad::Pipeline,
+    shader: ShaderId,
+    params: PipelineParams,
     uniforms: Vec<Uniform>,
     uniforms_data: Vec<u8>,
     textures: Vec<String>,
     textures_data: BTreeMap<String, MiniquadTexture>,
+    /// The instance layout of instanced pipelines
+    instance: Option<InstanceLayout>,
+    /// Incremented every time the pipeline is replaced, to catch stale [UniformHandle]s
+    revision: u32,
     _m: PhantomData<V>
 }
 
 impl<V> PipelineExt<V>
 where V: AsVertex {
+    fn new(
+        backend: &mut dyn RenderingBackend,
+        shader: ShaderId,
+        params: PipelineParams,
+        mut uniforms: Vec<UniformDesc>,
+        textures: Vec<String>,
+        instance: Option<InstanceLayout>,
+    ) -> Self {
+        let mut layouts = vec![BufferLayout::default()];
+        let mut attributes = V::attributes();
+
+        if let Some(instance) = &instance {
+            layouts.push(BufferLayout {
+                step_func: VertexStep::PerInstance,
+                ..Default::default()
+            });
+            attributes.extend(instance.attributes.iter().map(|attribute| VertexAttribute {
+                buffer_index: 1,
+                ..attribute.clone()
+            }));
+        }
+
+        let pipeline = backend.new_pipeline(&layouts, &attributes, shader, params);
+
+        let mut max_offset = 0;
+
+        for (name, kind) in shader::uniforms().into_iter().rev() {
+            uniforms.insert(0, UniformDesc::new(name, kind));
+        }
+
+        let uniforms = uniforms
+            .iter()
+            .scan(0, |offset, uniform| {
+                let byte_size = uniform.uniform_type.size() * uniform.array_count;
+                let uniform = Uniform {
+                    name: uniform.name.clone(),
+                    uniform_type: uniform.uniform_type,
+                    byte_size,
+                    byte_offset: *offset,
+                };
+                *offset += byte_size;
+                max_offset = *offset;
+
+                Some(uniform)
+            })
+            .collect();
+
+        Self {
+            pipeline,
+            uniforms,
+            uniforms_data: vec![0; max_offset],
+            textures,
+            textures_data: BTreeMap::new(),
+            instance,
+            shader,
+            params,
+            revision: 0,
+            _m: PhantomData
+        }
+    }
+
+    /// Recreate this pipeline with another shader, keeping its params and instance layout.
+    ///
+    /// Values of uniforms and textures that are still present (with the same type)
+    /// are carried over. The old pipeline and its shader are deleted
+    fn replace(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        shader: ShaderId,
+        uniforms: Vec<UniformDesc>,
+        textures: Vec<String>,
+    ) {
+        let instance = self.instance.clone();
+        let mut pipeline =
+            PipelineExt::new(backend, shader, self.params, uniforms, textures, instance);
+
+        for uniform in &pipeline.uniforms {
+            let previous = self.uniforms.iter().find(|previous| {
+                previous.name == uniform.name
+                    && std::mem::discriminant(&previous.uniform_type)
+                        == std::mem::discriminant(&uniform.uniform_type)
+                    && previous.byte_size == uniform.byte_size
+            });
+
+            if let Some(previous) = previous {
+                pipeline.uniforms_data[uniform.byte_offset..uniform.byte_offset + uniform.byte_size]
+                    .copy_from_slice(
+                        &self.uniforms_data
+                            [previous.byte_offset..previous.byte_offset + previous.byte_size],
+                    );
+            }
+        }
+
+        pipeline.textures_data = std::mem::take(&mut self.textures_data);
+        pipeline
+            .textures_data
+            .retain(|name, _| pipeline.textures.contains(name));
+
+        backend.delete_pipeline(self.pipeline);
+        backend.delete_shader(self.shader);
+        pipeline.revision = self.revision.wrapping_add(1);
+        *self = pipeline;
+    }
+
+    /// Find a uniform by name, checking that it's declared with the type of `T`
+    fn find_uniform<T: UniformValue>(&self, name: &str) -> Result<usize, UniformError> {
+        let index = self
+            .uniforms
+            .iter()
+            .position(|uniform| uniform.name == name)
+            .ok_or_else(|| UniformError::UnknownName(name.to_string()))?;
+
+        let uniform_type = self.uniforms[index].uniform_type;
+        if std::mem::discriminant(&uniform_type) != std::mem::discriminant(&T::UNIFORM_TYPE) {
+            return Err(UniformError::TypeMismatch {
+                name: name.to_string(),
+                expected: uniform_type,
+                found: T::UNIFORM_TYPE,
+            });
+        }
+
+        Ok(index)
+    }
+
+    /// Write values into a (type checked) uniform, starting from its first element
+    fn write_uniform<T: UniformValue>(
+        &mut self,
+        index: usize,
+        values: &[T],
+    ) -> Result<(), UniformError> {
+        let uniform = &self.uniforms[index];
+        let capacity = uniform.byte_size / uniform.uniform_type.size();
+        if values.len() > capacity {
+            return Err(UniformError::ArrayOverflow {
+                name: uniform.name.clone(),
+                capacity,
+                len: values.len(),
+            });
+        }
+
+        // SAFETY: UniformValue types are plain data
+        let bytes = unsafe {
+            std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+        };
+        self.uniforms_data[uniform.byte_offset..uniform.byte_offset + bytes.len()]
+            .copy_from_slice(bytes);
+
+        Ok(())
+    }
+
     fn set_uniform<T>(&mut self, name: &str, uniform: T) {
         let uniform_meta = self.uniforms.iter().find(
             |Uniform {
@@ -206,104 +580,253 @@ where V: AsVertex {
     }
 }
 
-const MAX_PIPELINES: usize = 32;
+/// Per-instance vertex attributes of an instanced pipeline
+#[derive(Clone)]
+struct InstanceLayout {
+    type_id: TypeId,
+    attributes: Vec<VertexAttribute>,
+}
+
+impl InstanceLayout {
+    fn of<I: AsInstance>() -> Self {
+        Self {
+            type_id: TypeId::of::<I>(),
+            attributes: I::attributes(),
+        }
+    }
+}
+
+/// Blend modes of the default pipelines, see [Renderer::with_blend_mode]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BlendMode {
+    /// Regular alpha blending
+    #[default]
+    Alpha,
+    /// The source color, multiplied by its alpha, is added to the destination.
+    /// Useful for lights, glows and particles
+    Additive,
+    /// The destination color is multiplied by the source color
+    Multiply,
+    /// Alpha blending for colors that are already multiplied by their alpha
+    PremultipliedAlpha,
+    /// No blending at all, the source color replaces the destination
+    Replace,
+}
+
+impl BlendMode {
+    /// The color blend state of this mode, as used in [PipelineParams::color_blend]
+    pub fn color_blend(self) -> Option<BlendState> {
+        match self {
+            BlendMode::Alpha => Some(BlendState::new(
+                Equation::Add,
+                BlendFactor::Value(BlendValue::SourceAlpha),
+                BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+            )),
+            BlendMode::Additive => Some(BlendState::new(
+                Equation::Add,
+                BlendFactor::Value(BlendValue::SourceAlpha),
+                BlendFactor::One,
+            )),
+            BlendMode::Multiply => Some(BlendState::new(
+                Equation::Add,
+                BlendFactor::Value(BlendValue::DestinationColor),
+                BlendFactor::Zero,
+            )),
+            BlendMode::PremultipliedAlpha => Some(BlendState::new(
+                Equation::Add,
+                BlendFactor::One,
+                BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
+            )),
+            BlendMode::Replace => None,
+        }
+    }
+}
+
+/// Stencil masking of the default pipelines, see [Renderer::with_stencil_mode].
+///
+/// The mask is kept in the stencil buffer, so masking needs a render target with one
+/// (see [RenderTargetParams::stencil](crate::texture::RenderTargetParams::stencil)).
+/// With OpenGL, that render target has to be created with
+/// [GlAccess::Raw](crate::texture::GlAccess::Raw). Without a stencil buffer, no mask is kept, so
+/// [StencilMode::Inside] and [StencilMode::Outside] draw everywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum StencilMode {
+    /// No stencil testing, draw everywhere
+    #[default]
+    Disabled,
+    /// Add the drawn geometry to the mask, without drawing any color
+    WriteMask,
+    /// Remove the drawn geometry from the mask, without drawing any color
+    EraseMask,
+    /// Draw only inside of the mask
+    Inside,
+    /// Draw only outside of the mask
+    Outside,
+}
+
+impl StencilMode {
+    /// The stencil value of masked pixels
+    const MASK: i32 = 1;
+
+    /// The stencil state of this mode, as used in [PipelineParams::stencil_test]
+    pub fn stencil_test(self) -> Option<StencilState> {
+        let (pass_op, test_func) = match self {
+            StencilMode::Disabled => return None,
+            StencilMode::WriteMask => (StencilOp::Replace, CompareFunc::Always),
+            StencilMode::EraseMask => (StencilOp::Zero, CompareFunc::Always),
+            StencilMode::Inside => (StencilOp::Keep, CompareFunc::Equal),
+            StencilMode::Outside => (StencilOp::Keep, CompareFunc::NotEqual),
+        };
+
+        let face = StencilFaceState {
+            fail_op: StencilOp::Keep,
+            depth_fail_op: StencilOp::Keep,
+            pass_op,
+            test_func,
+            test_ref: Self::MASK,
+            test_mask: u32::MAX,
+            write_mask: u32::MAX,
+        };
+        Some(StencilState {
+            front: face,
+            back: face,
+        })
+    }
+
+    /// Apply this mode to pipeline params.
+    ///
+    /// This is how stencil masking is used with custom materials: create a material with
+    /// `StencilMode::Inside.apply(params)` and it will only draw inside of the mask.
+    pub fn apply(self, params: PipelineParams) -> PipelineParams {
+        let color_write = match self {
+            StencilMode::WriteMask | StencilMode::EraseMask => (false, false, false, false),
+            _ => params.color_write,
+        };
+
+        PipelineParams {
+            stencil_test: self.stencil_test(),
+            color_write,
+            ..params
+        }
+    }
+}
+
+/// A variant of the default pipeline.
+///
+/// Default pipelines are created on first use, for every combination of draw state that needs one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct DefaultPipeline {
+    draw_mode: DrawMode,
+    depth_test: bool,
+    blend_mode: BlendMode,
+    stencil_mode: StencilMode,
+    /// Whether this is the instanced variant, for [Instance]
+    instanced: bool,
+}
+
+impl DefaultPipeline {
+    fn params(&self) -> PipelineParams {
+        let params = PipelineParams {
+            color_blend: self.blend_mode.color_blend(),
+            primitive_type: match self.draw_mode {
+                DrawMode::Triangles => PrimitiveType::Triangles,
+                DrawMode::Lines => PrimitiveType::Lines,
+            },
+            ..Default::default()
+        };
+        let params = if self.depth_test {
+            PipelineParams {
+                depth_write: true,
+                depth_test: Comparison::LessOrEqual,
+                ..params
+            }
+        } else {
+            params
+        };
+
+        self.stencil_mode.apply(params)
+    }
+}
+
+/// The pipeline of a draw call
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DrawPipeline<V>
+where V: AsVertex {
+    Default(DefaultPipeline),
+    Custom(GlPipeline<V>),
+}
+
+/// Ids of pipeline storages, to tell apart pipelines of different renderers
+static NEXT_STORAGE_ID: AtomicU32 = AtomicU32::new(0);
+
+/// A slot of [PipelineStorage]. Its generation is incremented every time its pipeline is deleted
+struct PipelineSlot<V>
+where V: AsVertex {
+    pipeline: Option<PipelineExt<V>>,
+    generation: u32,
+}
 
 struct PipelineStorage<V>
 where V: AsVertex {
-    pipelines: [Option<PipelineExt<V>>; MAX_PIPELINES],
-    pipelines_amount: usize,
+    id: u32,
+    pipelines: Vec<PipelineSlot<V>>,
+    /// Indices of empty slots, reused before growing
+    free_slots: Vec<usize>,
+    released: ReleaseQueue<V>,
+    /// GPU resources of deleted pipelines. Deleting pipelines doesn't require a backend,
+    /// so they're deleted later, in [PipelineStorage::release]
+    garbage: Vec<(miniquad::Pipeline, ShaderId)>,
+
+    shader: ShaderId,
+    instanced_shader: ShaderId,
+    default_pipelines: HashMap<DefaultPipeline, PipelineExt<V>>,
 }
 
 impl<V> PipelineStorage<V>
 where V: AsVertex {
-    const TRIANGLES_PIPELINE: GlPipeline<V> = GlPipeline::new(0);
-    const LINES_PIPELINE: GlPipeline<V> = GlPipeline::new(1);
-    const TRIANGLES_DEPTH_PIPELINE: GlPipeline<V> = GlPipeline::new(2);
-    const LINES_DEPTH_PIPELINE: GlPipeline<V> = GlPipeline::new(3);
-
     fn new(ctx: &mut dyn RenderingBackend) -> Self {
+        let sources = shader::Sources::of::<V>();
+
         let shader = ctx
             .new_shader(
                 match ctx.info().backend {
                     Backend::OpenGl => ShaderSource::Glsl {
-                        vertex: shader::VERTEX,
+                        vertex: sources.vertex,
                         fragment: shader::FRAGMENT,
                     },
                     Backend::Metal => ShaderSource::Msl {
-                        program: shader::METAL,
+                        program: sources.metal,
                     },
                 },
                 shader::meta(),
             )
             .unwrap_or_else(|e| panic!("Failed to load shader: {}", e));
 
-        let params = PipelineParams {
-            color_blend: Some(BlendState::new(
-                Equation::Add,
-                BlendFactor::Value(BlendValue::SourceAlpha),
-                BlendFactor::OneMinusValue(BlendValue::SourceAlpha),
-            )),
-            ..Default::default()
-        };
-
-        let mut storage = Self {
-            pipelines: Default::default(),
-            pipelines_amount: 0,
-        };
-
-        let triangles_pipeline = storage.make_pipeline(
-            ctx,
-            shader,
-            PipelineParams {
-                primitive_type: PrimitiveType::Triangles,
-                ..params
-            },
-            vec![],
-            vec![],
-        );
-        assert_eq!(triangles_pipeline, Self::TRIANGLES_PIPELINE);
-
-        let lines_pipeline = storage.make_pipeline(
-            ctx,
-            shader,
-            PipelineParams {
-                primitive_type: PrimitiveType::Lines,
-                ..params
-            },
-            vec![],
-            vec![],
-        );
-        assert_eq!(lines_pipeline, Self::LINES_PIPELINE);
-
-        let triangles_depth_pipeline = storage.make_pipeline(
-            ctx,
-            shader,
-            PipelineParams {
-                depth_write: true,
-                depth_test: Comparison::LessOrEqual,
-                primitive_type: PrimitiveType::Triangles,
-                ..params
-            },
-            vec![],
-            vec![],
-        );
-        assert_eq!(triangles_depth_pipeline, Self::TRIANGLES_DEPTH_PIPELINE);
+        let instanced_shader = ctx
+            .new_shader(
+                match ctx.info().backend {
+                    Backend::OpenGl => ShaderSource::Glsl {
+                        vertex: sources.instanced_vertex,
+                        fragment: shader::FRAGMENT,
+                    },
+                    Backend::Metal => ShaderSource::Msl {
+                        program: &sources.instanced_metal,
+                    },
+                },
+                shader::meta(),
+            )
+            .unwrap_or_else(|e| panic!("Failed to load shader: {}", e));
 
-        let lines_depth_pipeline = storage.make_pipeline(
-            ctx,
+        Self {
+            id: NEXT_STORAGE_ID.fetch_add(1, Ordering::Relaxed),
+            pipelines: vec![],
+            free_slots: vec![],
+            released: ReleaseQueue::default(),
+            garbage: vec![],
             shader,
-            PipelineParams {
-                depth_write: true,
-                depth_test: Comparison::LessOrEqual,
-                primitive_type: PrimitiveType::Lines,
-                ..params
-            },
-            vec![],
-            vec![],
-        );
-        assert_eq!(lines_depth_pipeline, Self::LINES_DEPTH_PIPELINE);
-
-        storage
+            instanced_shader,
+            default_pipelines: HashMap::new(),
+        }
     }
 
     fn make_pipeline(
@@ -311,84 +834,277 @@ where V: AsVertex {
         backend: &mut dyn RenderingBackend,
         shader: ShaderId,
         params: PipelineParams,
-        mut uniforms: Vec<UniformDesc>,
+        uniforms: Vec<UniformDesc>,
         textures: Vec<String>,
+        instance: Option<InstanceLayout>,
     ) -> GlPipeline<V> {
-        let pipeline = backend.new_pipeline(
-            &[BufferLayout::default()],
-            &(V::attributes()),
-            shader,
-            params,
-        );
-
-        let id = self
-            .pipelines
-            .iter()
-            .position(|p| p.is_none())
-            .unwrap_or_else(|| panic!("Pipelines amount exceeded"));
+        let id = self.free_slots.pop().unwrap_or_else(|| {
+            self.pipelines.push(PipelineSlot {
+                pipeline: None,
+                generation: 0,
+            });
+            self.pipelines.len() - 1
+        });
 
-        let mut max_offset = 0;
+        let slot = &mut self.pipelines[id];
+        slot.pipeline = Some(PipelineExt::new(
+            backend, shader, params, uniforms, textures, instance,
+        ));
 
-        for (name, kind) in shader::uniforms().into_iter().rev() {
-            uniforms.insert(0, UniformDesc::new(name, kind));
-        }
+        GlPipeline::new(id, slot.generation, self.id)
+    }
 
-        let uniforms = uniforms
-            .iter()
-            .scan(0, |offset, uniform| {
-                let byte_size = uniform.uniform_type.size() * uniform.array_count;
-                let uniform = Uniform {
-                    name: uniform.name.clone(),
-                    uniform_type: uniform.uniform_type,
-                    byte_size,
-                    byte_offset: *offset,
-                };
-                *offset += byte_size;
-                max_offset = *offset;
+    /// Get a default pipeline, creating it if it doesn't exist yet
+    fn get_default_pipeline(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        pipeline: DefaultPipeline,
+    ) -> &mut PipelineExt<V> {
+        let (shader, instance) = if pipeline.instanced {
+            (self.instanced_shader, Some(InstanceLayout::of::<Instance>()))
+        } else {
+            (self.shader, None)
+        };
 
-                Some(uniform)
-            })
-            .collect();
+        self.default_pipelines.entry(pipeline).or_insert_with(|| {
+            PipelineExt::new(backend, shader, pipeline.params(), vec![], vec![], instance)
+        })
+    }
 
-        self.pipelines[id] = Some(PipelineExt {
-            pipeline,
-            uniforms,
-            uniforms_data: vec![0; max_offset],
-            textures,
-            textures_data: BTreeMap::new(),
-            _m: PhantomData
-        });
-        self.pipelines_amount += 1;
+    /// Get the pipeline of a draw call
+    fn get_draw_pipeline(
+        &mut self,
+        backend: &mut dyn RenderingBackend,
+        pipeline: DrawPipeline<V>,
+    ) -> Option<&mut PipelineExt<V>> {
+        match pipeline {
+            DrawPipeline::Default(pipeline) => Some(self.get_default_pipeline(backend, pipeline)),
+            DrawPipeline::Custom(pipeline) => self.get_pipeline_mut(&pipeline),
+        }
+    }
 
-        GlPipeline::new(id)
+    /// The slot of a pipeline, if it belongs to this storage and wasn't deleted
+    fn slot(&self, pip: &GlPipeline<V>) -> Option<&PipelineSlot<V>> {
+        self.pipelines
+            .get(pip.id)
+            .filter(|slot| pip.storage == self.id && slot.generation == pip.generation)
     }
 
-    /// Get the default pipeline by draw mode and depth flag
-    const fn get_default_pipeline_by(
-        &self,
-        draw_mode: DrawMode,
-        depth_enabled: bool,
-    ) -> GlPipeline<V> {
-        match (draw_mode, depth_enabled) {
-            (DrawMode::Triangles, false) => Self::TRIANGLES_PIPELINE,
-            (DrawMode::Triangles, true) => Self::TRIANGLES_DEPTH_PIPELINE,
-            (DrawMode::Lines, false) => Self::LINES_PIPELINE,
-            (DrawMode::Lines, true) => Self::LINES_DEPTH_PIPELINE,
-        }
+    /// Find a pipeline by pipeline ID ([GlPipeline])
+    fn get_pipeline(&self, pip: &GlPipeline<V>) -> Option<&PipelineExt<V>> {
+        debug_assert_eq!(pip.storage, self.id, "The pipeline belongs to another renderer");
+        self.slot(pip).and_then(|slot| slot.pipeline.as_ref())
     }
 
     /// Find a pipeline by pipeline ID ([GlPipeline])
     fn get_pipeline_mut(&mut self, pip: &GlPipeline<V>) -> Option<&mut PipelineExt<V>> {
-        (self.pipelines[pip.id]).as_mut()
+        self.get_pipeline(pip)?;
+        self.pipelines[pip.id].pipeline.as_mut()
     }
 
     /// Check whether this storage has the specified pipeline
     fn has_pipeline(&self, pip: &GlPipeline<V>) -> bool {
-        self.pipelines[pip.id].is_some()
+        self.slot(pip).is_some_and(|slot| slot.pipeline.is_some())
     }
 
     fn delete_pipeline(&mut self, pip: GlPipeline<V>) {
-        self.pipelines[pip.id] = None;
+        if self.get_pipeline(&pip).is_none() {
+            return;
+        }
+
+        let slot = &mut self.pipelines[pip.id];
+        let pipeline = slot.pipeline.take().unwrap();
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(pip.id);
+        self.garbage.push((pipeline.pipeline, pipeline.shader));
+    }
+
+    /// Delete released pipelines, and the GPU resources of all deleted ones
+    fn release(&mut self, backend: &mut dyn RenderingBackend) {
+        let released = std::mem::take(&mut *self.released.lock().unwrap());
+        for pipeline in released {
+            self.delete_pipeline(pipeline);
+        }
+
+        for (pipeline, shader) in self.garbage.drain(..) {
+            backend.delete_pipeline(pipeline);
+            backend.delete_shader(shader);
+        }
+    }
+}
+
+/// Batched indices, stored in the index format of the renderer
+enum BatchIndices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl BatchIndices {
+    fn with_capacity(format: IndexFormat, capacity: usize) -> Self {
+        match format {
+            IndexFormat::U16 => Self::U16(Vec::with_capacity(capacity)),
+            IndexFormat::U32 => Self::U32(Vec::with_capacity(capacity)),
+        }
+    }
+
+    const fn format(&self) -> IndexFormat {
+        match self {
+            Self::U16(_) => IndexFormat::U16,
+            Self::U32(_) => IndexFormat::U32,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::U16(indices) => indices.len(),
+            Self::U32(indices) => indices.len(),
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Self::U16(indices) => indices.clear(),
+            Self::U32(indices) => indices.clear(),
+        }
+    }
+
+    /// Append indices, offsetting every one of them by `offset`.
+    ///
+    /// The caller has to make sure the resulting indices fit into the index format
+    fn extend<I: AsIndex>(&mut self, indices: &[I], offset: u32) {
+        match self {
+            Self::U16(batch) => batch.extend(indices.iter().map(|x| (x.to_u32() + offset) as u16)),
+            Self::U32(batch) => batch.extend(indices.iter().map(|x| x.to_u32() + offset)),
+        }
+    }
+
+    /// Append a range of indices from another buffer of the same format, offsetting them by `offset`
+    fn extend_from(&mut self, other: &Self, start: usize, count: usize, offset: u32) {
+        match (self, other) {
+            (Self::U16(batch), Self::U16(other)) => batch.extend(
+                other[start..(start + count)]
+                    .iter()
+                    .map(|x| x + offset as u16),
+            ),
+            (Self::U32(batch), Self::U32(other)) => {
+                batch.extend(other[start..(start + count)].iter().map(|x| x + offset))
+            }
+            _ => unreachable!("Index buffers of different formats"),
+        }
+    }
+
+    fn source(&self, start: usize, count: usize) -> BufferSource<'_> {
+        match self {
+            Self::U16(indices) => BufferSource::slice(&indices[start..(start + count)]),
+            Self::U32(indices) => BufferSource::slice(&indices[start..(start + count)]),
+        }
+    }
+
+    /// Copy a range of indices into `out`, whatever the format is
+    fn copy_to(&self, start: usize, count: usize, out: &mut Vec<u32>) {
+        match self {
+            Self::U16(indices) => {
+                out.extend(indices[start..(start + count)].iter().map(|x| *x as u32))
+            }
+            Self::U32(indices) => out.extend_from_slice(&indices[start..(start + count)]),
+        }
+    }
+}
+
+/// A draw call of a [DrawList], with its geometry in the buffers of the list
+#[derive(Debug, Clone)]
+struct RecordedDrawCall<V>
+where V: AsVertex {
+    geometry: Geometry,
+    vertices: std::ops::Range<usize>,
+    indices: std::ops::Range<usize>,
+    /// For static and instanced geometry, which isn't copied into the list
+    indices_count: usize,
+
+    clip: Clip,
+    viewport: Option<(i32, i32, i32, i32)>,
+    texture: Option<miniquad::TextureId>,
+    model: glam::Mat4,
+    draw_mode: DrawMode,
+    pipeline: DrawPipeline<V>,
+    uniforms: Option<Vec<u8>>,
+    layer: i32,
+}
+
+/// Draw calls recorded from a [Renderer], to draw the same geometry on later frames
+/// without building it again. See [Renderer::begin_draw_list].
+///
+/// Along with the geometry, every draw call keeps its texture, clip, viewport, model matrix,
+/// layer, pipeline and material uniforms. Static meshes and instance buffers aren't copied,
+/// so they have to outlive the list.
+#[derive(Debug, Clone)]
+pub struct DrawList<V = Vertex>
+where V: AsVertex {
+    draw_calls: Vec<RecordedDrawCall<V>>,
+    vertices: Vec<V>,
+    indices: Vec<u32>,
+}
+
+impl<V> DrawList<V>
+where V: AsVertex {
+    pub const fn new() -> Self {
+        Self {
+            draw_calls: vec![],
+            vertices: vec![],
+            indices: vec![],
+        }
+    }
+
+    /// The amount of draw calls in this list
+    pub fn len(&self) -> usize {
+        self.draw_calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.draw_calls.is_empty()
+    }
+}
+
+impl<V> Default for DrawList<V>
+where V: AsVertex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Create an empty stream index buffer of the specified format
+fn new_index_buffer(
+    backend: &mut dyn RenderingBackend,
+    format: IndexFormat,
+    max_indices: usize,
+) -> BufferId {
+    let source = match format {
+        IndexFormat::U16 => BufferSource::empty::<u16>(max_indices),
+        IndexFormat::U32 => BufferSource::empty::<u32>(max_indices),
+    };
+    backend.new_buffer(BufferType::IndexBuffer, BufferUsage::Stream, source)
+}
+
+/// Parameters for [Renderer::new_ex]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RendererParams {
+    /// Maximum amount of vertices per draw call
+    pub max_vertices: usize,
+    /// Maximum amount of indices per draw call
+    pub max_indices: usize,
+    /// The element type of the index buffers.
+    ///
+    /// With [IndexFormat::U16], a single draw call can't have more than 65536 vertices.
+    pub index_format: IndexFormat,
+}
+
+impl Default for RendererParams {
+    fn default() -> Self {
+        Self {
+            max_vertices: 10000,
+            max_indices: 5000,
+            index_format: IndexFormat::U16,
+        }
     }
 }
 
@@ -413,32 +1129,60 @@ where
     max_indices: usize,
 
     batch_vertex_buffer: Vec<V>,
-    batch_index_buffer: Vec<u16>,
+    batch_index_buffer: BatchIndices,
+
+    // Scratch buffers, used when reordering draw calls by layer
+    sorted_vertex_buffer: Vec<V>,
+    sorted_index_buffer: BatchIndices,
+    // Scratch buffer for geometry with a baked model matrix
+    baked_vertex_buffer: Vec<V>,
+
+    /// The first draw call of the draw list being recorded
+    draw_list_start: Option<usize>,
+
+    collect_stats: bool,
+    stats: FrameStats,
 }
 
 impl<V> Renderer<V>
 where V: AsVertex {
+    /// Create a renderer with 16-bit indices
     pub fn new(
         ctx: &mut dyn miniquad::RenderingBackend,
         max_vertices: usize,
         max_indices: usize,
     ) -> Self {
+        Self::new_ex(
+            ctx,
+            RendererParams {
+                max_vertices,
+                max_indices,
+                index_format: IndexFormat::U16,
+            },
+        )
+    }
+
+    /// Create a renderer with custom parameters, for example with 32-bit indices
+    pub fn new_ex(ctx: &mut dyn miniquad::RenderingBackend, params: RendererParams) -> Self {
+        let RendererParams {
+            max_vertices,
+            max_indices,
+            index_format,
+        } = params;
+
+        if max_vertices > index_format.max_vertices() {
+            warn!(
+                "max_vertices {} can't be addressed with {:?} indices, clamping",
+                max_vertices, index_format
+            );
+        }
+        let max_vertices = max_vertices.min(index_format.max_vertices());
+
         let white_texture = ctx.new_texture_from_rgba8(1, 1, &[255, 255, 255, 255]);
 
         Self {
             pipelines: PipelineStorage::new(ctx),
-            state: RendererState {
-                clip: None,
-                viewport: None,
-                texture: None,
-                model_stack: vec![glam::Mat4::IDENTITY],
-                draw_mode: DrawMode::Triangles,
-                pipeline: None,
-                break_batching: false,
-                depth_test_enable: false,
-                render_pass: None,
-                capture: false,
-            },
+            state: RendererState::new(),
             draw_calls: Vec::with_capacity(200),
             draw_calls_bindings: Vec::with_capacity(200),
             draw_calls_count: 0,
@@ -446,9 +1190,15 @@ where V: AsVertex {
 
             white_texture,
             batch_vertex_buffer: Vec::with_capacity(max_vertices),
-            batch_index_buffer: Vec::with_capacity(max_indices),
+            batch_index_buffer: BatchIndices::with_capacity(index_format, max_indices),
+            sorted_vertex_buffer: Vec::new(),
+            sorted_index_buffer: BatchIndices::with_capacity(index_format, 0),
+            baked_vertex_buffer: Vec::new(),
+            draw_list_start: None,
             max_vertices,
             max_indices,
+            collect_stats: false,
+            stats: FrameStats::default(),
         }
     }
 
@@ -460,17 +1210,91 @@ where V: AsVertex {
         uniforms: Vec<UniformDesc>,
         textures: Vec<String>,
     ) -> Result<GlPipeline<V>, Error> {
+        self.make_pipeline_ex(ctx, shader, params, uniforms, textures, None)
+    }
+
+    /// Create a pipeline for instanced drawing with [Renderer::draw_instanced].
+    ///
+    /// On top of the vertex attributes of `V`, the shader receives the per-instance attributes of `I`.
+    pub fn make_instanced_pipeline<I: AsInstance>(
+        &mut self,
+        ctx: &mut dyn miniquad::RenderingBackend,
+        shader: miniquad::ShaderSource,
+        params: PipelineParams,
+        uniforms: Vec<UniformDesc>,
+        textures: Vec<String>,
+    ) -> Result<GlPipeline<V>, Error> {
+        self.make_pipeline_ex(
+            ctx,
+            shader,
+            params,
+            uniforms,
+            textures,
+            Some(InstanceLayout::of::<I>()),
+        )
+    }
+
+    fn make_pipeline_ex(
+        &mut self,
+        ctx: &mut dyn miniquad::RenderingBackend,
+        shader: miniquad::ShaderSource,
+        params: PipelineParams,
+        uniforms: Vec<UniformDesc>,
+        textures: Vec<String>,
+        instance: Option<InstanceLayout>,
+    ) -> Result<GlPipeline<V>, Error> {
+        let shader = Self::new_shader(ctx, shader, &uniforms, &textures)?;
+
+        Ok(self
+            .pipelines
+            .make_pipeline(ctx, shader, params, uniforms, textures, instance))
+    }
+
+    /// Replace the shader of an existing pipeline, for example to reload it after its source
+    /// has changed.
+    ///
+    /// The pipeline keeps its slot, so its [GlPipeline] (and [Material](super::Material)) stays
+    /// valid, along with its pipeline params and the values of uniforms and textures that are
+    /// still present.
+    /// If the shader fails to compile, the error is returned and the pipeline is left as it was.
+    pub fn replace_pipeline(
+        &mut self,
+        ctx: &mut dyn miniquad::RenderingBackend,
+        pipeline: &GlPipeline<V>,
+        shader: miniquad::ShaderSource,
+        uniforms: Vec<UniformDesc>,
+        textures: Vec<String>,
+    ) -> Result<(), Error> {
+        if self.pipelines.get_pipeline(pipeline).is_none() {
+            return Err(Error::UnknownPipeline);
+        }
+
+        let shader = Self::new_shader(ctx, shader, &uniforms, &textures)?;
+        self.state.break_batching = true;
+        self.pipelines
+            .get_pipeline_mut(pipeline)
+            .unwrap()
+            .replace(ctx, shader, uniforms, textures);
+
+        Ok(())
+    }
+
+    fn new_shader(
+        ctx: &mut dyn miniquad::RenderingBackend,
+        shader: miniquad::ShaderSource,
+        uniforms: &[UniformDesc],
+        textures: &[String],
+    ) -> Result<ShaderId, Error> {
         let mut shader_meta: ShaderMeta = shader::meta();
 
-        for uniform in &uniforms {
+        for uniform in uniforms {
             shader_meta.uniforms.uniforms.push(uniform.clone());
         }
 
-        for texture in &textures {
+        for texture in textures {
+            // The name is reserved for the texture that will be drawn with that material
             if texture == "Texture" {
-                panic!(
-                    "you can't use name `Texture` for your texture. This name is reserved for the texture that will be drawn with that material"
-                );
+                return Err(Error::ReservedName(texture.clone()));
             }
             shader_meta.images.push(texture.clone());
         }
@@ -480,16 +1304,17 @@ where V: AsVertex {
         //     ShaderSource::Msl { program } => program,
         // };
 
-        let shader = ctx.new_shader(shader, shader_meta)?;
-
-        Ok(self
-            .pipelines
-            .make_pipeline(ctx, shader, params, uniforms, textures))
+        Ok(ctx.new_shader(shader, shader_meta)?)
     }
 
-    /// Clear the framebuffer with a specified color, then clear the draw calls
+    /// Clear the framebuffer with a specified color (along with the depth and stencil buffers),
+    /// then clear the draw calls
     pub fn clear(&mut self, ctx: &mut dyn miniquad::RenderingBackend, color: Color) {
-        let clear = PassAction::clear_color(color.r, color.g, color.b, color.a);
+        let clear = PassAction::Clear {
+            color: Some((color.r, color.g, color.b, color.a)),
+            depth: Some(1.),
+            stencil: Some(0),
+        };
 
         if let Some(current_pass) = self.state.render_pass {
             ctx.begin_pass(Some(current_pass), clear);
@@ -501,33 +1326,116 @@ where V: AsVertex {
         self.clear_draw_calls();
     }
 
-    /// Reset only draw calls state
+    /// Reset only draw calls state. A draw list being recorded is discarded
     pub fn clear_draw_calls(&mut self) {
         self.draw_calls_count = 0;
+
+        if self.draw_list_start.take().is_some() {
+            warn!("The renderer was drawn or reset while recording a draw list, discarding it");
+        }
     }
 
     /// Reset internal state to known default
     pub fn reset(&mut self) {
-        self.state.clip = None;
+        self.state.clip = Clip::default();
+        self.state.clip_stack.clear();
+        self.state.clip_stacks.clear();
         self.state.texture = None;
         self.state.model_stack = vec![glam::Mat4::IDENTITY];
-        self.draw_calls_count = 0;
+        self.state.layer = 0;
+        self.state.blend_mode = BlendMode::Alpha;
+        self.state.stencil_mode = StencilMode::Disabled;
+        self.clear_draw_calls();
+    }
+
+    /// Sort draw calls by layer, preserving the submission order inside of every layer.
+    ///
+    /// Only draw calls into the same render pass that were submitted one after another are sorted
+    /// together, so a pass that renders into a texture still ends before the draw calls that
+    /// sample it, whatever their layers are.
+    ///
+    /// Draw calls that end up next to each other after sorting are merged where possible, so the
+    /// geometry is copied into the scratch buffers in the new order.
+    fn sort_draw_calls(&mut self) {
+        let draw_calls = &mut self.draw_calls[..self.draw_calls_count];
+        let sorted = draw_calls.windows(2).all(|dcs| {
+            dcs[0].render_pass != dcs[1].render_pass || dcs[0].layer <= dcs[1].layer
+        });
+        if sorted {
+            return;
+        }
+        // A stable sort, so the submission order is kept inside of a layer
+        for run in draw_calls.chunk_by_mut(|a, b| a.render_pass == b.render_pass) {
+            run.sort_by_key(|dc| dc.layer);
+        }
+
+        let vertices = &mut self.sorted_vertex_buffer;
+        let indices = &mut self.sorted_index_buffer;
+        vertices.clear();
+        indices.clear();
+
+        let mut count = 0;
+        for ix in 0..self.draw_calls_count {
+            if self.draw_calls[ix].geometry != Geometry::Batched {
+                self.draw_calls.swap(count, ix);
+                count += 1;
+                continue;
+            }
+
+            let merge = count > 0
+                && self.draw_calls[count - 1].can_merge(
+                    &self.draw_calls[ix],
+                    self.max_vertices,
+                    self.max_indices,
+                );
+
+            let dc = &self.draw_calls[ix];
+            let (vertices_start, vertices_count) = (dc.vertices_start, dc.vertices_count);
+            let (indices_start, indices_count) = (dc.indices_start, dc.indices_count);
+
+            if !merge {
+                self.draw_calls.swap(count, ix);
+
+                let dc = &mut self.draw_calls[count];
+                dc.vertices_start = vertices.len();
+                dc.indices_start = indices.len();
+                dc.vertices_count = 0;
+                dc.indices_count = 0;
+                count += 1;
+            }
+
+            let dc = &mut self.draw_calls[count - 1];
+            vertices.extend_from_slice(
+                &self.batch_vertex_buffer[vertices_start..(vertices_start + vertices_count)],
+            );
+            indices.extend_from(
+                &self.batch_index_buffer,
+                indices_start,
+                indices_count,
+                dc.vertices_count as u32,
+            );
+            dc.vertices_count += vertices_count;
+            dc.indices_count += indices_count;
+        }
+
+        self.draw_calls_count = count;
+        std::mem::swap(&mut self.batch_vertex_buffer, &mut self.sorted_vertex_buffer);
+        std::mem::swap(&mut self.batch_index_buffer, &mut self.sorted_index_buffer);
     }
 
     pub fn draw(&mut self, ctx: &mut dyn miniquad::RenderingBackend, projection: glam::Mat4) {
         let white_texture = self.white_texture;
 
+        self.sort_draw_calls();
+
         for _ in 0..self.draw_calls.len() - self.draw_calls_bindings.len() {
             let vertex_buffer = ctx.new_buffer(
                 BufferType::VertexBuffer,
                 BufferUsage::Stream,
                 BufferSource::empty::<V>(self.max_vertices),
             );
-            let index_buffer = ctx.new_buffer(
-                BufferType::IndexBuffer,
-                BufferUsage::Stream,
-                BufferSource::empty::<u16>(self.max_indices),
-            );
+            let index_buffer =
+                new_index_buffer(ctx, self.batch_index_buffer.format(), self.max_indices);
             let bindings = Bindings {
                 vertex_buffers: vec![vertex_buffer],
                 index_buffer,
@@ -538,23 +1446,28 @@ where V: AsVertex {
         }
         assert_eq!(self.draw_calls_bindings.len(), self.draw_calls.len());
 
-        let (screen_width, screen_height) = miniquad::window::screen_size();
         let time = (miniquad::date::now() - self.start_time) as f32;
         let time = glam::vec4(time, time.sin(), time.cos(), 0.);
 
+        let mut previous_pipeline = None;
+        let mut previous_textures = vec![];
+
         for (dc, bindings) in self.draw_calls[0..self.draw_calls_count]
             .iter_mut()
             .zip(self.draw_calls_bindings.iter_mut())
         {
-            // ! We unwrap here, since a draw call can't possibly be added with a pipeline that isn't available
-            // ! in the storage
-            let pipeline = self.pipelines.get_pipeline_mut(&dc.pipeline).unwrap();
+            let Some(pipeline) = self.pipelines.get_draw_pipeline(ctx, dc.pipeline) else {
+                warn!("Skipping a draw call, its pipeline was deleted");
+                dc.reset();
+                continue;
+            };
 
             let (width, height) = if let Some(render_pass) = dc.render_pass {
-                let render_texture = ctx.render_pass_texture(render_pass);
+                let render_texture = ctx.render_pass_color_attachments(render_pass)[0];
                 let (width, height) = ctx.texture_size(render_texture);
                 (width, height)
             } else {
+                let (screen_width, screen_height) = miniquad::window::screen_size();
                 (screen_width as u32, screen_height as u32)
             };
 
@@ -564,20 +1477,20 @@ where V: AsVertex {
                 ctx.begin_default_pass(PassAction::Nothing);
             }
 
-            ctx.buffer_update(
-                bindings.vertex_buffers[0],
-                BufferSource::slice(
-                    &self.batch_vertex_buffer
-                        [dc.vertices_start..(dc.vertices_start + dc.vertices_count)],
-                ),
-            );
-            ctx.buffer_update(
-                bindings.index_buffer,
-                BufferSource::slice(
-                    &self.batch_index_buffer
-                        [dc.indices_start..(dc.indices_start + dc.indices_count)],
-                ),
-            );
+            if dc.geometry == Geometry::Batched {
+                ctx.buffer_update(
+                    bindings.vertex_buffers[0],
+                    BufferSource::slice(
+                        &self.batch_vertex_buffer
+                            [dc.vertices_start..(dc.vertices_start + dc.vertices_count)],
+                    ),
+                );
+                ctx.buffer_update(
+                    bindings.index_buffer,
+                    self.batch_index_buffer
+                        .source(dc.indices_start, dc.indices_count),
+                );
+            }
 
             bindings.images[0] = dc.texture.unwrap_or(white_texture);
             bindings
@@ -586,7 +1499,23 @@ where V: AsVertex {
 
             for (pos, name) in pipeline.textures.iter().enumerate() {
                 if let Some(texture) = pipeline.textures_data.get(name).copied() {
-                    bindings.images[2 + pos] = texture;
+                    bindings.images[1 + pos] = texture;
+                }
+            }
+
+            if self.collect_stats {
+                self.stats.draw_calls += 1;
+                self.stats.indices += dc.indices_count;
+                if dc.geometry == Geometry::Batched {
+                    self.stats.vertices += dc.vertices_count;
+                }
+                if previous_pipeline != Some(pipeline.pipeline) {
+                    self.stats.pipeline_switches += 1;
+                    previous_pipeline = Some(pipeline.pipeline);
+                }
+                if previous_textures != bindings.images {
+                    self.stats.texture_switches += 1;
+                    previous_textures.clone_from(&bindings.images);
                 }
             }
 
@@ -596,12 +1525,25 @@ where V: AsVertex {
             } else {
                 ctx.apply_viewport(0, 0, width as i32, height as i32);
             }
-            if let Some(clip) = dc.clip {
-                ctx.apply_scissor_rect(clip.0, height as i32 - (clip.1 + clip.3), clip.2, clip.3);
-            } else {
-                ctx.apply_scissor_rect(0, 0, width as i32, height as i32);
+            let (x, y, w, h) = dc.clip.resolve(dc.viewport, width as i32, height as i32);
+            ctx.apply_scissor_rect(x, y, w, h);
+            match dc.geometry {
+                Geometry::Batched => ctx.apply_bindings(bindings),
+                Geometry::Static {
+                    vertex_buffer,
+                    index_buffer,
+                } => ctx.apply_bindings_from_slice(&[vertex_buffer], index_buffer, &bindings.images),
+                Geometry::Instanced {
+                    vertex_buffer,
+                    index_buffer,
+                    instance_buffer,
+                    ..
+                } => ctx.apply_bindings_from_slice(
+                    &[vertex_buffer, instance_buffer],
+                    index_buffer,
+                    &bindings.images,
+                ),
             }
-            ctx.apply_bindings(bindings);
 
             if let Some(ref uniforms) = dc.uniforms {
                 // for i in 0..uniforms.len() {
@@ -616,7 +1558,11 @@ where V: AsVertex {
                 pipeline.uniforms_data.as_ptr(),
                 pipeline.uniforms_data.len(),
             );
-            ctx.draw(0, dc.indices_count as i32, 1);
+            let instances = match dc.geometry {
+                Geometry::Instanced { instances, .. } => instances,
+                _ => 1,
+            };
+            ctx.draw(0, dc.indices_count as i32, instances as i32);
             ctx.end_render_pass();
 
             // TODO: Telemetry
@@ -624,35 +1570,133 @@ where V: AsVertex {
             //     telemetry::track_drawcall(&pipeline.pipeline, bindings, dc.indices_count);
             // }
 
-            dc.vertices_count = 0;
-            dc.indices_count = 0;
-            dc.vertices_start = 0;
-            dc.indices_start = 0;
+            dc.reset();
         }
 
-        self.draw_calls_count = 0;
+        self.clear_draw_calls();
         self.batch_index_buffer.clear();
         self.batch_vertex_buffer.clear();
-    }
 
-    pub(crate) fn with_capture(&mut self, capture: bool) {
-        self.state.capture = capture;
+        // Pipelines of materials dropped this frame were still used by the draw calls above
+        self.release_pipelines(ctx);
     }
 
-    pub const fn get_active_render_pass(&self) -> Option<RenderPass> {
-        self.state.render_pass
+    /// Start or stop collecting [FrameStats]. They aren't collected by default.
+    ///
+    /// Statistics are kept until they're taken with [Renderer::take_frame_stats], so while
+    /// collecting, take them once per frame
+    pub fn with_stats(&mut self, collect: bool) {
+        self.collect_stats = collect;
     }
 
-    pub const fn is_depth_test_enabled(&self) -> bool {
-        self.state.depth_test_enable
+    pub const fn is_collecting_stats(&self) -> bool {
+        self.collect_stats
     }
 
-    pub fn with_render_pass(&mut self, render_pass: Option<RenderPass>) {
-        self.state.render_pass = render_pass;
+    /// Statistics collected since the last [Renderer::take_frame_stats]
+    pub const fn frame_stats(&self) -> &FrameStats {
+        &self.stats
     }
 
-    pub fn with_depth_test(&mut self, enable: bool) {
-        self.state.depth_test_enable = enable;
+    /// Take the statistics collected since the last call, and start collecting anew.
+    ///
+    /// Call it once per frame, after the last [Renderer::draw] of the frame
+    pub fn take_frame_stats(&mut self) -> FrameStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    fn record_batch_break(&mut self, reason: BatchBreak) {
+        if self.collect_stats {
+            self.stats.batch_breaks.push(reason);
+        }
+    }
+
+    /// Replace the draw state with the default one, returning the previous state.
+    ///
+    /// Used by code that draws on its own (like post-processing), so the state of the caller can be
+    /// restored with [Renderer::restore_state] after.
+    pub(crate) fn take_state(&mut self) -> RendererState<V> {
+        std::mem::replace(&mut self.state, RendererState::new())
+    }
+
+    pub(crate) fn restore_state(&mut self, state: RendererState<V>) {
+        self.state = state;
+        self.state.break_batching = true;
+    }
+
+    pub(crate) fn with_capture(&mut self, capture: bool) {
+        self.state.capture = capture;
+    }
+
+    pub const fn get_active_render_pass(&self) -> Option<RenderPass> {
+        self.state.render_pass
+    }
+
+    pub const fn is_depth_test_enabled(&self) -> bool {
+        self.state.depth_test_enable
+    }
+
+    pub fn with_render_pass(&mut self, render_pass: Option<RenderPass>) {
+        let state = &mut self.state;
+        if state.render_pass != render_pass {
+            let stack = std::mem::take(&mut state.clip_stack);
+            if !stack.is_empty() {
+                state.clip_stacks.push((state.render_pass, stack));
+            }
+            if let Some(ix) = state
+                .clip_stacks
+                .iter()
+                .position(|(pass, _)| *pass == render_pass)
+            {
+                state.clip_stack = state.clip_stacks.swap_remove(ix).1;
+            }
+            state.clip.stack = state.clip_stack.last().copied();
+        }
+
+        state.render_pass = render_pass;
+    }
+
+    /// Set the layer of the following draw calls.
+    ///
+    /// On [Renderer::draw], draw calls are sorted by layer, from the lowest to the highest, while
+    /// the submission order inside of a layer is preserved. Layers only apply inside of a render
+    /// pass: draw calls are never moved before or after the ones of another pass. The default
+    /// layer is 0.
+    pub fn with_layer(&mut self, layer: i32) {
+        self.state.layer = layer;
+    }
+
+    pub const fn get_layer(&self) -> i32 {
+        self.state.layer
+    }
+
+    pub fn with_depth_test(&mut self, enable: bool) {
+        self.state.depth_test_enable = enable;
+    }
+
+    /// Set the blend mode of the default pipelines.
+    ///
+    /// It has no effect on custom pipelines (materials), since their blending is defined by their own
+    /// [PipelineParams].
+    pub fn with_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.state.blend_mode = blend_mode;
+    }
+
+    pub const fn get_blend_mode(&self) -> BlendMode {
+        self.state.blend_mode
+    }
+
+    /// Set the stencil mode of the default pipelines, to draw a mask and then draw only inside or
+    /// outside of it. The mask is cleared by [Renderer::clear].
+    ///
+    /// Like [Renderer::with_blend_mode], it has no effect on custom pipelines. Materials can be
+    /// masked as well, by creating them with [StencilMode::apply] params.
+    pub fn with_stencil_mode(&mut self, stencil_mode: StencilMode) {
+        self.state.stencil_mode = stencil_mode;
+    }
+
+    pub const fn get_stencil_mode(&self) -> StencilMode {
+        self.state.stencil_mode
     }
 
     pub fn with_texture(&mut self, texture: Option<&TextureId>) {
@@ -660,8 +1704,104 @@ where V: AsVertex {
         self.state.texture = texture.copied();
     }
 
+    /// Set a scissor rectangle in framebuffer pixels, from the top-left corner of the framebuffer.
+    ///
+    /// It's intersected with the clip stack ([Renderer::push_clip]), but otherwise independent
+    /// of it: it stays the same when render passes change
     pub fn with_scissor(&mut self, clip: Option<(i32, i32, i32, i32)>) {
-        self.state.clip = clip;
+        self.state.clip.scissor = clip;
+    }
+
+    /// Clip the following draw calls to a rectangle, intersected with the current clip,
+    /// until the matching [Renderer::pop_clip]. Useful for nested panels and scroll areas.
+    ///
+    /// The rectangle is in logical pixels from the top-left corner of the viewport, the coordinates
+    /// of [pixel_perfect_projection_matrix](super::camera::pixel_perfect_projection_matrix).
+    /// On the screen they're scaled by the dpi scale, render targets have none.
+    ///
+    /// Every render pass has its own clip stack: switching to another render pass starts
+    /// without a clip, and switching back restores the clip stack of that pass.
+    ///
+    /// To clip to a rectangle in the coordinates of a camera, see [Renderer::push_clip_world].
+    pub fn push_clip(&mut self, rect: Rect) {
+        let scale = match self.state.render_pass {
+            Some(_) => 1.0,
+            None => miniquad::window::dpi_scale(),
+        };
+        self.push_clip_pixels(
+            rect.x * scale,
+            rect.y * scale,
+            (rect.x + rect.w) * scale,
+            (rect.y + rect.h) * scale,
+        );
+    }
+
+    /// Like [Renderer::push_clip], but the rectangle is in the world coordinates of a camera,
+    /// so it matches what's drawn with the camera's [matrix](super::Camera::matrix), whatever
+    /// its zoom and offset. With a rotation, the clip is the bounding box of the rotated
+    /// rectangle.
+    ///
+    /// The camera should be the one of the current render pass and viewport. The backend is only
+    /// used to get the size of the camera's render target.
+    pub fn push_clip_world(
+        &mut self,
+        ctx: &mut dyn RenderingBackend,
+        rect: Rect,
+        camera: &dyn super::Camera,
+    ) {
+        let (width, height) = match (camera.viewport(), camera.render_pass()) {
+            (Some((_, _, w, h)), _) => (w as f32, h as f32),
+            (None, Some(pass)) => {
+                let params = ctx.texture_params(pass.color_texture);
+                (params.width as f32, params.height as f32)
+            }
+            (None, None) => miniquad::window::screen_size(),
+        };
+
+        let matrix = camera.matrix();
+        let corners = [
+            rect.point(),
+            rect.point() + glam::vec2(rect.w, 0.),
+            rect.point() + glam::vec2(0., rect.h),
+            rect.point() + rect.size(),
+        ]
+        .map(|corner| matrix.project_point3(corner.extend(0.)));
+
+        let (min, max) = corners
+            .iter()
+            .fold((glam::Vec3::MAX, glam::Vec3::MIN), |(min, max), corner| {
+                (min.min(*corner), max.max(*corner))
+            });
+
+        // From normalized device coordinates to pixels from the top-left corner of the viewport
+        self.push_clip_pixels(
+            (min.x + 1.) / 2. * width,
+            (1. - max.y) / 2. * height,
+            (max.x + 1.) / 2. * width,
+            (1. - min.y) / 2. * height,
+        );
+    }
+
+    /// Push a clip in framebuffer pixels from the top-left corner of the viewport, rounding outward
+    fn push_clip_pixels(&mut self, left: f32, top: f32, right: f32, bottom: f32) {
+        let (left, top) = (left.floor() as i32, top.floor() as i32);
+        let (right, bottom) = (right.ceil() as i32, bottom.ceil() as i32);
+
+        let mut clip = (left, top, (right - left).max(0), (bottom - top).max(0));
+        if let Some(parent) = self.state.clip_stack.last() {
+            clip = intersect_rect(*parent, clip);
+        }
+
+        self.state.clip_stack.push(clip);
+        self.state.clip.stack = Some(clip);
+    }
+
+    /// Restore the clip from before the last [Renderer::push_clip] of the current render pass
+    pub fn pop_clip(&mut self) {
+        if self.state.clip_stack.pop().is_none() {
+            warn!("pop_clip() without a matching push_clip()");
+        }
+        self.state.clip.stack = self.state.clip_stack.last().copied();
     }
 
     pub fn with_viewport(&mut self, viewport: Option<(i32, i32, i32, i32)>) {
@@ -689,16 +1829,58 @@ where V: AsVertex {
         }
     }
 
+    /// Translate the following geometry in 2D, on top of the current transform.
+    /// Undone with [Renderer::pop_transform]
+    pub fn push_translation(&mut self, offset: glam::Vec2) {
+        self.push_model_matrix(glam::Mat4::from_translation(offset.extend(0.)));
+    }
+
+    /// Rotate the following geometry in 2D around the origin, on top of the current transform.
+    /// Undone with [Renderer::pop_transform]
+    pub fn push_rotation(&mut self, radians: f32) {
+        self.push_model_matrix(glam::Mat4::from_rotation_z(radians));
+    }
+
+    /// Scale the following geometry in 2D from the origin, on top of the current transform.
+    /// Undone with [Renderer::pop_transform]
+    pub fn push_scale(&mut self, scale: glam::Vec2) {
+        self.push_model_matrix(glam::Mat4::from_scale(scale.extend(1.)));
+    }
+
+    /// Undo the last [Renderer::push_translation], [Renderer::push_rotation] or
+    /// [Renderer::push_scale]. The same as [Renderer::pop_model_matrix]
+    pub fn pop_transform(&mut self) {
+        if self.pop_model_matrix().is_none() {
+            warn!("pop_transform() without a matching push");
+        }
+    }
+
+    /// Transform batched geometry by the model matrix on the CPU, instead of on the GPU.
+    ///
+    /// Every model matrix change starts a new draw call otherwise, since the matrix is a uniform.
+    /// With baked transforms, lots of small transformed sprites are still batched together,
+    /// for the cost of transforming their vertices when they're pushed.
+    ///
+    /// Only vertices with a position can be transformed, see [AsVertex::transformed].
+    /// Static meshes and instanced draws always use the model matrix uniform.
+    pub fn with_baked_transforms(&mut self, bake: bool) {
+        self.state.bake_transforms = bake;
+    }
+
+    pub const fn is_baking_transforms(&self) -> bool {
+        self.state.bake_transforms
+    }
+
     pub fn with_pipeline(&mut self, pipeline: Option<GlPipeline<V>>) {
         if self.state.pipeline == pipeline {
             return;
         }
 
         if let Some(ref pip) = pipeline {
-            assert!(
-                self.has_pipeline(pip),
-                "The provided pipeline isn't present in the renderer"
-            )
+            if self.pipelines.get_pipeline(pip).is_none() {
+                warn!("The provided pipeline isn't present in the renderer");
+                return;
+            }
         }
 
         self.state.break_batching = true;
@@ -711,6 +1893,14 @@ where V: AsVertex {
 
     /// TODO: Document this
     pub fn push_geometry(&mut self, vertices: &[V], indices: &[u16]) {
+        self.push_geometry_indexed(vertices, indices);
+    }
+
+    /// The same as [Renderer::push_geometry], but accepts any index type.
+    ///
+    /// [u32] indices can be pushed into a renderer with [IndexFormat::U16] as well, as long as
+    /// the geometry has no more than 65536 vertices.
+    pub fn push_geometry_indexed<I: AsIndex>(&mut self, vertices: &[V], indices: &[I]) {
         if vertices.len() >= self.max_vertices || indices.len() >= self.max_indices {
             warn!("geometry() exceeded max drawcall size, clamping");
         }
@@ -718,83 +1908,397 @@ where V: AsVertex {
         let vertices = &vertices[0..self.max_vertices.min(vertices.len())];
         let indices = &indices[0..self.max_indices.min(indices.len())];
 
-        let pip = self.state.pipeline.unwrap_or(
-            self.pipelines
-                .get_default_pipeline_by(self.state.draw_mode, self.state.depth_test_enable),
-        );
+        let pip = self.current_pipeline(self.state.draw_mode);
 
-        let previous_dc_ix = if self.draw_calls_count == 0 {
-            None
+        let mut model = self.state.model();
+        let mut baked = std::mem::take(&mut self.baked_vertex_buffer);
+        baked.clear();
+        if self.state.bake_transforms && model != glam::Mat4::IDENTITY {
+            baked.extend(vertices.iter().map_while(|vertex| vertex.transformed(&model)));
+        }
+        // Vertices without a position are left to the model matrix
+        let vertices = if !baked.is_empty() && baked.len() == vertices.len() {
+            model = glam::Mat4::IDENTITY;
+            &baked[..]
         } else {
-            Some(self.draw_calls_count - 1)
+            vertices
         };
-        let previous_dc = previous_dc_ix.and_then(|ix| self.draw_calls.get(ix));
-
-        if previous_dc.map_or(true, |draw_call| {
-            draw_call.texture != self.state.texture
-                || draw_call.clip != self.state.clip
-                || draw_call.viewport != self.state.viewport
-                || draw_call.model != self.state.model()
-                || draw_call.pipeline != pip
-                || draw_call.render_pass != self.state.render_pass
-                || draw_call.draw_mode != self.state.draw_mode
-                || draw_call.vertices_count >= self.max_vertices - vertices.len()
-                || draw_call.indices_count >= self.max_indices - indices.len()
-                || draw_call.capture != self.state.capture
-                || self.state.break_batching
-        }) {
-            let uniforms = self.state.pipeline.map(|pipeline| {
-                self.pipelines
-                    .get_pipeline_mut(&pipeline)
-                    .unwrap()
-                    .uniforms_data
-                    .clone()
-            });
 
-            if self.draw_calls_count >= self.draw_calls.len() {
-                self.draw_calls.push(DrawCall::new(
-                    self.state.texture,
-                    self.state.model(),
-                    self.state.draw_mode,
-                    pip,
-                    uniforms.clone(),
-                    self.state.render_pass,
-                ));
-            }
+        let previous_dc = self
+            .draw_calls_count
+            .checked_sub(1)
+            .and_then(|ix| self.draw_calls.get(ix));
+        let batch_break = previous_dc.map(|draw_call| {
+            self.batch_break(draw_call, pip, model, vertices.len(), indices.len())
+        });
 
-            self.draw_calls[self.draw_calls_count].texture = self.state.texture;
-            self.draw_calls[self.draw_calls_count].uniforms = uniforms;
-            self.draw_calls[self.draw_calls_count].vertices_count = 0;
-            self.draw_calls[self.draw_calls_count].indices_count = 0;
-            self.draw_calls[self.draw_calls_count].clip = self.state.clip;
-            self.draw_calls[self.draw_calls_count].viewport = self.state.viewport;
-            self.draw_calls[self.draw_calls_count].model = self.state.model();
-            self.draw_calls[self.draw_calls_count].pipeline = pip;
-            self.draw_calls[self.draw_calls_count].render_pass = self.state.render_pass;
-            self.draw_calls[self.draw_calls_count].capture = self.state.capture;
-            self.draw_calls[self.draw_calls_count].indices_start = self.batch_index_buffer.len();
-            self.draw_calls[self.draw_calls_count].vertices_start = self.batch_vertex_buffer.len();
-
-            self.draw_calls_count += 1;
-            self.state.break_batching = false;
+        // No previous draw call, or one the geometry can't be appended to
+        if batch_break != Some(None) {
+            if let Some(Some(reason)) = batch_break {
+                self.record_batch_break(reason);
+            }
+            self.begin_draw_call(
+                pip,
+                Geometry::Batched,
+                self.state.texture,
+                self.state.draw_mode,
+                model,
+            );
         };
 
         let dc = &mut self.draw_calls[self.draw_calls_count - 1];
 
         self.batch_vertex_buffer.extend(vertices);
         self.batch_index_buffer
-            .extend(indices.iter().map(|x| *x + dc.vertices_count as u16));
+            .extend(indices, dc.vertices_count as u32);
 
         dc.vertices_count += vertices.len();
         dc.indices_count += indices.len();
 
         dc.texture = self.state.texture;
+        self.baked_vertex_buffer = baked;
     }
 
+    /// Why geometry can't be appended to the previous draw call, if it can't
+    fn batch_break(
+        &self,
+        draw_call: &DrawCall<V>,
+        pipeline: DrawPipeline<V>,
+        model: glam::Mat4,
+        vertices: usize,
+        indices: usize,
+    ) -> Option<BatchBreak> {
+        let state = &self.state;
+
+        Some(if draw_call.geometry != Geometry::Batched {
+            BatchBreak::Geometry
+        } else if draw_call.render_pass != state.render_pass {
+            BatchBreak::RenderPass
+        } else if draw_call.layer != state.layer {
+            BatchBreak::Layer
+        } else if draw_call.draw_mode != state.draw_mode {
+            BatchBreak::DrawMode
+        } else if draw_call.pipeline != pipeline {
+            BatchBreak::Pipeline
+        } else if draw_call.texture != state.texture {
+            BatchBreak::Texture
+        } else if draw_call.clip != state.clip {
+            BatchBreak::Clip
+        } else if draw_call.viewport != state.viewport {
+            BatchBreak::Viewport
+        } else if draw_call.model != model {
+            BatchBreak::ModelMatrix
+        } else if draw_call.vertices_count >= self.max_vertices - vertices
+            || draw_call.indices_count >= self.max_indices - indices
+        {
+            BatchBreak::Capacity
+        } else if state.break_batching || draw_call.capture != state.capture {
+            BatchBreak::Explicit
+        } else {
+            return None;
+        })
+    }
+
+    /// The pipeline for the next draw call: either the current custom one, or a default one
+    /// for the current draw state.
+    ///
+    /// Instanced pipelines can only draw instances, so the default pipeline is used instead
+    /// (the opposite of the mismatch [Renderer::draw_instanced] rejects).
+    fn current_pipeline(&self, draw_mode: DrawMode) -> DrawPipeline<V> {
+        match self.state.pipeline {
+            Some(pipeline)
+                if self
+                    .pipelines
+                    .get_pipeline(&pipeline)
+                    .is_some_and(|pipeline| pipeline.instance.is_some()) =>
+            {
+                warn!("The current pipeline is instanced, drawing with the default pipeline");
+                DrawPipeline::Default(self.default_pipeline(draw_mode, false))
+            }
+            Some(pipeline) => DrawPipeline::Custom(pipeline),
+            None => DrawPipeline::Default(self.default_pipeline(draw_mode, false)),
+        }
+    }
+
+    fn default_pipeline(&self, draw_mode: DrawMode, instanced: bool) -> DefaultPipeline {
+        DefaultPipeline {
+            draw_mode,
+            depth_test: self.state.depth_test_enable,
+            blend_mode: self.state.blend_mode,
+            stencil_mode: self.state.stencil_mode,
+            instanced,
+        }
+    }
+
+    /// Start a new draw call with the current state
+    fn begin_draw_call(
+        &mut self,
+        pip: DrawPipeline<V>,
+        geometry: Geometry,
+        texture: Option<miniquad::TextureId>,
+        draw_mode: DrawMode,
+        model: glam::Mat4,
+    ) {
+        let uniforms = self.state.pipeline.and_then(|pipeline| {
+            self.pipelines
+                .get_pipeline(&pipeline)
+                .map(|pipeline| pipeline.uniforms_data.clone())
+        });
+
+        if self.draw_calls_count >= self.draw_calls.len() {
+            self.draw_calls.push(DrawCall::new(
+                texture,
+                model,
+                draw_mode,
+                pip,
+                uniforms.clone(),
+                self.state.render_pass,
+            ));
+        }
+
+        self.draw_calls[self.draw_calls_count].geometry = geometry;
+        self.draw_calls[self.draw_calls_count].texture = texture;
+        self.draw_calls[self.draw_calls_count].draw_mode = draw_mode;
+        self.draw_calls[self.draw_calls_count].uniforms = uniforms;
+        self.draw_calls[self.draw_calls_count].vertices_count = 0;
+        self.draw_calls[self.draw_calls_count].indices_count = 0;
+        self.draw_calls[self.draw_calls_count].clip = self.state.clip;
+        self.draw_calls[self.draw_calls_count].viewport = self.state.viewport;
+        self.draw_calls[self.draw_calls_count].model = model;
+        self.draw_calls[self.draw_calls_count].pipeline = pip;
+        self.draw_calls[self.draw_calls_count].render_pass = self.state.render_pass;
+        self.draw_calls[self.draw_calls_count].capture = self.state.capture;
+        self.draw_calls[self.draw_calls_count].layer = self.state.layer;
+        self.draw_calls[self.draw_calls_count].indices_start = self.batch_index_buffer.len();
+        self.draw_calls[self.draw_calls_count].vertices_start = self.batch_vertex_buffer.len();
+
+        self.draw_calls_count += 1;
+        self.state.break_batching = false;
+    }
+
+    /// Submit a [StaticMesh] as a separate draw call, without copying its geometry.
+    ///
+    /// The current model matrix, pipeline, render pass, scissor, viewport and depth test are used,
+    /// while the texture is taken from the mesh.
+    pub fn draw_static_mesh(&mut self, mesh: &StaticMesh<V>) {
+        let pip = self.current_pipeline(DrawMode::Triangles);
+
+        if self.draw_calls_count > 0 {
+            self.record_batch_break(BatchBreak::Geometry);
+        }
+        self.begin_draw_call(
+            pip,
+            Geometry::Static {
+                vertex_buffer: mesh.vertex_buffer(),
+                index_buffer: mesh.index_buffer(),
+            },
+            mesh.texture,
+            DrawMode::Triangles,
+            self.state.model(),
+        );
+        self.draw_calls[self.draw_calls_count - 1].indices_count = mesh.indices_count();
+    }
+
+    /// Draw a [StaticMesh] once for every instance in the [InstanceBuffer], in a single draw call.
+    ///
+    /// Without a custom pipeline, the default instanced pipeline is used, which only supports [Instance].
+    /// Custom instance types require a pipeline created with [Renderer::make_instanced_pipeline]
+    /// (or an instanced material) for the same instance type. Otherwise, a warning is logged and
+    /// nothing is drawn.
+    pub fn draw_instanced<I: AsInstance>(
+        &mut self,
+        mesh: &StaticMesh<V>,
+        instances: &InstanceBuffer<I>,
+    ) {
+        if instances.is_empty() {
+            return;
+        }
+
+        let pip = match self.state.pipeline {
+            Some(pipeline) => {
+                let Some(pipeline_ext) = self.pipelines.get_pipeline(&pipeline) else {
+                    warn!("The current pipeline was deleted");
+                    return;
+                };
+                let pipeline_instance = pipeline_ext
+                    .instance
+                    .as_ref()
+                    .map(|instance| instance.type_id);
+                if pipeline_instance != Some(TypeId::of::<I>()) {
+                    warn!("The current pipeline wasn't created for this instance type");
+                    return;
+                }
+                DrawPipeline::Custom(pipeline)
+            }
+            None if TypeId::of::<I>() == TypeId::of::<Instance>() => {
+                DrawPipeline::Default(self.default_pipeline(DrawMode::Triangles, true))
+            }
+            None => {
+                warn!("Custom instance types can only be drawn with an instanced pipeline");
+                return;
+            }
+        };
+
+        if self.draw_calls_count > 0 {
+            self.record_batch_break(BatchBreak::Geometry);
+        }
+        self.begin_draw_call(
+            pip,
+            Geometry::Instanced {
+                vertex_buffer: mesh.vertex_buffer(),
+                index_buffer: mesh.index_buffer(),
+                instance_buffer: instances.buffer(),
+                instances: instances.len(),
+            },
+            mesh.texture,
+            DrawMode::Triangles,
+            self.state.model(),
+        );
+        self.draw_calls[self.draw_calls_count - 1].indices_count = mesh.indices_count();
+    }
+
+    /// Start recording the following draw calls into a [DrawList], until [Renderer::end_draw_list].
+    ///
+    /// Recorded geometry is still drawn this frame. The recording has to end before
+    /// [Renderer::draw] or [Renderer::reset], which discard it.
+    pub fn begin_draw_list(&mut self) {
+        if self.draw_list_start.is_some() {
+            warn!("begin_draw_list() while already recording a draw list, restarting it");
+        }
+
+        // So the recorded geometry doesn't end up in a draw call started before the recording
+        self.state.break_batching = true;
+        self.draw_list_start = Some(self.draw_calls_count);
+    }
+
+    /// Finish recording a [DrawList], started with [Renderer::begin_draw_list]
+    pub fn end_draw_list(&mut self) -> DrawList<V> {
+        let mut list = DrawList::new();
+        let Some(start) = self.draw_list_start.take() else {
+            warn!("end_draw_list() without a matching begin_draw_list()");
+            return list;
+        };
+        for dc in &self.draw_calls[start..self.draw_calls_count] {
+            let vertices_start = list.vertices.len();
+            let indices_start = list.indices.len();
+            if dc.geometry == Geometry::Batched {
+                let vertices = dc.vertices_start..(dc.vertices_start + dc.vertices_count);
+                list.vertices
+                    .extend_from_slice(&self.batch_vertex_buffer[vertices]);
+                self.batch_index_buffer
+                    .copy_to(dc.indices_start, dc.indices_count, &mut list.indices);
+            }
+
+            list.draw_calls.push(RecordedDrawCall {
+                geometry: dc.geometry,
+                vertices: vertices_start..list.vertices.len(),
+                indices: indices_start..list.indices.len(),
+                indices_count: dc.indices_count,
+                clip: dc.clip,
+                viewport: dc.viewport,
+                texture: dc.texture,
+                model: dc.model,
+                draw_mode: dc.draw_mode,
+                pipeline: dc.pipeline,
+                uniforms: dc.uniforms.clone(),
+                layer: dc.layer,
+            });
+        }
+
+        list
+    }
+
+    /// Draw a recorded [DrawList] into the current render pass
+    pub fn draw_list(&mut self, list: &DrawList<V>) {
+        self.draw_list_ex(list, glam::Mat4::IDENTITY);
+    }
+
+    /// Draw a recorded [DrawList] into the current render pass, with `transform` applied
+    /// on top of the current model matrix and the model matrices the list was recorded with.
+    ///
+    /// Every draw call of the list stays a separate draw call.
+    pub fn draw_list_ex(&mut self, list: &DrawList<V>, transform: glam::Mat4) {
+        let model = self.state.model() * transform;
+
+        for call in &list.draw_calls {
+            if call.vertices.len() > self.max_vertices || call.indices.len() > self.max_indices {
+                warn!("A draw call of the draw list doesn't fit into this renderer, skipping");
+                continue;
+            }
+
+            if self.draw_calls_count > 0 {
+                self.record_batch_break(BatchBreak::DrawList);
+            }
+            self.begin_draw_call(
+                call.pipeline,
+                call.geometry,
+                call.texture,
+                call.draw_mode,
+                model * call.model,
+            );
+
+            let dc = &mut self.draw_calls[self.draw_calls_count - 1];
+            dc.clip = call.clip;
+            dc.viewport = call.viewport;
+            dc.uniforms.clone_from(&call.uniforms);
+            dc.layer = call.layer;
+
+            if call.geometry == Geometry::Batched {
+                self.batch_vertex_buffer
+                    .extend_from_slice(&list.vertices[call.vertices.clone()]);
+                self.batch_index_buffer
+                    .extend(&list.indices[call.indices.clone()], 0);
+                dc.vertices_count = call.vertices.len();
+                dc.indices_count = call.indices.len();
+            } else {
+                dc.indices_count = call.indices_count;
+            }
+        }
+
+        // The state of the last draw call is the one of the list, not the current one
+        self.state.break_batching = true;
+    }
+
+    /// Push the geometry of a [CommandBuffer], with the textures and draw modes it was recorded
+    /// with. Everything else, like the pipeline and the model matrix, is the current state.
+    ///
+    /// The geometry is batched as if it was pushed into the renderer directly.
+    pub fn append_command_buffer(&mut self, buffer: &CommandBuffer<V>) {
+        let texture = self.state.texture;
+        let draw_mode = self.state.draw_mode;
+
+        for (texture, draw_mode, vertices, indices) in buffer.commands() {
+            self.state.texture = texture;
+            self.state.draw_mode = draw_mode;
+            self.push_geometry_indexed(vertices, indices);
+        }
+
+        self.state.texture = texture;
+        self.state.draw_mode = draw_mode;
+    }
+
+    /// Delete a pipeline. Its slot is freed right away, while its GPU resources
+    /// (the miniquad pipeline and shader) are deleted at the end of the next [Renderer::draw],
+    /// or with [Renderer::release_pipelines]
     pub fn delete_pipeline(&mut self, pipeline: GlPipeline<V>) {
         self.pipelines.delete_pipeline(pipeline);
     }
 
+    /// Delete the pipelines of dropped [OwnedMaterial](super::material::OwnedMaterial)s,
+    /// and the GPU resources of every deleted pipeline.
+    ///
+    /// [Renderer::draw] does this already, after drawing. Calling it in the middle of a frame skips
+    /// draw calls of materials that were dropped after being used
+    pub fn release_pipelines(&mut self, ctx: &mut dyn miniquad::RenderingBackend) {
+        self.pipelines.release(ctx);
+    }
+
+    /// The queue [OwnedMaterial](super::material::OwnedMaterial)s put their pipeline into
+    /// when dropped
+    pub(crate) fn release_queue(&self) -> ReleaseQueue<V> {
+        self.pipelines.released.clone()
+    }
+
     /// Check whether this renderer has this specific pipeline
     pub fn has_pipeline(&self, pipeline: &GlPipeline<V>) -> bool {
         self.pipelines.has_pipeline(pipeline)
@@ -803,10 +2307,10 @@ where V: AsVertex {
     pub fn set_uniform<T>(&mut self, pipeline: &GlPipeline<V>, name: &str, uniform: T) {
         self.state.break_batching = true;
 
-        self.pipelines
-            .get_pipeline_mut(pipeline)
-            .expect("The provided pipeline has to be present in the renderer")
-            .set_uniform(name, uniform);
+        match self.pipelines.get_pipeline_mut(pipeline) {
+            Some(pipeline) => pipeline.set_uniform(name, uniform),
+            None => warn!("Trying to set uniform {} of a deleted pipeline", name),
+        }
     }
 
     pub fn set_uniform_array<T: ToBytes>(
@@ -817,18 +2321,99 @@ where V: AsVertex {
     ) {
         self.state.break_batching = true;
 
-        self.pipelines
-            .get_pipeline_mut(pipeline)
-            .expect("The provided pipeline has to be present in the renderer")
-            .set_uniform_array(name, uniform);
+        match self.pipelines.get_pipeline_mut(pipeline) {
+            Some(pipeline) => pipeline.set_uniform_array(name, uniform),
+            None => warn!("Trying to set uniform {} of a deleted pipeline", name),
+        }
     }
 
-    /// Set a texture under specified name for the provided pipeline
-    pub fn set_texture(&mut self, pipeline: &GlPipeline<V>, name: &str, texture: TextureId) {
+    /// Set a uniform, checking that it exists and is declared with the type of `T`.
+    ///
+    /// Unlike [Renderer::set_uniform], which only warns, mistakes are returned as [UniformError]s.
+    /// For uniform arrays, this sets the first element
+    pub fn try_set_uniform<T: UniformValue>(
+        &mut self,
+        pipeline: &GlPipeline<V>,
+        name: &str,
+        uniform: T,
+    ) -> Result<(), Error> {
+        self.try_set_uniform_array(pipeline, name, &[uniform])
+    }
+
+    /// Set the elements of a uniform array, starting from the first one.
+    /// Fewer values than the array holds are fine, more are an [UniformError::ArrayOverflow]
+    pub fn try_set_uniform_array<T: UniformValue>(
+        &mut self,
+        pipeline: &GlPipeline<V>,
+        name: &str,
+        uniform: &[T],
+    ) -> Result<(), Error> {
         let pipeline = self
             .pipelines
             .get_pipeline_mut(pipeline)
-            .expect("The provided pipeline has to be present in the renderer");
+            .ok_or(Error::UnknownPipeline)?;
+
+        let index = pipeline.find_uniform::<T>(name)?;
+        pipeline.write_uniform(index, uniform)?;
+        self.state.break_batching = true;
+
+        Ok(())
+    }
+
+    /// Look up a uniform of a pipeline once, to set it later without a search by name
+    pub fn uniform_handle<T: UniformValue>(
+        &self,
+        pipeline: &GlPipeline<V>,
+        name: &str,
+    ) -> Result<UniformHandle<V, T>, Error> {
+        let pipeline_ext = self
+            .pipelines
+            .get_pipeline(pipeline)
+            .ok_or(Error::UnknownPipeline)?;
+
+        Ok(UniformHandle {
+            pipeline: *pipeline,
+            index: pipeline_ext.find_uniform::<T>(name)?,
+            revision: pipeline_ext.revision,
+            _m: PhantomData,
+        })
+    }
+
+    /// Set a uniform by its handle
+    pub fn set_uniform_handle<T: UniformValue>(
+        &mut self,
+        handle: UniformHandle<V, T>,
+        uniform: T,
+    ) -> Result<(), Error> {
+        self.set_uniform_handle_array(handle, &[uniform])
+    }
+
+    /// Set the elements of a uniform array by its handle, see [Renderer::try_set_uniform_array]
+    pub fn set_uniform_handle_array<T: UniformValue>(
+        &mut self,
+        handle: UniformHandle<V, T>,
+        uniform: &[T],
+    ) -> Result<(), Error> {
+        let pipeline = self
+            .pipelines
+            .get_pipeline_mut(&handle.pipeline)
+            .ok_or(Error::UnknownPipeline)?;
+
+        if pipeline.revision != handle.revision {
+            return Err(UniformError::StaleHandle.into());
+        }
+        pipeline.write_uniform(handle.index, uniform)?;
+        self.state.break_batching = true;
+
+        Ok(())
+    }
+
+    /// Set a texture under specified name for the provided pipeline
+    pub fn set_texture(&mut self, pipeline: &GlPipeline<V>, name: &str, texture: TextureId) {
+        let Some(pipeline) = self.pipelines.get_pipeline_mut(pipeline) else {
+            warn!("Trying to set texture {} of a deleted pipeline", name);
+            return;
+        };
 
         pipeline
             .textures
@@ -857,9 +2442,12 @@ where V: AsVertex {
         max_vertices: usize,
         max_indices: usize,
     ) {
-        self.max_vertices = max_vertices;
+        let format = self.batch_index_buffer.format();
+        self.max_vertices = max_vertices.min(format.max_vertices());
         self.max_indices = max_indices;
-        self.draw_calls_count = 0;
+        self.clear_draw_calls();
+        self.batch_vertex_buffer.clear();
+        self.batch_index_buffer.clear();
 
         for draw_call in &mut self.draw_calls {
             draw_call.indices_start = 0;
@@ -879,11 +2467,8 @@ where V: AsVertex {
                 BufferSource::empty::<V>(self.max_vertices),
             );
 
-            let index_buffer = backend.new_buffer(
-                BufferType::IndexBuffer,
-                BufferUsage::Stream,
-                BufferSource::empty::<u16>(self.max_indices),
-            );
+            let index_buffer =
+                new_index_buffer(backend, self.batch_index_buffer.format(), self.max_indices);
 
             *binding = Bindings {
                 vertex_buffers: vec![vertex_buffer],
@@ -899,8 +2484,58 @@ where V: AsVertex {
     }
 }
 
-mod shader {
-    use miniquad::{ShaderMeta, UniformBlockLayout, UniformDesc, UniformType};
+pub(crate) mod shader {
+    use miniquad::{ShaderMeta, UniformBlockLayout, UniformDesc, UniformType, VertexAttribute};
+
+    use crate::graphics::{AsVertex, Vertex2D};
+
+    /// The default shaders for a vertex type. The fragment shader is the same for all of them
+    pub struct Sources {
+        pub vertex: &'static str,
+        pub instanced_vertex: &'static str,
+        pub metal: &'static str,
+        pub instanced_metal: String,
+    }
+
+    impl Sources {
+        /// The 2D shaders for vertices laid out like [Vertex2D], and the regular ones otherwise
+        pub fn of<V: AsVertex>() -> Self {
+            let layout = |attributes: Vec<VertexAttribute>| {
+                attributes
+                    .into_iter()
+                    .map(|attribute| (attribute.name, attribute.format))
+                    .collect::<Vec<_>>()
+            };
+
+            if layout(V::attributes()) == layout(Vertex2D::attributes()) {
+                Self {
+                    vertex: VERTEX_2D,
+                    instanced_vertex: INSTANCED_VERTEX_2D,
+                    metal: METAL_2D,
+                    instanced_metal: instanced_metal::<V>(INSTANCED_METAL_2D),
+                }
+            } else {
+                Self {
+                    vertex: VERTEX,
+                    instanced_vertex: INSTANCED_VERTEX,
+                    metal: METAL,
+                    instanced_metal: instanced_metal::<V>(INSTANCED_METAL),
+                }
+            }
+        }
+    }
+
+    /// miniquad numbers instance attributes right after the vertex attributes, so the `INSTANCE`
+    /// attribute indices of an instanced Metal shader depend on the vertex type
+    fn instanced_metal<V: AsVertex>(template: &str) -> String {
+        let first = V::attributes().len();
+        (0..5).fold(template.to_string(), |source, ix| {
+            source.replace(
+                &format!("[[attribute(INSTANCE{})]]", ix),
+                &format!("[[attribute({})]]", first + ix),
+            )
+        })
+    }
 
     pub const VERTEX: &str = r#"#version 100
     attribute vec3 position;
@@ -930,6 +2565,30 @@ mod shader {
         gl_FragColor = color * texture2D(Texture, uv) ;
     }"#;
 
+    pub const INSTANCED_VERTEX: &str = r#"#version 100
+    attribute vec3 position;
+    attribute vec2 texcoord;
+    attribute vec4 color0;
+    attribute vec4 normal;
+    attribute vec4 instance_model0;
+    attribute vec4 instance_model1;
+    attribute vec4 instance_model2;
+    attribute vec4 instance_model3;
+    attribute vec4 instance_color;
+
+    varying lowp vec2 uv;
+    varying lowp vec4 color;
+
+    uniform mat4 Model;
+    uniform mat4 Projection;
+
+    void main() {
+        mat4 instance_model = mat4(instance_model0, instance_model1, instance_model2, instance_model3);
+        gl_Position = Projection * Model * instance_model * vec4(position, 1);
+        color = color0 / 255.0 * instance_color / 255.0;
+        uv = texcoord;
+    }"#;
+
     pub const METAL: &str = r#"
 #include <metal_stdlib>
     using namespace metal;
@@ -970,6 +2629,180 @@ mod shader {
         return in.color * tex.sample(texSmplr, in.uv);
     }
     "#;
+    pub const INSTANCED_METAL: &str = r#"
+#include <metal_stdlib>
+    using namespace metal;
+
+    struct Uniforms
+    {
+        float4x4 Model;
+        float4x4 Projection;
+    };
+
+    struct Vertex
+    {
+        float3 position         [[attribute(0)]];
+        float2 texcoord         [[attribute(1)]];
+        float4 color0           [[attribute(2)]];
+        float4 instance_model0  [[attribute(INSTANCE0)]];
+        float4 instance_model1  [[attribute(INSTANCE1)]];
+        float4 instance_model2  [[attribute(INSTANCE2)]];
+        float4 instance_model3  [[attribute(INSTANCE3)]];
+        float4 instance_color   [[attribute(INSTANCE4)]];
+    };
+
+    struct RasterizerData
+    {
+        float4 position [[position]];
+        float4 color [[user(locn0)]];
+        float2 uv [[user(locn1)]];
+    };
+
+    vertex RasterizerData vertexShader(Vertex v [[stage_in]], constant Uniforms& uniforms [[buffer(0)]])
+    {
+        RasterizerData out;
+
+        float4x4 instance_model = float4x4(v.instance_model0, v.instance_model1, v.instance_model2, v.instance_model3);
+        out.position = uniforms.Model * uniforms.Projection * instance_model * float4(v.position, 1);
+        out.color = v.color0 / 255.0 * v.instance_color / 255.0;
+        out.uv = v.texcoord;
+
+        return out;
+    }
+
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
+    {
+        return in.color * tex.sample(texSmplr, in.uv);
+    }
+    "#;
+
+    pub const VERTEX_2D: &str = r#"#version 100
+    attribute vec2 position;
+    attribute vec2 texcoord;
+    attribute vec4 color0;
+
+    varying lowp vec2 uv;
+    varying lowp vec4 color;
+
+    uniform mat4 Model;
+    uniform mat4 Projection;
+
+    void main() {
+        gl_Position = Projection * Model * vec4(position, 0, 1);
+        color = color0 / 255.0;
+        uv = texcoord;
+    }"#;
+
+    pub const INSTANCED_VERTEX_2D: &str = r#"#version 100
+    attribute vec2 position;
+    attribute vec2 texcoord;
+    attribute vec4 color0;
+    attribute vec4 instance_model0;
+    attribute vec4 instance_model1;
+    attribute vec4 instance_model2;
+    attribute vec4 instance_model3;
+    attribute vec4 instance_color;
+
+    varying lowp vec2 uv;
+    varying lowp vec4 color;
+
+    uniform mat4 Model;
+    uniform mat4 Projection;
+
+    void main() {
+        mat4 instance_model = mat4(instance_model0, instance_model1, instance_model2, instance_model3);
+        gl_Position = Projection * Model * instance_model * vec4(position, 0, 1);
+        color = color0 / 255.0 * instance_color / 255.0;
+        uv = texcoord;
+    }"#;
+
+    pub const METAL_2D: &str = r#"
+#include <metal_stdlib>
+    using namespace metal;
+
+    struct Uniforms
+    {
+        float4x4 Model;
+        float4x4 Projection;
+    };
+
+    struct Vertex
+    {
+        float2 position    [[attribute(0)]];
+        float2 texcoord    [[attribute(1)]];
+        float4 color0      [[attribute(2)]];
+    };
+
+    struct RasterizerData
+    {
+        float4 position [[position]];
+        float4 color [[user(locn0)]];
+        float2 uv [[user(locn1)]];
+    };
+
+    vertex RasterizerData vertexShader(Vertex v [[stage_in]], constant Uniforms& uniforms [[buffer(0)]])
+    {
+        RasterizerData out;
+
+        out.position = uniforms.Model * uniforms.Projection * float4(v.position, 0, 1);
+        out.color = v.color0 / 255.0;
+        out.uv = v.texcoord;
+
+        return out;
+    }
+
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
+    {
+        return in.color * tex.sample(texSmplr, in.uv);
+    }
+    "#;
+    pub const INSTANCED_METAL_2D: &str = r#"
+#include <metal_stdlib>
+    using namespace metal;
+
+    struct Uniforms
+    {
+        float4x4 Model;
+        float4x4 Projection;
+    };
+
+    struct Vertex
+    {
+        float2 position         [[attribute(0)]];
+        float2 texcoord         [[attribute(1)]];
+        float4 color0           [[attribute(2)]];
+        float4 instance_model0  [[attribute(INSTANCE0)]];
+        float4 instance_model1  [[attribute(INSTANCE1)]];
+        float4 instance_model2  [[attribute(INSTANCE2)]];
+        float4 instance_model3  [[attribute(INSTANCE3)]];
+        float4 instance_color   [[attribute(INSTANCE4)]];
+    };
+
+    struct RasterizerData
+    {
+        float4 position [[position]];
+        float4 color [[user(locn0)]];
+        float2 uv [[user(locn1)]];
+    };
+
+    vertex RasterizerData vertexShader(Vertex v [[stage_in]], constant Uniforms& uniforms [[buffer(0)]])
+    {
+        RasterizerData out;
+
+        float4x4 instance_model = float4x4(v.instance_model0, v.instance_model1, v.instance_model2, v.instance_model3);
+        out.position = uniforms.Model * uniforms.Projection * instance_model * float4(v.position, 0, 1);
+        out.color = v.color0 / 255.0 * v.instance_color / 255.0;
+        out.uv = v.texcoord;
+
+        return out;
+    }
+
+    fragment float4 fragmentShader(RasterizerData in [[stage_in]], texture2d<float> tex [[texture(0)]], sampler texSmplr [[sampler(0)]])
+    {
+        return in.color * tex.sample(texSmplr, in.uv);
+    }
+    "#;
+
     pub fn uniforms() -> Vec<(&'static str, UniformType)> {
         vec![
             ("Projection", UniformType::Mat4),
@@ -990,3 +2823,826 @@ mod shader {
         }
     }
 }
+
+/// A renderer drawing into a `size` by `size` render target, on a
+/// [SoftwareBackend](super::backend::SoftwareBackend) wrapped in a
+/// [RecordingBackend](super::backend::RecordingBackend)
+#[cfg(test)]
+pub(crate) fn test_renderer<V: AsVertex>(
+    size: u32,
+) -> (
+    super::backend::RecordingBackend,
+    Renderer<V>,
+    crate::texture::RenderTarget,
+) {
+    use super::backend::{RecordingBackend, SoftwareBackend};
+
+    let mut backend = RecordingBackend::new(SoftwareBackend::new(size, size));
+    let target = crate::texture::new_render_target(&mut backend, size, size);
+    let mut renderer = Renderer::new(&mut backend, 100, 100);
+    renderer.with_render_pass(Some(target.render_pass.render_pass));
+
+    (backend, renderer, target)
+}
+
+#[test]
+fn renderer_u32_indices() {
+    use super::backend::{Command, RecordingBackend, SoftwareBackend};
+
+    let mut backend = RecordingBackend::new(SoftwareBackend::new(1, 1));
+    let target = crate::texture::new_render_target(&mut backend, 1, 1);
+
+    let mut renderer = Renderer::new_ex(
+        &mut backend,
+        RendererParams {
+            max_vertices: 100_000,
+            max_indices: 100_000,
+            index_format: IndexFormat::U32,
+        },
+    );
+    renderer.with_render_pass(Some(target.render_pass.render_pass));
+
+    let vertices = vec![Vertex::new(0., 0., 0., 0., 0., crate::color::WHITE); 70_000];
+    let indices: Vec<u32> = vec![0, 1, 69_999];
+    renderer.push_geometry_indexed(&vertices, &indices);
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let index_upload = backend.commands().iter().find_map(|command| match command {
+        Command::BufferUpdate {
+            bytes,
+            element_size: 4,
+            ..
+        } => Some(bytes.clone()),
+        _ => None,
+    });
+    let expected = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+    assert_eq!(index_upload, Some(expected));
+    assert_eq!(backend.draw_calls(), 1);
+
+    // Up to index u32::MAX, not unbounded
+    #[cfg(target_pointer_width = "64")]
+    assert_eq!(IndexFormat::U32.max_vertices(), u32::MAX as usize + 1);
+}
+
+#[test]
+fn renderer_static_mesh() {
+    use super::backend::Command;
+    use crate::color::RED;
+
+    let (mut backend, mut renderer, target) = test_renderer(4);
+
+    let vertices = [
+        Vertex::new(-1., -1., 0., 0., 0., RED),
+        Vertex::new(1., -1., 0., 0., 0., RED),
+        Vertex::new(1., 1., 0., 0., 0., RED),
+        Vertex::new(-1., 1., 0., 0., 0., RED),
+    ];
+    let mesh = StaticMesh::new(&mut backend, &vertices, &[0u16, 1, 2, 0, 2, 3], None);
+
+    backend.clear_commands();
+    renderer.draw_static_mesh(&mesh);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    // Nothing but the uniforms is uploaded
+    assert!(!backend
+        .commands()
+        .iter()
+        .any(|command| matches!(command, Command::BufferUpdate { .. })));
+    assert_eq!(backend.draw_calls(), 1);
+
+    let image = crate::texture::Image::from_texture(&mut backend, &target.texture);
+    assert!(image.get_image_data().iter().all(|pixel| *pixel == <[u8; 4]>::from(RED)));
+}
+
+#[test]
+fn renderer_draw_instanced() {
+    use super::backend::Command;
+    use crate::color::{BLUE, WHITE};
+
+    let (mut backend, mut renderer, target) = test_renderer(4);
+
+    // A quad covering the left half of the target, instanced to the right half as well
+    let vertices = [
+        Vertex::new(-1., -1., 0., 0., 0., WHITE),
+        Vertex::new(0., -1., 0., 0., 0., WHITE),
+        Vertex::new(0., 1., 0., 0., 0., WHITE),
+        Vertex::new(-1., 1., 0., 0., 0., WHITE),
+    ];
+    let mesh = StaticMesh::new(&mut backend, &vertices, &[0u16, 1, 2, 0, 2, 3], None);
+    let instances = InstanceBuffer::from_slice(
+        &mut backend,
+        &[
+            Instance::from_translation(glam::Vec3::ZERO, BLUE),
+            Instance::from_translation(glam::vec3(1., 0., 0.), BLUE),
+        ],
+    );
+
+    backend.clear_commands();
+    renderer.draw_instanced(&mesh, &instances);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    assert!(backend.commands().contains(&Command::Draw {
+        base_element: 0,
+        num_elements: 6,
+        num_instances: 2,
+    }));
+
+    let image = crate::texture::Image::from_texture(&mut backend, &target.texture);
+    assert!(image.get_image_data().iter().all(|pixel| *pixel == <[u8; 4]>::from(BLUE)));
+
+    // Ordinary geometry isn't drawn with an instanced pipeline, it has no instances to bind
+    let instanced = renderer
+        .make_instanced_pipeline::<Instance>(
+            &mut backend,
+            ShaderSource::Glsl {
+                vertex: shader::INSTANCED_VERTEX,
+                fragment: shader::FRAGMENT,
+            },
+            Default::default(),
+            vec![],
+            vec![],
+        )
+        .unwrap();
+    renderer.with_pipeline(Some(instanced));
+    renderer.push_geometry(&vertices, &[0, 1, 2, 0, 2, 3]);
+    assert!(matches!(renderer.draw_calls[0].pipeline, DrawPipeline::Default(_)));
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    let image = crate::texture::Image::from_texture(&mut backend, &target.texture);
+    assert_eq!(image.get_image_data()[0], <[u8; 4]>::from(WHITE));
+}
+
+#[test]
+fn renderer_layers() {
+    use crate::color::{BLUE, GREEN, RED};
+    use crate::draw::draw_rectangle;
+
+    let (mut backend, mut renderer, target) = test_renderer::<Vertex>(4);
+
+    renderer.with_layer(1);
+    draw_rectangle(&mut renderer, -1., -1., 2., 2., RED);
+    renderer.with_layer(0);
+    draw_rectangle(&mut renderer, -1., -1., 2., 2., BLUE);
+    renderer.with_layer(1);
+    draw_rectangle(&mut renderer, -1., -1., 1., 2., GREEN);
+    assert_eq!(renderer.draw_calls(), 3);
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    // Both layer 1 rectangles are merged into a single draw call, drawn after the blue one
+    assert_eq!(backend.draw_calls(), 2);
+
+    let image = crate::texture::Image::from_texture(&mut backend, &target.texture);
+    for (ix, pixel) in image.get_image_data().iter().enumerate() {
+        let expected = if ix % 4 < 2 { GREEN } else { RED };
+        assert_eq!(*pixel, <[u8; 4]>::from(expected));
+    }
+}
+
+#[test]
+fn renderer_layers_keep_pass_order() {
+    use crate::color::{BLACK, GREEN, RED, WHITE};
+    use crate::draw::{draw_rectangle, draw_texture_ex, DrawTextureParams};
+    use crate::texture::new_render_target;
+
+    let (mut backend, mut renderer, target) = test_renderer::<Vertex>(4);
+    let offscreen = new_render_target(&mut backend, 4, 4);
+    renderer.with_render_pass(Some(offscreen.render_pass.render_pass));
+    renderer.clear(&mut backend, BLACK);
+    renderer.with_layer(1);
+    draw_rectangle(&mut renderer, -1., -1., 2., 2., RED);
+
+    // A lower layer, but it samples the offscreen target, so it has to be drawn after it.
+    // Inside of this pass, layers are still sorted
+    renderer.with_render_pass(Some(target.render_pass.render_pass));
+    draw_rectangle(&mut renderer, -1., -1., 1., 2., GREEN);
+    renderer.with_layer(0);
+    let texture = crate::texture::Texture::from_texture(&mut backend, offscreen.texture);
+    let params = DrawTextureParams {
+        dest_size: Some(glam::vec2(2., 2.)),
+        ..Default::default()
+    };
+    draw_texture_ex(&mut renderer, &texture, -1., -1., WHITE, params);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let image = crate::texture::Image::from_texture(&mut backend, &target.texture);
+    for (ix, pixel) in image.get_image_data().iter().enumerate() {
+        let expected = if ix % 4 < 2 { GREEN } else { RED };
+        assert_eq!(*pixel, <[u8; 4]>::from(expected));
+    }
+}
+
+#[test]
+fn renderer_blend_modes() {
+    use crate::color::Color;
+    use crate::draw::draw_rectangle;
+
+    let (mut backend, mut renderer, target) = test_renderer::<Vertex>(4);
+    renderer.clear(&mut backend, Color::new(0.2, 0.2, 0.2, 1.0));
+
+    draw_rectangle(&mut renderer, -1., -1., 2., 2., Color::new(0.4, 0.0, 0.0, 1.0));
+    renderer.with_blend_mode(BlendMode::Additive);
+    draw_rectangle(&mut renderer, -1., -1., 2., 2., Color::new(0.0, 0.4, 0.0, 1.0));
+    renderer.with_blend_mode(BlendMode::Multiply);
+    draw_rectangle(&mut renderer, -1., -1., 2., 2., Color::new(0.5, 0.5, 1.0, 1.0));
+    renderer.with_blend_mode(BlendMode::Multiply);
+    draw_rectangle(&mut renderer, -1., -1., 2., 2., Color::new(1.0, 1.0, 0.5, 1.0));
+
+    // Every blend mode change breaks the batch, while the same mode keeps batching
+    assert_eq!(renderer.draw_calls(), 3);
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    assert_eq!(backend.draw_calls(), 3);
+    // Only the default pipelines that were used are created
+    assert_eq!(renderer.pipelines.default_pipelines.len(), 3);
+
+    let image = crate::texture::Image::from_texture(&mut backend, &target.texture);
+    for pixel in image.get_image_data() {
+        assert!(pixel[0].abs_diff(51) <= 1, "{:?}", pixel);
+        assert!(pixel[1].abs_diff(51) <= 1, "{:?}", pixel);
+        assert!(pixel[2].abs_diff(0) <= 1, "{:?}", pixel);
+    }
+}
+
+#[test]
+fn renderer_stencil_mask() {
+    use super::backend::SoftwareBackend;
+    use crate::color::{BLACK, BLUE, GREEN, RED};
+    use crate::draw::draw_rectangle;
+    use crate::texture::{new_render_target_ex, GlAccess, RenderTargetParams};
+
+    let mut backend = SoftwareBackend::new(4, 4);
+    let target = new_render_target_ex(
+        &mut backend,
+        4,
+        4,
+        RenderTargetParams {
+            stencil: true,
+            gl_access: GlAccess::Emulated,
+            ..Default::default()
+        },
+    );
+
+    let mut renderer: Renderer = Renderer::new(&mut backend, 100, 100);
+    renderer.with_render_pass(Some(target.render_pass.render_pass));
+    renderer.clear(&mut backend, BLACK);
+
+    // The mask covers the left half, and isn't drawn itself
+    renderer.with_stencil_mode(StencilMode::WriteMask);
+    draw_rectangle(&mut renderer, -1., -1., 1., 2., GREEN);
+    renderer.with_stencil_mode(StencilMode::Inside);
+    draw_rectangle(&mut renderer, -1., -1., 0.5, 2., RED);
+    renderer.with_stencil_mode(StencilMode::Outside);
+    draw_rectangle(&mut renderer, -1., -1., 2., 2., BLUE);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let image = crate::texture::Image::from_texture(&mut backend, &target.texture);
+    for (ix, pixel) in image.get_image_data().iter().enumerate() {
+        let expected = match ix % 4 {
+            0 => RED,
+            1 => BLACK,
+            _ => BLUE,
+        };
+        assert_eq!(*pixel, <[u8; 4]>::from(expected), "pixel {}", ix);
+    }
+}
+
+#[test]
+fn renderer_multiple_render_targets() {
+    use super::backend::SoftwareBackend;
+    use crate::color::RED;
+    use crate::texture::{new_render_target_ex, Image, RenderTargetParams};
+
+    let mut backend = SoftwareBackend::new(4, 4);
+    let target = new_render_target_ex(
+        &mut backend,
+        4,
+        4,
+        RenderTargetParams {
+            color_formats: vec![TextureFormat::RGBA8, TextureFormat::RGBA8],
+            ..Default::default()
+        },
+    );
+    assert_eq!(target.color_textures.len(), 2);
+    assert_eq!(target.texture, target.color_textures[0]);
+
+    let mut renderer: Renderer = Renderer::new(&mut backend, 100, 100);
+    renderer.with_render_pass(Some(target.render_pass.render_pass));
+    crate::draw::draw_rectangle(&mut renderer, -1., -1., 2., 2., RED);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    // The default shader writes the same color into every attachment
+    for texture in &target.color_textures {
+        let image = Image::from_texture(&mut backend, texture);
+        assert!(image.get_image_data().iter().all(|pixel| *pixel == <[u8; 4]>::from(RED)));
+    }
+}
+
+#[test]
+fn renderer_depth_texture() {
+    use super::backend::{Command, RecordingBackend, SoftwareBackend};
+    use super::material::{load_material, use_material, MaterialParams};
+    use crate::color::WHITE;
+    use crate::texture::{
+        new_render_target, new_render_target_ex, read_depth_texture, GlAccess, RenderTargetParams,
+    };
+
+    let mut backend = RecordingBackend::new(SoftwareBackend::new(4, 4));
+    let target = new_render_target_ex(
+        &mut backend,
+        4,
+        4,
+        RenderTargetParams {
+            depth: true,
+            gl_access: GlAccess::Emulated,
+            ..Default::default()
+        },
+    );
+    assert_eq!(target.depth_texture, target.render_pass.depth_texture);
+
+    let mut renderer = Renderer::new(&mut backend, 100, 100);
+    renderer.with_render_pass(Some(target.render_pass.render_pass));
+    renderer.clear(&mut backend, WHITE);
+    renderer.with_depth_test(true);
+
+    // A quad at the depth of 0.75 in window coordinates, covering the left half
+    let vertices = [
+        Vertex::new(-1., -1., 0.5, 0., 0., WHITE),
+        Vertex::new(0., -1., 0.5, 0., 0., WHITE),
+        Vertex::new(0., 1., 0.5, 0., 0., WHITE),
+        Vertex::new(-1., 1., 0.5, 0., 0., WHITE),
+    ];
+    renderer.push_geometry(&vertices, &[0, 1, 2, 0, 2, 3]);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let depth = target.read_depth(&mut backend).unwrap();
+    for (ix, depth) in depth.iter().enumerate() {
+        let expected = if ix % 4 < 2 { 0.75 } else { 1.0 };
+        assert!((depth - expected).abs() < 0.001, "pixel {}: {}", ix, depth);
+    }
+
+    // Without GL access, depth can't be read with OpenGL. Color textures have no depth
+    let depth_texture = target.depth_texture.unwrap();
+    assert_eq!(read_depth_texture(&mut backend, depth_texture, GlAccess::Disabled), None);
+    assert_eq!(read_depth_texture(&mut backend, target.texture, GlAccess::Emulated), None);
+
+    // The depth texture can be sampled by a material in another pass
+    let material = load_material(
+        &mut backend,
+        &mut renderer,
+        ShaderSource::Glsl {
+            vertex: shader::VERTEX,
+            fragment: shader::FRAGMENT,
+        },
+        MaterialParams {
+            textures: vec!["Depth".to_string()],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    material.set_texture(&mut renderer, "Depth", &depth_texture);
+
+    let other = new_render_target(&mut backend, 4, 4);
+    renderer.with_render_pass(Some(other.render_pass.render_pass));
+    use_material(&mut renderer, &material);
+    renderer.push_geometry(&vertices, &[0, 1, 2]);
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let textures = backend.commands().iter().find_map(|command| match command {
+        Command::ApplyBindings { textures, .. } => Some(textures.clone()),
+        _ => None,
+    });
+    let depth_texture = backend.texture_index(depth_texture);
+    assert_eq!(textures.map(|textures| textures[1]), Some(depth_texture));
+}
+
+#[test]
+fn renderer_checked_uniforms() {
+    use super::backend::Command;
+    use crate::color::WHITE;
+    use glam::{vec2, vec4, Vec2};
+
+    let (mut backend, mut renderer, _target) = test_renderer(1);
+
+    let shader = || ShaderSource::Glsl {
+        vertex: "",
+        fragment: "",
+    };
+    let uniforms = || {
+        vec![
+            UniformDesc::new("Color", UniformType::Float4),
+            UniformDesc::new("Offsets", UniformType::Float2).array(3),
+        ]
+    };
+    let pipeline = renderer
+        .make_pipeline(&mut backend, shader(), Default::default(), uniforms(), vec![])
+        .unwrap();
+
+    assert!(matches!(
+        renderer.try_set_uniform(&pipeline, "Colour", vec4(1., 0., 0., 1.)),
+        Err(Error::UniformError(UniformError::UnknownName(_)))
+    ));
+    assert!(matches!(
+        renderer.try_set_uniform(&pipeline, "Color", 1.0f32),
+        Err(Error::UniformError(UniformError::TypeMismatch { .. }))
+    ));
+    assert!(matches!(
+        renderer.try_set_uniform_array(&pipeline, "Offsets", &[Vec2::ZERO; 4]),
+        Err(Error::UniformError(UniformError::ArrayOverflow {
+            capacity: 3,
+            len: 4,
+            ..
+        }))
+    ));
+
+    let color = renderer.uniform_handle(&pipeline, "Color").unwrap();
+    renderer.set_uniform_handle(color, vec4(1., 0., 0., 1.)).unwrap();
+    renderer
+        .try_set_uniform_array(&pipeline, "Offsets", &[vec2(1., 2.), vec2(3., 4.)])
+        .unwrap();
+    // The unchecked setter works with arrays that aren't 16 bytes long as well
+    renderer.set_uniform_array(&pipeline, "Offsets", &[vec2(1., 2.), vec2(3., 4.), vec2(5., 6.)]);
+
+    renderer.with_pipeline(Some(pipeline));
+    renderer.push_geometry(&[Vertex::new(0., 0., 0., 0., 0., WHITE); 3], &[0, 1, 2]);
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let uniforms_data = backend.commands().iter().find_map(|command| match command {
+        Command::ApplyUniforms { bytes } => Some(bytes.clone()),
+        _ => None,
+    });
+    let expected: Vec<u8> = [1., 0., 0., 1., 1., 2., 3., 4., 5., 6.]
+        .iter()
+        .flat_map(|value: &f32| value.to_ne_bytes())
+        .collect();
+    assert!(uniforms_data.unwrap().ends_with(&expected));
+
+    // Handles become stale once the pipeline is replaced
+    renderer
+        .replace_pipeline(&mut backend, &pipeline, shader(), uniforms(), vec![])
+        .unwrap();
+    assert!(matches!(
+        renderer.set_uniform_handle(color, vec4(0., 1., 0., 1.)),
+        Err(Error::UniformError(UniformError::StaleHandle))
+    ));
+}
+
+#[test]
+fn renderer_pipeline_storage() {
+    use super::backend::Command;
+    use super::material::{load_material, use_material, MaterialParams};
+    use crate::color::WHITE;
+
+    let (mut backend, mut renderer, _target) = test_renderer(4);
+    let shader = || ShaderSource::Glsl {
+        vertex: shader::VERTEX,
+        fragment: shader::FRAGMENT,
+    };
+
+    // More than the 32 slots renderers used to have
+    let pipelines = (0..40)
+        .map(|_| {
+            renderer
+                .make_pipeline(&mut backend, shader(), Default::default(), vec![], vec![])
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert!(pipelines.iter().all(|pipeline| renderer.has_pipeline(pipeline)));
+
+    // A deleted pipeline doesn't resolve to the one that reuses its slot
+    renderer.delete_pipeline(pipelines[3]);
+    let reused = renderer
+        .make_pipeline(&mut backend, shader(), Default::default(), vec![], vec![])
+        .unwrap();
+    assert!(renderer.has_pipeline(&reused));
+    assert!(!renderer.has_pipeline(&pipelines[3]));
+    assert!(matches!(
+        renderer.try_set_uniform(&pipelines[3], "_Time", glam::Vec4::ZERO),
+        Err(Error::UnknownPipeline)
+    ));
+
+    // An owned material can be dropped right after drawing with it
+    let material = load_material(&mut backend, &mut renderer, shader(), MaterialParams::default())
+        .unwrap()
+        .into_owned(&renderer);
+    let pipeline = *material.pipeline();
+    use_material(&mut renderer, &material);
+    renderer.push_geometry(&[Vertex::new(0., 0., 0., 0., 0., WHITE); 3], &[0, 1, 2]);
+    drop(material);
+    assert!(renderer.has_pipeline(&pipeline));
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    assert!(backend
+        .commands()
+        .iter()
+        .any(|command| matches!(command, Command::Draw { .. })));
+    assert!(!renderer.has_pipeline(&pipeline));
+
+    // Or returned to the renderer
+    let material = load_material(&mut backend, &mut renderer, shader(), MaterialParams::default())
+        .unwrap()
+        .into_owned(&renderer);
+    let pipeline = *material.pipeline();
+    material.release(&mut renderer);
+    assert!(!renderer.has_pipeline(&pipeline));
+}
+
+#[test]
+#[should_panic(expected = "another renderer")]
+fn renderer_foreign_pipeline() {
+    use super::backend::SoftwareBackend;
+
+    let mut backend = SoftwareBackend::new(4, 4);
+    let mut renderer: Renderer = Renderer::new(&mut backend, 100, 100);
+    let mut other: Renderer = Renderer::new(&mut backend, 100, 100);
+    let pipeline = other
+        .make_pipeline(
+            &mut backend,
+            ShaderSource::Glsl {
+                vertex: shader::VERTEX,
+                fragment: shader::FRAGMENT,
+            },
+            Default::default(),
+            vec![],
+            vec![],
+        )
+        .unwrap();
+
+    assert!(!renderer.has_pipeline(&pipeline));
+    renderer.with_pipeline(Some(pipeline));
+}
+
+#[test]
+fn renderer_frame_stats() {
+    use crate::color::WHITE;
+    use crate::texture::new_render_target;
+
+    let (mut backend, mut renderer, _target) = test_renderer(4);
+    renderer.with_stats(true);
+    let texture = new_render_target(&mut backend, 4, 4).texture;
+
+    let triangle = [Vertex::new(0., 0., 0., 0., 0., WHITE); 3];
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.with_texture(Some(&texture));
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.push_model_matrix(glam::Mat4::from_translation(glam::vec3(1., 0., 0.)));
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.with_scissor(Some((0, 0, 2, 2)));
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.with_blend_mode(BlendMode::Additive);
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.push_geometry(&[Vertex::new(0., 0., 0., 0., 0., WHITE); 99], &[0, 1, 2]);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let stats = renderer.take_frame_stats();
+    assert_eq!(
+        stats.batch_breaks,
+        [
+            BatchBreak::Texture,
+            BatchBreak::ModelMatrix,
+            BatchBreak::Clip,
+            BatchBreak::Pipeline,
+            BatchBreak::Capacity,
+        ]
+    );
+    assert_eq!(stats.draw_calls, 6);
+    assert_eq!(stats.vertices, 3 * 6 + 99);
+    assert_eq!(stats.indices, 3 * 7);
+    assert_eq!(stats.pipeline_switches, 2);
+    assert_eq!(stats.texture_switches, 2);
+    assert_eq!(
+        stats.to_string(),
+        "draw calls: 6, vertices: 117, indices: 21, pipeline switches: 2, texture switches: 2, \
+         batch breaks: 5 (pipeline 1, texture 1, clip 1, model matrix 1, capacity 1)"
+    );
+    assert_eq!(renderer.frame_stats(), &FrameStats::default());
+
+    // Nothing is collected once it's disabled
+    renderer.with_stats(false);
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.with_texture(None);
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    assert_eq!(renderer.frame_stats(), &FrameStats::default());
+}
+
+#[test]
+fn renderer_clip_stack() {
+    use super::backend::Command;
+    use crate::color::WHITE;
+    use crate::texture::new_render_target;
+
+    let (mut backend, mut renderer, target) = test_renderer(8);
+    let a = target.render_pass.render_pass;
+    let b = new_render_target(&mut backend, 8, 8).render_pass.render_pass;
+    let triangle = [Vertex::new(0., 0., 0., 0., 0., WHITE); 3];
+
+    renderer.with_render_pass(Some(a));
+    renderer.push_clip(Rect::new(0., 0., 6., 6.));
+    renderer.push_clip(Rect::new(2., 2., 6., 6.));
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+
+    // Another render pass starts without a clip, and switching back restores it
+    renderer.with_render_pass(Some(b));
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.with_render_pass(Some(a));
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+
+    renderer.pop_clip();
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+
+    // Relative to the viewport
+    renderer.pop_clip();
+    renderer.with_viewport(Some((4, 0, 4, 4)));
+    renderer.push_clip(Rect::new(0., 0., 2., 2.));
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let scissors: Vec<_> = backend
+        .commands()
+        .iter()
+        .filter_map(|command| match command {
+            Command::ApplyScissorRect(x, y, w, h) => Some((*x, *y, *w, *h)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        scissors,
+        [(2, 2, 4, 4), (0, 0, 8, 8), (2, 2, 4, 4), (0, 2, 6, 6), (4, 2, 2, 2)]
+    );
+}
+
+#[test]
+fn renderer_clip_world() {
+    use super::backend::Command;
+    use super::{Camera, Camera2D};
+    use crate::color::WHITE;
+
+    let (mut backend, mut renderer, target) = test_renderer(8);
+    let triangle = [Vertex::new(0., 0., 0., 0., 0., WHITE); 3];
+
+    // The camera shows the world from (-2, -4) to (6, 4), zoomed out 4 times
+    let camera = Camera2D {
+        zoom: glam::vec2(0.25, 0.25),
+        target: glam::vec2(2., 0.),
+        render_target: Some(target),
+        ..Default::default()
+    };
+    assert_eq!(camera.viewport(), None);
+
+    // The top-right quarter of the target
+    renderer.push_clip_world(&mut backend, Rect::new(2., 0., 4., 4.), &camera);
+    renderer.push_geometry(&triangle, &[0, 1, 2]);
+    renderer.pop_clip();
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, camera.matrix());
+
+    assert!(backend.commands().contains(&Command::ApplyScissorRect(4, 4, 4, 4)));
+}
+
+#[test]
+fn renderer_baked_transforms() {
+    use crate::color::WHITE;
+
+    let (mut backend, mut renderer, target) = test_renderer(4);
+    renderer.with_stats(true);
+    let triangle = [Vertex::new(1., 0., 0., 0., 0., WHITE); 3];
+
+    let push_sprites = |renderer: &mut Renderer| {
+        renderer.with_render_pass(Some(target.render_pass.render_pass));
+        renderer.push_translation(glam::vec2(1., 2.));
+        renderer.push_geometry(&triangle, &[0, 1, 2]);
+        renderer.push_rotation(std::f32::consts::FRAC_PI_2);
+        renderer.push_scale(glam::vec2(2., 2.));
+        renderer.push_geometry(&triangle, &[0, 1, 2]);
+        renderer.pop_transform();
+        renderer.pop_transform();
+        renderer.pop_transform();
+        renderer.push_geometry(&triangle, &[0, 1, 2]);
+    };
+
+    push_sprites(&mut renderer);
+    assert_eq!(
+        renderer.frame_stats().batch_breaks,
+        [BatchBreak::ModelMatrix, BatchBreak::ModelMatrix]
+    );
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    renderer.take_frame_stats();
+
+    renderer.with_baked_transforms(true);
+    push_sprites(&mut renderer);
+    assert!(renderer.frame_stats().batch_breaks.is_empty());
+
+    let positions: Vec<_> = renderer
+        .batch_vertex_buffer
+        .iter()
+        .step_by(3)
+        .map(|vertex| vertex.position.round())
+        .collect();
+    assert_eq!(
+        positions,
+        [glam::vec3(2., 2., 0.), glam::vec3(1., 4., 0.), glam::vec3(1., 0., 0.)]
+    );
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    assert_eq!(renderer.take_frame_stats().draw_calls, 1);
+}
+
+#[test]
+fn renderer_draw_list() {
+    use super::backend::Command;
+    use crate::color::{BLUE, RED};
+    use crate::draw::draw_rectangle;
+
+    let (mut backend, mut renderer, target) = test_renderer::<Vertex>(4);
+    renderer.with_stats(true);
+
+    draw_rectangle(&mut renderer, 0., 0., 1., 1., RED);
+    renderer.begin_draw_list();
+    draw_rectangle(&mut renderer, 0., 0., 2., 2., RED);
+    renderer.with_scissor(Some((0, 0, 1, 1)));
+    draw_rectangle(&mut renderer, 2., 2., 2., 2., BLUE);
+    let list = renderer.end_draw_list();
+    renderer.with_scissor(None);
+    assert_eq!(list.len(), 2);
+    assert_eq!(list.vertices.len(), 8);
+    assert_eq!(list.indices, [0, 1, 2, 0, 2, 3, 0, 1, 2, 0, 2, 3]);
+
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    assert_eq!(renderer.take_frame_stats().draw_calls, 3);
+
+    // Replayed on another frame, with an offset
+    let offset = glam::Mat4::from_translation(glam::vec3(1., 0., 0.));
+    renderer.with_render_pass(Some(target.render_pass.render_pass));
+    renderer.draw_list_ex(&list, offset);
+    draw_rectangle(&mut renderer, 0., 0., 1., 1., RED);
+    assert_eq!(renderer.batch_vertex_buffer[..8], list.vertices[..]);
+    assert_eq!(renderer.draw_calls[1].model, offset);
+
+    backend.clear_commands();
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+    let stats = renderer.take_frame_stats();
+    assert_eq!(stats.batch_breaks, [BatchBreak::DrawList, BatchBreak::Clip]);
+    assert_eq!(stats.draw_calls, 3);
+    assert!(backend
+        .commands()
+        .contains(&Command::ApplyScissorRect(0, 3, 1, 1)));
+
+    // Resetting the renderer discards the recording, even once more draw calls are submitted
+    draw_rectangle(&mut renderer, 0., 0., 1., 1., RED);
+    renderer.begin_draw_list();
+    draw_rectangle(&mut renderer, 0., 0., 1., 1., BLUE);
+    renderer.reset();
+    draw_rectangle(&mut renderer, 0., 0., 1., 1., RED);
+    renderer.with_scissor(Some((0, 0, 1, 1)));
+    draw_rectangle(&mut renderer, 0., 0., 1., 1., RED);
+    assert_eq!(renderer.draw_calls(), 2);
+    assert!(renderer.end_draw_list().is_empty());
+}
+
+#[test]
+fn renderer_vertex_2d() {
+    use super::Vertex2D;
+    use crate::color::{BLUE, RED};
+    use crate::draw::{draw_rectangle, draw_texture_ex, DrawTextureParams};
+
+    assert_eq!(shader::Sources::of::<Vertex2D>().vertex, shader::VERTEX_2D);
+    assert_eq!(shader::Sources::of::<Vertex>().vertex, shader::VERTEX);
+    // Instance attributes come right after the 3 attributes of Vertex2D and the 4 of Vertex
+    assert!(shader::Sources::of::<Vertex2D>()
+        .instanced_metal
+        .contains("instance_model0  [[attribute(3)]]"));
+    assert!(shader::Sources::of::<Vertex>()
+        .instanced_metal
+        .contains("instance_color   [[attribute(8)]]"));
+
+    let (mut backend, mut renderer, target) = test_renderer::<Vertex2D>(4);
+    let texture = crate::texture::Texture::from_rgba8(&mut backend, 1, 1, &[255; 4]);
+
+    // The left half is red, the right half a white texture tinted blue
+    draw_rectangle(&mut renderer, -1., -1., 1., 2., RED);
+    let params = DrawTextureParams {
+        dest_size: Some(glam::vec2(1., 2.)),
+        ..Default::default()
+    };
+    draw_texture_ex(&mut renderer, &texture, 0., -1., BLUE, params);
+    renderer.draw(&mut backend, glam::Mat4::IDENTITY);
+
+    let image = crate::texture::Image::from_texture(&mut backend, &target.texture);
+    let pixels = image.get_image_data();
+    assert!(pixels.iter().all(|pixel| *pixel != [0; 4]));
+    assert_eq!(pixels[0], <[u8; 4]>::from(RED));
+    assert_eq!(pixels[3], <[u8; 4]>::from(BLUE));
+}
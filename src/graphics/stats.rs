@@ -0,0 +1,113 @@
+//! Per-frame rendering statistics, to find out where draw calls come from
+
+use std::{collections::BTreeMap, fmt};
+
+/// Why a draw call was started instead of appending geometry to the previous one.
+///
+/// When several things change at once, the first one in the order of this enum is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BatchBreak {
+    /// Static meshes and instanced draws are always separate draw calls,
+    /// and so is the geometry pushed after them
+    Geometry,
+    /// Every draw call of a replayed [DrawList](super::DrawList) is a separate draw call
+    DrawList,
+    RenderPass,
+    /// See [Renderer::with_layer](super::Renderer::with_layer)
+    Layer,
+    DrawMode,
+    /// Another material, or another default pipeline
+    /// (a blend mode, stencil mode or depth test change)
+    Pipeline,
+    Texture,
+    /// The scissor rectangle or the clip stack
+    Clip,
+    Viewport,
+    ModelMatrix,
+    /// The previous draw call had no room for more vertices or indices
+    Capacity,
+    /// Batching was broken explicitly: uniforms or textures of the current material were set,
+    /// or its pipeline was replaced
+    Explicit,
+}
+
+impl BatchBreak {
+    pub const fn name(self) -> &'static str {
+        match self {
+            BatchBreak::Geometry => "geometry",
+            BatchBreak::DrawList => "draw list",
+            BatchBreak::RenderPass => "render pass",
+            BatchBreak::Layer => "layer",
+            BatchBreak::DrawMode => "draw mode",
+            BatchBreak::Pipeline => "pipeline",
+            BatchBreak::Texture => "texture",
+            BatchBreak::Clip => "clip",
+            BatchBreak::Viewport => "viewport",
+            BatchBreak::ModelMatrix => "model matrix",
+            BatchBreak::Capacity => "capacity",
+            BatchBreak::Explicit => "explicit",
+        }
+    }
+}
+
+/// Statistics collected by a [Renderer](super::Renderer) once enabled with
+/// [Renderer::with_stats](super::Renderer::with_stats), see
+/// [Renderer::take_frame_stats](super::Renderer::take_frame_stats).
+///
+/// Batch breaks are recorded when geometry is submitted, the rest once it's drawn.
+/// Draw calls of different layers that end up next to each other after sorting are merged,
+/// so there can be fewer draw calls than batch breaks
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Draw calls submitted to the backend
+    pub draw_calls: usize,
+    /// Vertices of batched geometry. Vertices of static meshes aren't counted
+    pub vertices: usize,
+    pub indices: usize,
+    /// How many times a draw call used another pipeline than the one before it,
+    /// including the first one
+    pub pipeline_switches: usize,
+    /// How many times a draw call bound other textures than the one before it,
+    /// including the first one
+    pub texture_switches: usize,
+    /// The reason of every batch break, in submission order
+    pub batch_breaks: Vec<BatchBreak>,
+}
+
+impl FrameStats {
+    /// The amount of batch breaks per reason
+    pub fn batch_breaks_by_reason(&self) -> BTreeMap<BatchBreak, usize> {
+        let mut breaks = BTreeMap::new();
+        for reason in &self.batch_breaks {
+            *breaks.entry(*reason).or_default() += 1;
+        }
+        breaks
+    }
+}
+
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "draw calls: {}, vertices: {}, indices: {}, pipeline switches: {}, \
+             texture switches: {}, batch breaks: {}",
+            self.draw_calls,
+            self.vertices,
+            self.indices,
+            self.pipeline_switches,
+            self.texture_switches,
+            self.batch_breaks.len()
+        )?;
+
+        let breaks = self.batch_breaks_by_reason();
+        for (ix, (reason, count)) in breaks.iter().enumerate() {
+            let separator = if ix == 0 { " (" } else { ", " };
+            write!(f, "{}{} {}", separator, reason.name(), count)?;
+        }
+        if !breaks.is_empty() {
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
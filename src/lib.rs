@@ -2,6 +2,9 @@
 //!
 //! A crate designed to expose macroquad abstractions in a modular way
 
+// Derive macros refer to this crate by its name
+extern crate self as macroquad_abstractions;
+
 mod tobytes;
 
 #[cfg(feature = "quad-snd")]
@@ -25,7 +28,7 @@ pub use glam;
 
 mod error;
 
-pub use error::Error;
+pub use error::{Error, UniformError};
 
 /// Cross platform random generator.
 pub mod rand {
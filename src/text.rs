@@ -2,7 +2,7 @@
 
 use std::collections::HashMap;
 
-use crate::{texture::{Image, Texture}, Error};
+use crate::{texture::{Image, Texture}, utils::Rect, Error};
 
 use glam::{vec3, Mat4};
 use miniquad::{FilterMode, RenderingBackend, TextureId};
@@ -17,6 +17,15 @@ pub(crate) struct CharacterInfo {
     pub sprite: SpriteKey,
 }
 
+/// A cached glyph: its metrics, and where it is in the font atlas
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Glyph {
+    pub offset_x: i32,
+    pub offset_y: i32,
+    pub advance: f32,
+    pub rect: Rect,
+}
+
 /// A FontAtlas is a loaded font, a GPU texture storing all characters and a character map to access
 /// said characters.
 ///
@@ -126,6 +135,17 @@ impl FontAtlas {
         self.characters.insert((character, size), character_info);
     }
 
+    /// The glyph of a cached character
+    pub(crate) fn glyph(&self, character: char, size: u16) -> Option<Glyph> {
+        let info = self.characters.get(&(character, size))?;
+        Some(Glyph {
+            offset_x: info.offset_x,
+            offset_y: info.offset_y,
+            advance: info.advance,
+            rect: self.atlas.get(info.sprite)?.rect,
+        })
+    }
+
     pub(crate) fn get(&self, character: char, size: u16) -> Option<CharacterInfo> {
         self.characters.get(&(character, size)).cloned()
     }
@@ -212,6 +232,26 @@ impl FontAtlas {
         self.atlas.set_filter(backend, filter_mode);
     }
 
+    /// Take a read-only copy of the glyph cache, to draw text on other threads.
+    ///
+    /// The atlas texture is updated first, since the snapshot can't do it on its own.
+    /// See [FontSnapshot]
+    pub fn snapshot(&mut self, backend: &mut dyn RenderingBackend) -> FontSnapshot {
+        let glyphs = self
+            .characters
+            .keys()
+            .filter_map(|&(character, size)| {
+                Some(((character, size), self.glyph(character, size)?))
+            })
+            .collect();
+
+        FontSnapshot {
+            texture: self.atlas.texture(backend).clone(),
+            dpi_scale: miniquad::window::dpi_scale(),
+            glyphs,
+        }
+    }
+
     /// Get a reference to the inner atlas. 
     /// 
     /// This can be used to clean the GPU texture when it's no longer used
@@ -220,6 +260,43 @@ impl FontAtlas {
     }
 }
 
+/// A read-only copy of the glyph cache of a [FontAtlas], taken with [FontAtlas::snapshot].
+///
+/// It can be shared between threads to draw text into
+/// [CommandBuffer](crate::graphics::CommandBuffer)s with
+/// [draw_text_cached](crate::draw::draw_text_cached), which can't rasterize new glyphs.
+/// Characters that weren't cached when the snapshot was taken are skipped, so populate the cache
+/// with every character and size you need first. Like when drawing text, font sizes are scaled by
+/// the dpi scale, which is the one from when the snapshot was taken.
+///
+/// A snapshot shares the atlas texture, and doesn't see glyphs cached after it was taken:
+/// once the atlas grows, its texture is recreated, so take a new snapshot then.
+#[derive(Debug, Clone)]
+pub struct FontSnapshot {
+    texture: Texture,
+    dpi_scale: f32,
+    glyphs: HashMap<(char, u16), Glyph>,
+}
+
+impl FontSnapshot {
+    pub(crate) fn glyph(&self, character: char, size: u16) -> Option<Glyph> {
+        self.glyphs.get(&(character, size)).copied()
+    }
+
+    pub const fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub const fn dpi_scale(&self) -> f32 {
+        self.dpi_scale
+    }
+
+    /// Whether a character of this size (already scaled by the dpi scale) is in the snapshot
+    pub fn contains(&self, character: char, size: u16) -> bool {
+        self.glyphs.contains_key(&(character, size))
+    }
+}
+
 /// Load font from file with "path"
 pub fn load_ttf_font(
     backend: &mut dyn RenderingBackend,
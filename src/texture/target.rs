@@ -1,4 +1,34 @@
-use miniquad::{RenderingBackend, TextureId};
+use miniquad::{Backend, PassAction, RenderingBackend, TextureFormat, TextureId};
+
+use crate::logging::warn;
+
+/// How render targets get the few features miniquad's API doesn't cover with OpenGL: stencil
+/// buffers and [depth readback](read_depth_texture). It only matters with backends that report
+/// [Backend::OpenGl], which can't be told apart from each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GlAccess {
+    /// No raw GL calls are made, so these features aren't available with OpenGL
+    #[default]
+    Disabled,
+    /// The backend owns a current GL context (miniquad's own OpenGL backend, or a wrapper around
+    /// it), so these features are implemented with raw GL calls
+    Raw,
+    /// The backend isn't backed by GL, and supports these features through miniquad's API, like
+    /// the `SoftwareBackend` used in tests
+    Emulated,
+}
+
+impl GlAccess {
+    /// Whether raw GL calls are made next to this backend
+    fn raw(self, backend: &dyn RenderingBackend) -> bool {
+        self == GlAccess::Raw && backend.info().backend == Backend::OpenGl
+    }
+
+    /// Whether a feature miniquad can't provide with OpenGL is missing from this backend
+    fn unavailable(self, backend: &dyn RenderingBackend) -> bool {
+        self == GlAccess::Disabled && backend.info().backend == Backend::OpenGl
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct RenderPass {
@@ -16,6 +46,24 @@ pub struct RenderTargetParams {
     /// depth: true creates a depth render target attachment and allows
     /// such a render target being used for a depth-testing cameras
     pub depth: bool,
+
+    /// stencil: true creates a stencil buffer, which is required for stencil masking
+    /// (see [StencilMode](crate::graphics::StencilMode)).
+    /// A stencil buffer always comes together with a depth buffer. With OpenGL, it's attached with
+    /// raw GL calls, so it needs [GlAccess::Raw]. Its depth buffer isn't a texture, so the render
+    /// target has no [RenderTarget::depth_texture] even if `depth` is requested too.
+    /// With [GlAccess::Disabled], OpenGL render targets get no stencil buffer, and a warning is
+    /// logged
+    pub stencil: bool,
+
+    /// How features miniquad doesn't cover with OpenGL are provided, see [GlAccess]
+    pub gl_access: GlAccess,
+
+    /// Formats of the color attachments, one texture is created for every format.
+    /// With more than one, a material can write to all of them in a single pass
+    /// (`gl_FragData[i]` in GLSL, `[[color(i)]]` in Metal), which is useful for G-buffers.
+    /// Note that multiple color attachments are not supported on GL2, GLES2 and WebGL1
+    pub color_formats: Vec<TextureFormat>,
 }
 
 impl Default for RenderTargetParams {
@@ -23,14 +71,52 @@ impl Default for RenderTargetParams {
         RenderTargetParams {
             sample_count: 1,
             depth: false,
+            stencil: false,
+            gl_access: GlAccess::Disabled,
+            color_formats: vec![TextureFormat::RGBA8],
         }
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct RenderTarget {
+    /// The first color texture
     pub texture: TextureId,
+    /// All color textures, in the order of [RenderTargetParams::color_formats]
+    pub color_textures: Vec<TextureId>,
+    /// The depth texture, if the render target was created with one.
+    ///
+    /// Once rendering into this target is done, it can be bound to [Material](crate::graphics::Material)
+    /// textures like any other texture. Multisampled depth textures can't be sampled.
+    /// With OpenGL, render targets with a stencil buffer have no depth texture
+    pub depth_texture: Option<TextureId>,
     pub render_pass: RenderPass,
+    /// See [RenderTargetParams::gl_access]
+    pub gl_access: GlAccess,
+    /// The OpenGL depth-stencil renderbuffer, see [RenderTargetParams::stencil]
+    gl_depth_stencil: Option<u32>,
+}
+
+impl RenderTarget {
+    /// Read the depth texture back into CPU memory, see [read_depth_texture].
+    /// Returns [None] if this render target has no depth texture, or if the backend can't read it
+    pub fn read_depth(&self, backend: &mut dyn RenderingBackend) -> Option<Vec<f32>> {
+        self.depth_texture
+            .and_then(|texture| read_depth_texture(backend, texture, self.gl_access))
+    }
+
+    /// Delete the render pass with all of its attachments.
+    ///
+    /// Prefer this to deleting only [RenderPass::render_pass], which leaks the stencil buffer of
+    /// OpenGL render targets
+    pub fn delete(self, backend: &mut dyn RenderingBackend) {
+        backend.delete_render_pass(self.render_pass.render_pass);
+
+        if let Some(renderbuffer) = self.gl_depth_stencil {
+            // SAFETY: the renderbuffer was only created with GlAccess::Raw
+            unsafe { miniquad::gl::glDeleteRenderbuffers(1, &renderbuffer) };
+        }
+    }
 }
 
 /// A shortcut to create a render target with sample_count: 1 and no depth buffer
@@ -65,18 +151,43 @@ pub fn new_render_target_ex(
     height: u32,
     params: RenderTargetParams,
 ) -> RenderTarget {
-    let color_texture = backend.new_render_texture(miniquad::TextureParams {
-        width,
-        height,
-        sample_count: params.sample_count,
-        ..Default::default()
-    });
+    assert!(
+        !params.color_formats.is_empty(),
+        "A render target needs at least one color attachment"
+    );
+
+    let new_color_textures = |backend: &mut dyn RenderingBackend, sample_count: i32| {
+        params
+            .color_formats
+            .iter()
+            .map(|&format| {
+                backend.new_render_texture(miniquad::TextureParams {
+                    width,
+                    height,
+                    format,
+                    sample_count,
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let color_textures = new_color_textures(backend, params.sample_count);
+
+    // miniquad has no depth-stencil texture format, so with OpenGL the stencil buffer is attached
+    // separately. Depth textures of the other backends already have a stencil plane
+    let gl_stencil = params.stencil && params.gl_access.raw(backend);
+    let stencil = if params.stencil && params.gl_access.unavailable(backend) {
+        warn!("Stencil buffers need GlAccess::Raw with OpenGL, the render target has none");
+        false
+    } else {
+        params.stencil
+    };
 
-    let depth_texture = if params.depth {
+    let depth_texture = if (params.depth || stencil) && !gl_stencil {
         Some(backend.new_render_texture(miniquad::TextureParams {
             width,
             height,
-            format: miniquad::TextureFormat::Depth,
+            format: TextureFormat::Depth,
             sample_count: params.sample_count,
             ..Default::default()
         }))
@@ -85,32 +196,186 @@ pub fn new_render_target_ex(
     };
 
     let render_pass;
-    let texture;
-    if params.sample_count != 0 {
-        let color_resolve_texture = backend.new_render_texture(miniquad::TextureParams {
-            width,
-            height,
-            ..Default::default()
-        });
-        render_pass = backend.new_render_pass_mrt(
-            &[color_texture],
-            Some(&[color_resolve_texture]),
-            depth_texture,
-        );
-        texture = color_resolve_texture;
+    let textures;
+    if params.sample_count > 1 {
+        let resolve_textures = new_color_textures(backend, 1);
+        render_pass =
+            backend.new_render_pass_mrt(&color_textures, Some(&resolve_textures), depth_texture);
+        textures = resolve_textures;
     } else {
-        render_pass = backend.new_render_pass_mrt(&[color_texture], None, depth_texture);
-        texture = color_texture;
+        render_pass = backend.new_render_pass_mrt(&color_textures, None, depth_texture);
+        textures = color_textures;
     }
 
+    let gl_depth_stencil = gl_stencil
+        .then(|| attach_gl_depth_stencil(backend, render_pass, width, height, params.sample_count));
+
     let render_pass = RenderPass {
-        color_texture: texture,
-        depth_texture: None,
+        color_texture: textures[0],
+        depth_texture,
         render_pass,
     };
 
     RenderTarget {
-        texture,
+        texture: textures[0],
+        color_textures: textures,
+        depth_texture,
         render_pass,
+        gl_access: params.gl_access,
+        gl_depth_stencil,
+    }
+}
+
+/// Attach a packed depth-stencil renderbuffer to the framebuffer of an OpenGL render pass,
+/// and return the renderbuffer
+fn attach_gl_depth_stencil(
+    backend: &mut dyn RenderingBackend,
+    render_pass: miniquad::RenderPass,
+    width: u32,
+    height: u32,
+    sample_count: i32,
+) -> u32 {
+    use miniquad::gl::*;
+
+    // GL_DEPTH24_STENCIL8
+    #[cfg(not(target_arch = "wasm32"))]
+    const DEPTH_STENCIL_FORMAT: GLenum = 0x88F0;
+    // GL_DEPTH_STENCIL, WebGL 1 doesn't support sized depth-stencil formats
+    #[cfg(target_arch = "wasm32")]
+    const DEPTH_STENCIL_FORMAT: GLenum = 0x84F9;
+    const GL_DEPTH_STENCIL_ATTACHMENT: GLenum = 0x821A;
+
+    // miniquad doesn't expose framebuffers, but beginning a pass binds its framebuffer
+    backend.begin_pass(Some(render_pass), PassAction::Nothing);
+    let mut renderbuffer = 0;
+    unsafe {
+        glGenRenderbuffers(1, &mut renderbuffer);
+        glBindRenderbuffer(GL_RENDERBUFFER, renderbuffer);
+        if sample_count > 1 {
+            glRenderbufferStorageMultisample(
+                GL_RENDERBUFFER,
+                sample_count,
+                DEPTH_STENCIL_FORMAT,
+                width as i32,
+                height as i32,
+            );
+        } else {
+            glRenderbufferStorage(
+                GL_RENDERBUFFER,
+                DEPTH_STENCIL_FORMAT,
+                width as i32,
+                height as i32,
+            );
+        }
+        glFramebufferRenderbuffer(
+            GL_FRAMEBUFFER,
+            GL_DEPTH_STENCIL_ATTACHMENT,
+            GL_RENDERBUFFER,
+            renderbuffer,
+        );
+        glBindRenderbuffer(GL_RENDERBUFFER, 0);
+    }
+    backend.end_render_pass();
+    renderbuffer
+}
+
+/// Read a depth texture back into CPU memory, as depth values from 0 to 1.
+///
+/// Values are stored row by row, starting from the bottom row, the same way as with
+/// [Image::from_texture](super::Image::from_texture). This is an expensive operation,
+/// so avoid doing it often.
+///
+/// Returns [None] if the texture isn't a depth texture, or where reading depth isn't supported:
+/// on Metal, GLES and WebGL, and on OpenGL with [GlAccess::Disabled].
+/// With [GlAccess::Raw], depth is read with raw GL calls. With [GlAccess::Emulated], it's read
+/// with [texture_read_pixels](RenderingBackend::texture_read_pixels), which miniquad's own
+/// OpenGL backend doesn't support for depth textures.
+pub fn read_depth_texture(
+    backend: &mut dyn RenderingBackend,
+    texture: TextureId,
+    gl_access: GlAccess,
+) -> Option<Vec<f32>> {
+    let params = backend.texture_params(texture);
+    if !matches!(params.format, TextureFormat::Depth | TextureFormat::Depth32) {
+        return None;
+    }
+
+    // miniquad only creates GLES contexts on these platforms, which can't read depth back
+    let gles = cfg!(any(target_arch = "wasm32", target_os = "android", target_os = "ios"));
+    let raw = gl_access.raw(backend);
+    if backend.info().backend == Backend::Metal
+        || gl_access.unavailable(backend)
+        || (raw && gles)
+    {
+        return None;
+    }
+
+    let mut bytes = vec![0; params.format.size(params.width, params.height) as usize];
+    if raw {
+        read_gl_depth(backend, texture, &mut bytes);
+    } else {
+        backend.texture_read_pixels(texture, &mut bytes);
+    }
+
+    let depth = match params.format {
+        TextureFormat::Depth => bytes
+            .chunks_exact(2)
+            .map(|depth| u16::from_ne_bytes([depth[0], depth[1]]) as f32 / u16::MAX as f32)
+            .collect(),
+        _ => bytes
+            .chunks_exact(4)
+            .map(|depth| f32::from_ne_bytes([depth[0], depth[1], depth[2], depth[3]]))
+            .collect(),
+    };
+    Some(depth)
+}
+
+/// miniquad reads textures back by attaching them as a color attachment, which doesn't work with
+/// depth textures, so they're attached as a depth attachment instead
+fn read_gl_depth(backend: &mut dyn RenderingBackend, texture: TextureId, bytes: &mut [u8]) {
+    use miniquad::gl::*;
+
+    const GL_READ_FRAMEBUFFER_BINDING: GLenum = 0x8CAA;
+
+    let params = backend.texture_params(texture);
+    // SAFETY: the raw id is only used to attach the texture to a temporary framebuffer
+    let raw = match unsafe { backend.texture_raw_id(texture) } {
+        miniquad::RawId::OpenGl(raw) => raw,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    };
+    let pixel_type = match params.format {
+        TextureFormat::Depth => GL_UNSIGNED_SHORT,
+        _ => GL_FLOAT,
+    };
+
+    unsafe {
+        let mut bound = 0;
+        glGetIntegerv(GL_READ_FRAMEBUFFER_BINDING, &mut bound);
+
+        let mut framebuffer = 0;
+        glGenFramebuffers(1, &mut framebuffer);
+        glBindFramebuffer(GL_READ_FRAMEBUFFER, framebuffer);
+        glFramebufferTexture2D(
+            GL_READ_FRAMEBUFFER,
+            GL_DEPTH_ATTACHMENT,
+            GL_TEXTURE_2D,
+            raw,
+            0,
+        );
+        glReadBuffer(GL_NONE);
+
+        glReadPixels(
+            0,
+            0,
+            params.width as i32,
+            params.height as i32,
+            GL_DEPTH_COMPONENT,
+            pixel_type,
+            bytes.as_mut_ptr() as _,
+        );
+
+        glBindFramebuffer(GL_READ_FRAMEBUFFER, bound as GLuint);
+        glDeleteFramebuffers(1, &framebuffer);
     }
 }
@@ -36,7 +36,7 @@ impl<T: ToBytes> ToBytes for &[T] {
         unsafe {
             std::slice::from_raw_parts(
                 self.as_ptr() as *const _ as *const u8,
-                std::mem::size_of_val(self), // std::mem::size_of::<T>() * self.len(),
+                std::mem::size_of_val(*self), // std::mem::size_of::<T>() * self.len(),
             )
         }
     }